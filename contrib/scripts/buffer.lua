@@ -1,11 +1,15 @@
+function argument_spec()
+  return "[index:int]"
+end
+
 function run()
   windex = focused_window_index()
   if #arguments > 0 then
     index = tonumber(arguments[1])
-    if index > webview_count(windex) then
+    if index < 0 or index >= webview_count(windex) then
       return false
     end
-    focus_webview(windex, tonumber(arguments[1]))
+    focus_webview(windex, index)
   end
   return true
 end
@@ -17,10 +21,10 @@ function complete_command()
   local windex = focused_window_index()
   local total = webview_count(windex)
   for i = 0, total - 1 do
-    title = "" .. i .. " : " .. webview_title(windex, i):gsub(","," ")
+    title = "" .. i .. " : " .. webview_title(windex, i)
     if string.sub(title, 1, #query) == query then
-      titles[#titles + 1] = title
+      titles[#titles + 1] = {label=title, value=tostring(i)}
     end
   end
-  return table.concat(titles,",")
+  return titles
 end
@@ -8,7 +8,7 @@ function run()
   if #arguments > 0 then
     target = arguments[1]
   else
-    target = lookup_string(config_file_path, "window.start-page")
+    target = lookup_string("window.start-page")
   end
 
   if windex ~= NOT_FOUND then
@@ -9,7 +9,7 @@ function on_request_uri()
   if string.find(target, ".local") or string.find(target, "localhost") then
     return
   end
-  ignored_hosts = lookup_strings(config_file_path, "enforce-https.ignored-hosts")
+  ignored_hosts = lookup_strings("enforce-https.ignored-hosts")
   host = string.gmatch(string.match(requested_uri, "://(.*)"), "[^/]+")()
   for _, ignored_host in ipairs(ignored_hosts) do
     if ignored_host == host then
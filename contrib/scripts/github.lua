@@ -22,7 +22,8 @@ function run()
         target = string.format("https://github.com/%s/%s", owner, repo)
         load_uri(windex, webview_index, target)
       else
-        query = url_encode(table.concat(arguments, " "))
+        local util = require("webkitten.util")
+        query = util.url_encode(table.concat(arguments, " "))
         target = table.concat({"https://github.com/search?q=", query}, "")
         load_uri(windex, webview_index, target)
       end
@@ -46,13 +47,3 @@ function complete_command()
   end
   return ""
 end
-
-function url_encode(str)
-  if (str) then
-  str = string.gsub (str, "\n", "\r\n")
-  str = string.gsub (str, "([^%w %-%_%.%~])",
-    function (c) return string.format ("%%%02X", string.byte(c)) end)
-  str = string.gsub (str, " ", "+")
-  end
-  return str
-end
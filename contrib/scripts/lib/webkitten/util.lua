@@ -0,0 +1,44 @@
+-- Helpers shared by the contrib scripts, loaded using
+-- `local util = require("webkitten.util")`
+local util = {}
+
+-- Escapes text for use in the query string of a URI
+function util.url_encode(str)
+  if (str) then
+  str = string.gsub (str, "\n", "\r\n")
+  str = string.gsub (str, "([^%w %-%_%.%~])",
+    function (c) return string.format ("%%%02X", string.byte(c)) end)
+  str = string.gsub (str, " ", "+")
+  end
+  return str
+end
+
+-- Whether text ends with another string
+function util.ends_with(text, ending)
+  return ending == '' or string.sub(text, -string.len(ending)) == ending
+end
+
+-- Reads the contents of a file, or returns nil if the file cannot be read.
+-- Reading files requires the full sandbox profile.
+function util.load_file(file_path)
+  if not io then return nil end
+  local file = io.open(file_path)
+  if not file then return nil end
+  local content = file:read("*a")
+  file:close()
+  return content
+end
+
+-- Loads a URI in the focused buffer, or opens a new window if there is no
+-- focused window
+function util.open_uri(target)
+  local window, buffer = webkitten.window, webkitten.buffer
+  local windex = window.focused_index()
+  if windex ~= webkitten.NOT_FOUND then
+    buffer.load_uri(windex, buffer.focused_index(windex), target)
+  else
+    window.open(target)
+  end
+end
+
+return util
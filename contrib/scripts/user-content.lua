@@ -1,3 +1,5 @@
+local util = require("webkitten.util")
+
 function description()
   return "Loads custom CSS and JS"
 end
@@ -9,9 +11,9 @@ function run()
     log_info("loading " .. file_name)
     window_index = focused_window_index()
     webview_index = focused_webview_index(window_index)
-    if string.ends_with(file_name, ".css") then
+    if util.ends_with(file_name, ".css") then
       return load_css(file_name, window_index, webview_index)
-    elseif string.ends_with(file_name, ".js") then
+    elseif util.ends_with(file_name, ".js") then
       return load_js(file_name, window_index, webview_index)
     end
   end
@@ -20,7 +22,9 @@ end
 
 -- Loads all CSS and JS files from the configuration option
 -- `user-content.default-paths` into every page, as well as the files from
--- `user-content.site-paths` based on the domain name of the page.
+-- `user-content.site-paths` based on the domain name of the page. Reading
+-- files requires `commands.sandbox."user-content" = "full"`, which is set in
+-- the configuration file written on first launch.
 function on_load_uri()
   load_default_files()
   load_site_files()
@@ -28,7 +32,7 @@ end
 
 function load_site_files()
   host = string.gmatch(string.match(requested_uri, "://(.*)"), "[^/]+")()
-  base_paths = lookup_strings(config_file_path, "user-content.site-paths")
+  base_paths = lookup_strings("user-content.site-paths")
   for _, base_path in ipairs(base_paths) do
     load_css(string.format("%s/%s.css", base_path, host), window_index, webview_index)
     load_js(string.format("%s/%s.js", base_path, host), window_index, webview_index)
@@ -36,12 +40,12 @@ function load_site_files()
 end
 
 function load_default_files()
-  default_paths = lookup_strings(config_file_path, "user-content.default-paths")
+  default_paths = lookup_strings("user-content.default-paths")
   for _, base_path in ipairs(default_paths) do
     for _, file_name in ipairs(list_files(base_path)) do
-      if string.ends_with(file_name, ".css") then
+      if util.ends_with(file_name, ".css") then
         load_css(string.format("%s/%s", base_path, file_name), window_index, webview_index)
-      elseif string.ends_with(file_name, ".js") then
+      elseif util.ends_with(file_name, ".js") then
         load_js(string.format("%s/%s.js", base_path, file_name), window_index, webview_index)
       end
     end
@@ -49,7 +53,7 @@ function load_default_files()
 end
 
 function load_css(path, window_index, webview_index)
-  css = load_file(path)
+  css = util.load_file(path)
   if css then
     log_info(string.format("Loading CSS: %s", path))
     add_styles(window_index, webview_index, css)
@@ -59,7 +63,7 @@ function load_css(path, window_index, webview_index)
 end
 
 function load_js(path, window_index, webview_index)
-  js = load_file(path)
+  js = util.load_file(path)
   if js then
     log_info(string.format("Loading JS: %s", path))
     run_javascript(window_index, webview_index, js)
@@ -68,14 +72,6 @@ function load_js(path, window_index, webview_index)
   return false
 end
 
-function load_file(file_path)
-  local file = io.open(file_path)
-  if not file then return nil end
-  local content = file:read("*a")
-  file:close()
-  return content
-end
-
 function list_files(directory)
   local index, files = 0, {}
   local list_handle = io.popen('ls "' .. directory .. '"')
@@ -86,7 +82,3 @@ function list_files(directory)
   list_handle:close()
   return files
 end
-
-function string.ends_with(text, ending)
-  return ending == '' or string.sub(text, -string.len(ending)) == ending
-end
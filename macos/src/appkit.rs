@@ -133,6 +133,25 @@ impl NSApplication {
         NSArray::from_ptr(unsafe { msg_send![self.ptr, orderedWindows] }).unwrap()
     }
 
+    /// Remove the next key down event from the event queue without waiting
+    pub fn next_key_down_event(&self) -> Option<NSEvent> {
+        NSEvent::from_ptr(unsafe {
+            let distant_past: Id = msg_send![class!(NSDate), distantPast];
+            let mode = NSString::from("kCFRunLoopDefaultMode");
+            msg_send![self.ptr, nextEventMatchingMask:NS_KEY_DOWN_MASK
+                                            untilDate:distant_past
+                                               inMode:mode.ptr()
+                                              dequeue:YES]
+        })
+    }
+
+    /// Add an event to the event queue, before any other events if
+    /// `at_start` is set
+    pub fn post_event(&self, event: &NSEvent, at_start: bool) {
+        let value = if at_start { YES } else { NO };
+        unsafe { msg_send![self.ptr, postEvent:event.ptr() atStart:value] }
+    }
+
     pub fn window_by_number(&self, window_number: NSInteger) -> Option<NSWindow> {
         NSWindow::from_ptr(unsafe {
             msg_send![self.ptr, windowWithWindowNumber: window_number]
@@ -173,11 +192,30 @@ impl NSControl {
     }
 }
 
+/// Mask matching key down events in the event queue
+const NS_KEY_DOWN_MASK: NSUInteger = 1 << 10;
+
+/// Mask of the modifier flags which do not depend on the keyboard device
+const NS_DEVICE_INDEPENDENT_MODIFIER_FLAGS_MASK: NSUInteger = 0xffff0000;
+
 impl NSEvent {
 
     pub fn modifier_flags(&self) -> NSUInteger {
         unsafe { msg_send![self.ptr, modifierFlags] }
     }
+
+    pub fn characters_ignoring_modifiers(&self) -> Option<NSString> {
+        NSString::from_ptr(unsafe { msg_send![self.ptr, charactersIgnoringModifiers] })
+    }
+
+    /// Whether the event is a key press of a character with exactly the
+    /// modifiers in a mask
+    pub fn matches_key(&self, key: char, modifier: NSUInteger) -> bool {
+        let flags = self.modifier_flags() & NS_DEVICE_INDEPENDENT_MODIFIER_FLAGS_MASK;
+        let characters = self.characters_ignoring_modifiers();
+        let pressed = characters.as_ref().and_then(|c| c.as_str()).and_then(|c| c.chars().next());
+        flags == modifier && pressed.map(|c| c.to_lowercase().eq(key.to_lowercase())).unwrap_or(false)
+    }
 }
 
 impl NSLayoutConstraint {
@@ -314,6 +352,12 @@ impl NSTextField {
     pub fn set_delegate<T: ObjCClass>(&self, delegate: &T) {
         unsafe { msg_send![self.ptr, setDelegate:delegate.ptr()] }
     }
+
+    pub fn set_placeholder(&self, text: &str) {
+        unsafe {
+            msg_send![self.ptr, setPlaceholderString:NSString::from(text).ptr()]
+        }
+    }
 }
 
 impl NSResponder {
@@ -415,6 +459,10 @@ impl NSWindow {
         self.ptr = nil;
     }
 
+    pub fn set_delegate<T: ObjCClass>(&self, delegate: &T) {
+        unsafe { msg_send![self.ptr, setDelegate:delegate.ptr()] }
+    }
+
     pub fn release_delegate(&mut self) {
         unsafe {
             let delegate: Id = msg_send![self.ptr, delegate];
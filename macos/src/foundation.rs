@@ -226,6 +226,10 @@ impl NSError {
         NSString::from_ptr(unsafe { msg_send![self.ptr, localizedFailureReason] })
     }
 
+    pub fn user_info(&self) -> Option<NSDictionary> {
+        NSDictionary::from_ptr(unsafe { msg_send![self.ptr, userInfo] })
+    }
+
 }
 
 impl NSNotification {
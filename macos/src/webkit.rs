@@ -13,6 +13,8 @@ extern {}
 
 pub type ContentExtensionCompletionHandler = dyn Deref<Target=Block<(Id, Id), ()>>;
 
+pub type JavaScriptCompletionHandler = dyn Deref<Target=Block<(Id, Id), ()>>;
+
 pub enum WKFindOptions {
     CaseInsensitive = 1 << 0,
     AtWordStarts = 1 << 1,
@@ -237,6 +239,35 @@ impl WKWebView {
         }
     }
 
+    pub fn evaluate_javascript_with_completion(&self, script: &str,
+                                              block: &JavaScriptCompletionHandler) {
+        unsafe {
+            msg_send![self.ptr, evaluateJavaScript:NSString::from(script)
+                                 completionHandler:block.deref()]
+        }
+    }
+
+    /// Evaluate a script in the client content world, where scripts in the
+    /// page cannot replace the globals it uses
+    pub fn evaluate_javascript_in_client_world(&self, script: &str,
+                                               block: &JavaScriptCompletionHandler) {
+        unsafe {
+            let world: Id = msg_send![class!(WKContentWorld), defaultClientWorld];
+            msg_send![self.ptr, evaluateJavaScript:NSString::from(script)
+                                           inFrame:nil
+                                    inContentWorld:world
+                                 completionHandler:block.deref()]
+        }
+    }
+
+    pub fn can_evaluate_javascript_in_client_world(&self) -> bool {
+        let responds: BOOL = unsafe {
+            let selector = sel!(evaluateJavaScript:inFrame:inContentWorld:completionHandler:);
+            msg_send![self.ptr, respondsToSelector:selector]
+        };
+        responds == YES
+    }
+
     pub fn find_string(&self, query: &str) {
         let options: NSUInteger = WKFindOptions::CaseInsensitive as NSUInteger |
                                   WKFindOptions::WrapAround as NSUInteger |
@@ -320,6 +351,11 @@ impl WKWebsiteDataStore {
             }
         }
     }
+
+    pub fn is_persistent(&self) -> bool {
+        let persistent: BOOL = unsafe { msg_send![self.ptr, isPersistent] };
+        persistent == YES
+    }
 }
 
 impl _WKUserContentExtensionStore {
@@ -0,0 +1,313 @@
+//! Argument specs declared by commands, used to validate arguments before a
+//! command runs and to complete arguments with a fixed set of values
+use std::error;
+use std::fmt;
+
+use url::Url;
+
+/// The kind of value accepted by a parameter
+#[derive(Debug,Clone,PartialEq)]
+pub enum ArgumentType {
+    /// Any text, declared as `string` or without a type
+    Text,
+    /// A whole number, declared as `int`
+    Integer,
+    /// A URL or host name, declared as `uri`
+    Uri,
+    /// One of a set of values, declared as values separated by `|`
+    Enum(Vec<String>),
+}
+
+/// A named argument accepted by a command
+#[derive(Debug,Clone,PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub kind: ArgumentType,
+    /// Whether the argument may be omitted, declared by surrounding the
+    /// parameter with `[]`
+    pub optional: bool,
+    /// Whether the parameter accepts all remaining arguments, declared by a
+    /// trailing `...`
+    pub variadic: bool,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ArgumentErrorReason {
+    /// The spec itself could not be parsed
+    InvalidSpec,
+    /// A required argument was not provided
+    MissingArgument,
+    /// More arguments were provided than the spec accepts
+    UnexpectedArgument,
+    /// An argument does not match the type of its parameter
+    InvalidValue,
+}
+
+#[derive(Debug,PartialEq)]
+pub struct ArgumentError {
+    message: String,
+    reason: ArgumentErrorReason,
+}
+
+impl error::Error for ArgumentError {
+
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ArgumentError {
+
+    fn new<T: Into<String>>(reason: ArgumentErrorReason, message: T) -> ArgumentError {
+        ArgumentError { message: message.into(), reason }
+    }
+
+    /// The cause of the failure to parse a spec or validate arguments
+    pub fn reason(&self) -> ArgumentErrorReason {
+        self.reason
+    }
+}
+
+impl fmt::Display for ArgumentError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The arguments accepted by a command, declared as parameters separated by
+/// whitespace. Each parameter is a name optionally followed by `:` and a type,
+/// which is `string`, `int`, `uri`, or a set of values separated by `|`.
+/// Optional parameters are surrounded by `[]` and must follow any required
+/// parameters, and the last parameter may end with `...` to accept all
+/// remaining arguments.
+///
+/// ## Examples
+///
+/// ```
+/// use webkitten::argspec::{ArgumentSpec,ArgumentErrorReason};
+///
+/// let spec = ArgumentSpec::parse("index:int [mode:on|off] [tags...]").unwrap();
+/// assert!(spec.validate(&[String::from("2"), String::from("on")]).is_ok());
+/// let err = spec.validate(&[String::from("two")]).unwrap_err();
+/// assert_eq!(ArgumentErrorReason::InvalidValue, err.reason());
+/// assert_eq!("buffer index:int [mode:on|off] [tags...]", &spec.usage("buffer"));
+/// ```
+#[derive(Debug,Clone,PartialEq)]
+pub struct ArgumentSpec {
+    parameters: Vec<Parameter>,
+}
+
+impl ArgumentSpec {
+
+    /// Parse a spec declared by a command
+    pub fn parse(spec: &str) -> Result<ArgumentSpec, ArgumentError> {
+        let mut parameters: Vec<Parameter> = vec![];
+        for word in spec.split_whitespace() {
+            if parameters.last().map(|parameter| parameter.variadic).unwrap_or(false) {
+                return Err(invalid_spec(spec, "only the last parameter may accept several arguments"));
+            }
+            let parameter = parse_parameter(word).ok_or_else(|| invalid_spec(spec, &format!("'{}' is not a parameter", word)))?;
+            if !parameter.optional && parameters.iter().any(|parameter| parameter.optional) {
+                return Err(invalid_spec(spec, "required parameters must precede optional parameters"));
+            }
+            parameters.push(parameter);
+        }
+        Ok(ArgumentSpec { parameters })
+    }
+
+    /// The declared parameters, in order
+    pub fn parameters(&self) -> &[Parameter] {
+        &self.parameters
+    }
+
+    /// The parameter accepting the argument at an index, if any
+    pub fn parameter_at(&self, index: usize) -> Option<&Parameter> {
+        self.parameters.get(index).or_else(|| {
+            self.parameters.last().filter(|parameter| parameter.variadic)
+        })
+    }
+
+    /// Check that arguments satisfy the spec
+    pub fn validate(&self, arguments: &[String]) -> Result<(), ArgumentError> {
+        if let Some(parameter) = self.parameters.iter().skip(arguments.len()).find(|p| !p.optional) {
+            return Err(ArgumentError::new(ArgumentErrorReason::MissingArgument,
+                                          format!("Missing argument '{}'", parameter.name)));
+        }
+        for (index, argument) in arguments.iter().enumerate() {
+            let parameter = match self.parameter_at(index) {
+                Some(parameter) => parameter,
+                None => return Err(ArgumentError::new(ArgumentErrorReason::UnexpectedArgument,
+                                                      format!("Unexpected argument '{}'", argument))),
+            };
+            if !parameter.kind.accepts(argument) {
+                return Err(ArgumentError::new(ArgumentErrorReason::InvalidValue,
+                                              format!("'{}' must be {}, not '{}'", parameter.name,
+                                                      parameter.kind.describe(), argument)));
+            }
+        }
+        Ok(())
+    }
+
+    /// A usage line for a command accepting arguments matching the spec
+    pub fn usage(&self, command_name: &str) -> String {
+        let mut usage = String::from(command_name);
+        for parameter in &self.parameters {
+            usage.push(' ');
+            usage.push_str(&parameter.to_string());
+        }
+        usage
+    }
+
+    /// Values of the parameter accepting the final argument which begin with
+    /// that argument, if the parameter has a set of values
+    pub fn completions(&self, arguments: &[String]) -> Vec<String> {
+        let index = arguments.len().saturating_sub(1);
+        let partial = arguments.last().map(|arg| arg.as_str()).unwrap_or("");
+        match self.parameter_at(index).map(|parameter| &parameter.kind) {
+            Some(ArgumentType::Enum(values)) => values.iter()
+                .filter(|value| value.starts_with(partial))
+                .cloned()
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl ArgumentType {
+
+    fn accepts(&self, argument: &str) -> bool {
+        match *self {
+            ArgumentType::Text => true,
+            ArgumentType::Integer => argument.parse::<i64>().is_ok(),
+            ArgumentType::Uri => !argument.contains(char::is_whitespace)
+                && (Url::parse(argument).is_ok()
+                    || Url::parse(&format!("http://{}", argument)).is_ok()),
+            ArgumentType::Enum(ref values) => values.iter().any(|value| value == argument),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            ArgumentType::Text => String::from("text"),
+            ArgumentType::Integer => String::from("a whole number"),
+            ArgumentType::Uri => String::from("a URL"),
+            ArgumentType::Enum(ref values) => format!("one of {}", values.join(", ")),
+        }
+    }
+}
+
+impl fmt::Display for Parameter {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            ArgumentType::Text => String::new(),
+            ArgumentType::Integer => String::from(":int"),
+            ArgumentType::Uri => String::from(":uri"),
+            ArgumentType::Enum(ref values) => format!(":{}", values.join("|")),
+        };
+        let variadic = if self.variadic { "..." } else { "" };
+        if self.optional {
+            write!(f, "[{}{}{}]", self.name, kind, variadic)
+        } else {
+            write!(f, "{}{}{}", self.name, kind, variadic)
+        }
+    }
+}
+
+fn parse_parameter(word: &str) -> Option<Parameter> {
+    let optional = word.starts_with('[') && word.ends_with(']');
+    let word = if optional { &word[1..word.len() - 1] } else { word };
+    let variadic = word.ends_with("...");
+    let word = if variadic { &word[..word.len() - 3] } else { word };
+    let mut parts = word.splitn(2, ':');
+    let name = parts.next().filter(|name| is_name(name))?;
+    let kind = match parts.next() {
+        None | Some("string") => ArgumentType::Text,
+        Some("int") => ArgumentType::Integer,
+        Some("uri") => ArgumentType::Uri,
+        Some(values) => {
+            let values: Vec<String> = values.split('|').map(String::from).collect();
+            if values.iter().any(|value| !is_name(value)) {
+                return None;
+            }
+            ArgumentType::Enum(values)
+        },
+    };
+    Some(Parameter { name: String::from(name), kind, optional, variadic })
+}
+
+fn is_name(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+fn invalid_spec(spec: &str, description: &str) -> ArgumentError {
+    ArgumentError::new(ArgumentErrorReason::InvalidSpec,
+                       format!("Invalid argument spec '{}': {}", spec, description))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| String::from(*item)).collect()
+    }
+
+    #[test]
+    fn parse_parameters() {
+        let spec = ArgumentSpec::parse("index:int [target:uri] [rest...]").unwrap();
+        assert_eq!(&[
+            Parameter { name: String::from("index"), kind: ArgumentType::Integer, optional: false, variadic: false },
+            Parameter { name: String::from("target"), kind: ArgumentType::Uri, optional: true, variadic: false },
+            Parameter { name: String::from("rest"), kind: ArgumentType::Text, optional: true, variadic: true },
+        ], spec.parameters());
+        assert!(ArgumentSpec::parse("").unwrap().parameters().is_empty());
+    }
+
+    #[test]
+    fn parse_invalid_specs() {
+        for spec in &["[a] b", "a... b", "a:", "a:on|", "[]", "a:int:int"] {
+            let err = ArgumentSpec::parse(spec).unwrap_err();
+            assert_eq!(ArgumentErrorReason::InvalidSpec, err.reason());
+        }
+    }
+
+    #[test]
+    fn validate_counts() {
+        let spec = ArgumentSpec::parse("name [other]").unwrap();
+        assert_eq!(ArgumentErrorReason::MissingArgument,
+                   spec.validate(&[]).unwrap_err().reason());
+        assert!(spec.validate(&arguments(&["a"])).is_ok());
+        assert!(spec.validate(&arguments(&["a", "b"])).is_ok());
+        let err = spec.validate(&arguments(&["a", "b", "c"])).unwrap_err();
+        assert_eq!(ArgumentErrorReason::UnexpectedArgument, err.reason());
+        assert_eq!("Unexpected argument 'c'", &format!("{}", err));
+    }
+
+    #[test]
+    fn validate_variadic() {
+        let spec = ArgumentSpec::parse("indices:int...").unwrap();
+        assert!(spec.validate(&arguments(&["1", "2", "3"])).is_ok());
+        assert!(spec.validate(&arguments(&["1", "x"])).is_err());
+        assert!(spec.validate(&[]).is_err());
+        assert!(ArgumentSpec::parse("[tags...]").unwrap().validate(&[]).is_ok());
+    }
+
+    #[test]
+    fn validate_types() {
+        let spec = ArgumentSpec::parse("count:int target:uri mode:on|off").unwrap();
+        assert!(spec.validate(&arguments(&["-2", "example.com", "on"])).is_ok());
+        assert!(spec.validate(&arguments(&["2", "https://example.com/a b", "on"])).is_err());
+        let err = spec.validate(&arguments(&["2", "example.com", "maybe"])).unwrap_err();
+        assert_eq!("'mode' must be one of on, off, not 'maybe'", &format!("{}", err));
+    }
+
+    #[test]
+    fn complete_enum_values() {
+        let spec = ArgumentSpec::parse("action:save|search|open [query...]").unwrap();
+        assert_eq!(arguments(&["save", "search"]), spec.completions(&arguments(&["s"])));
+        assert_eq!(arguments(&["save", "search", "open"]), spec.completions(&[]));
+        assert!(spec.completions(&arguments(&["save", "x"])).is_empty());
+    }
+}
@@ -0,0 +1,504 @@
+//! Commands implemented natively, available without any command files in the
+//! search paths. A command file with the same name as a built-in command takes
+//! precedence over it.
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+use config::Config;
+use help::{self,CommandHelp};
+use Engine;
+use script::ScriptingEngine;
+use ui::{ApplicationUI,BrowserConfiguration,CommandError,CommandOutput,Completion,WindowArea};
+
+/// A command implemented in Rust rather than in a script
+pub trait NativeCommand {
+
+    /// The name used to invoke the command
+    fn name(&self) -> &str;
+
+    /// A summary of the command's purpose
+    fn describe(&self) -> &str;
+
+    /// Run the command with the arguments following the command name
+    fn run(&self, context: &dyn NativeContext, arguments: &[String]) -> CommandOutput;
+
+    /// Completions for the final argument in the command text
+    fn complete(&self, _context: &dyn NativeContext, _arguments: &[String]) -> Vec<Completion> {
+        vec![]
+    }
+}
+
+/// The browser and engine operations available to native commands. Unlike
+/// `ApplicationUI`, this trait has no generic methods, so commands can be
+/// stored as trait objects regardless of the UI and scripting engine in use.
+pub trait NativeContext {
+
+    /// The engine running the command
+    fn engine(&self) -> &Engine;
+
+    /// Copy text to the system clipboard
+    fn copy(&self, text: &str);
+
+    /// The index of the window the command was run from, or the focused
+    /// window if it was not run from a window
+    fn window_index(&self) -> Option<u32>;
+
+    /// Open a new window, loading a URI if any
+    fn open_window(&self, uri: Option<String>);
+
+    /// Close a window
+    fn close_window(&self, window_index: u32);
+
+    /// Capture keyboard input in given area
+    fn focus_window_area(&self, window_index: u32, area: WindowArea);
+
+    /// `true` if the command bar of a specified window is visible
+    fn command_field_visible(&self, window_index: u32) -> bool;
+
+    /// Set the visibility in the command bar of a specified window
+    fn set_command_field_visible(&self, window_index: u32, visible: bool);
+
+    /// Index of the webview currently visible in a specified window
+    fn focused_webview_index(&self, window_index: u32) -> Option<u32>;
+
+    /// Number of webviews in a window
+    fn webview_count(&self, window_index: u32) -> u32;
+
+    /// Open a new webview in a specified window, loading a URI if any
+    fn open_webview(&self, window_index: u32, uri: Option<String>);
+
+    /// Close a webview in a specified window
+    fn close_webview(&self, window_index: u32, webview_index: u32);
+
+    /// Focus a webview in a specified window, hiding the current webview
+    fn focus_webview(&self, window_index: u32, webview_index: u32);
+
+    /// Reload a webview in a specified window
+    fn reload_webview(&self, window_index: u32, webview_index: u32, disable_filters: bool);
+
+    /// Load a URI in a webview
+    fn set_uri(&self, window_index: u32, webview_index: u32, uri: &str);
+
+    /// Go back to the previously loaded resource in a webview
+    fn go_back(&self, window_index: u32, webview_index: u32) -> bool;
+
+    /// Go forward to the next loaded resource in a webview
+    fn go_forward(&self, window_index: u32, webview_index: u32) -> bool;
+
+    /// Get the currently loaded URI or empty string
+    fn uri(&self, window_index: u32, webview_index: u32) -> String;
+
+    /// Find and highlight text in a webview
+    fn find_string(&self, window_index: u32, webview_index: u32, query: &str);
+
+    /// Hide any highlighted results from `find_string`
+    fn hide_find_results(&self, window_index: u32, webview_index: u32);
+
+    /// Help for a single command, looked up by name or alias
+    fn command_help(&self, name: &str) -> Option<CommandHelp>;
+
+    /// Help for every enabled command
+    fn all_command_help(&self) -> Vec<CommandHelp>;
+
+    /// Completions for the names of commands and aliases matching a query
+    fn command_name_completions(&self, query: &str) -> Vec<Completion>;
+}
+
+/// Native commands available by name
+pub struct Builtins {
+    commands: BTreeMap<String, Box<dyn NativeCommand>>,
+}
+
+impl Builtins {
+
+    /// A registry without any commands
+    pub fn new() -> Self {
+        Builtins { commands: BTreeMap::new() }
+    }
+
+    /// Add a command, replacing any command with the same name
+    pub fn register(&mut self, command: Box<dyn NativeCommand>) {
+        self.commands.insert(String::from(command.name()), command);
+    }
+
+    /// The command with a name
+    pub fn get(&self, name: &str) -> Option<&dyn NativeCommand> {
+        self.commands.get(name).map(|command| command.as_ref())
+    }
+
+    /// Names of all commands, sorted
+    pub fn names(&self) -> Vec<&str> {
+        self.commands.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+impl Default for Builtins {
+
+    /// A registry of the commands provided with webkitten
+    fn default() -> Self {
+        let mut builtins = Builtins::new();
+        let commands = vec![
+            FunctionCommand::new("back", "Navigate back in the focused webview", run_back),
+            FunctionCommand::new("buffernew", "Open a new buffer with a URI or the configured start page",
+                                 run_buffer_new),
+            FunctionCommand::new("buffernext", "Focus the next buffer in the window", run_buffer_next),
+            FunctionCommand::new("bufferprev", "Focus the previous buffer in the window", run_buffer_prev),
+            FunctionCommand::new("cancel", "Stop command scripts which are currently running", run_cancel),
+            FunctionCommand::new("close", "Close the current webview or window", run_close),
+            FunctionCommand::new("copy", "Copy the URI of the focused webview", run_copy),
+            FunctionCommand::new("endfind", "Hide results from 'find'", run_end_find),
+            FunctionCommand::new("find", "Find text in the current webview", run_find),
+            FunctionCommand::new("focus-bar", "Focus the command bar", run_focus_bar),
+            FunctionCommand::new("focus-webview", "Focus the webview", run_focus_webview),
+            FunctionCommand::new("forward", "Navigate forward in the focused webview", run_forward),
+            FunctionCommand::new("go", "Open a web page", run_go),
+            FunctionCommand::new("reload", "Reload the current view, optionally skipping content filters (force)",
+                                 run_reload).with_completion(complete_reload),
+            FunctionCommand::new("toggle-bar", "Toggle the visibility of the command bar", run_toggle_bar),
+            FunctionCommand::new("windownew", "Open a new window with a URI or the configured start page",
+                                 run_window_new),
+        ];
+        for command in commands {
+            builtins.register(Box::new(command));
+        }
+        builtins.register(Box::new(help::HelpCommand));
+        builtins
+    }
+}
+
+/// A function running a native command with its arguments
+pub type RunFunction = fn(&dyn NativeContext, &[String]) -> CommandOutput;
+
+/// A function completing the final argument of a native command
+pub type CompleteFunction = fn(&dyn NativeContext, &[String]) -> Vec<Completion>;
+
+/// A native command which runs and completes using functions
+pub struct FunctionCommand {
+    name: &'static str,
+    description: &'static str,
+    run: RunFunction,
+    complete: Option<CompleteFunction>,
+}
+
+impl FunctionCommand {
+
+    pub fn new(name: &'static str, description: &'static str, run: RunFunction) -> Self {
+        FunctionCommand { name, description, run, complete: None }
+    }
+
+    /// Complete arguments using a function
+    pub fn with_completion(mut self, complete: CompleteFunction) -> Self {
+        self.complete = Some(complete);
+        self
+    }
+}
+
+impl NativeCommand for FunctionCommand {
+
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn describe(&self) -> &str {
+        self.description
+    }
+
+    fn run(&self, context: &dyn NativeContext, arguments: &[String]) -> CommandOutput {
+        (self.run)(context, arguments)
+    }
+
+    fn complete(&self, context: &dyn NativeContext, arguments: &[String]) -> Vec<Completion> {
+        self.complete.map(|complete| complete(context, arguments)).unwrap_or_default()
+    }
+}
+
+/// A `NativeContext` forwarding to an `ApplicationUI`
+pub struct UIContext<'a, T: 'a, S> {
+    ui: &'a T,
+    engine: &'a Engine,
+    window_index: Option<u32>,
+    scripting: PhantomData<S>,
+}
+
+impl<'a, T, S> UIContext<'a, T, S>
+    where T: ApplicationUI<S>,
+          S: ScriptingEngine {
+
+    /// A context for a command run from a window, if any
+    pub fn new(ui: &'a T, engine: &'a Engine, window_index: Option<u32>) -> Self {
+        UIContext { ui, engine, window_index, scripting: PhantomData }
+    }
+}
+
+impl<'a, T, S> NativeContext for UIContext<'a, T, S>
+    where T: ApplicationUI<S>,
+          S: ScriptingEngine {
+
+    fn engine(&self) -> &Engine { self.engine }
+
+    fn copy(&self, text: &str) { self.ui.copy(text) }
+
+    fn window_index(&self) -> Option<u32> {
+        self.window_index.or_else(|| self.ui.focused_window_index())
+    }
+
+    fn open_window(&self, uri: Option<String>) {
+        self.ui.open_window::<_, Config>(uri, None);
+    }
+
+    fn close_window(&self, window_index: u32) { self.ui.close_window(window_index) }
+
+    fn focus_window_area(&self, window_index: u32, area: WindowArea) {
+        self.ui.focus_window_area(window_index, area)
+    }
+
+    fn command_field_visible(&self, window_index: u32) -> bool {
+        self.ui.command_field_visible(window_index)
+    }
+
+    fn set_command_field_visible(&self, window_index: u32, visible: bool) {
+        self.ui.set_command_field_visible(window_index, visible)
+    }
+
+    fn focused_webview_index(&self, window_index: u32) -> Option<u32> {
+        self.ui.focused_webview_index(window_index)
+    }
+
+    fn webview_count(&self, window_index: u32) -> u32 { self.ui.webview_count(window_index) }
+
+    fn open_webview(&self, window_index: u32, uri: Option<String>) {
+        self.ui.open_webview::<_, Config>(window_index, uri, None)
+    }
+
+    fn close_webview(&self, window_index: u32, webview_index: u32) {
+        self.ui.close_webview(window_index, webview_index)
+    }
+
+    fn focus_webview(&self, window_index: u32, webview_index: u32) {
+        self.ui.focus_webview(window_index, webview_index)
+    }
+
+    fn reload_webview(&self, window_index: u32, webview_index: u32, disable_filters: bool) {
+        self.ui.reload_webview(window_index, webview_index, disable_filters)
+    }
+
+    fn set_uri(&self, window_index: u32, webview_index: u32, uri: &str) {
+        self.ui.set_uri(window_index, webview_index, uri)
+    }
+
+    fn go_back(&self, window_index: u32, webview_index: u32) -> bool {
+        self.ui.go_back(window_index, webview_index)
+    }
+
+    fn go_forward(&self, window_index: u32, webview_index: u32) -> bool {
+        self.ui.go_forward(window_index, webview_index)
+    }
+
+    fn uri(&self, window_index: u32, webview_index: u32) -> String {
+        self.ui.uri(window_index, webview_index)
+    }
+
+    fn find_string(&self, window_index: u32, webview_index: u32, query: &str) {
+        self.ui.find_string(window_index, webview_index, query)
+    }
+
+    fn hide_find_results(&self, window_index: u32, webview_index: u32) {
+        self.ui.hide_find_results(window_index, webview_index)
+    }
+
+    fn command_help(&self, name: &str) -> Option<CommandHelp> {
+        help::command::<S>(&self.engine.config, self.engine.scripts(), self.engine.builtins(), name)
+    }
+
+    fn all_command_help(&self) -> Vec<CommandHelp> {
+        help::all_commands::<S>(&self.engine.config, self.engine.scripts(), self.engine.builtins())
+    }
+
+    fn command_name_completions(&self, query: &str) -> Vec<Completion> {
+        self.engine.command_name_completions::<S>(query)
+    }
+}
+
+/// Run a command acting on the window it was run from
+fn in_window<F>(context: &dyn NativeContext, action: F) -> CommandOutput
+    where F: FnOnce(u32) -> CommandOutput {
+    match context.window_index() {
+        Some(window_index) => action(window_index),
+        None => CommandOutput::error(CommandError::ErrorDuringExecution, "No window is focused"),
+    }
+}
+
+/// Run a command acting on the focused webview of the window it was run from
+fn in_webview<F>(context: &dyn NativeContext, action: F) -> CommandOutput
+    where F: FnOnce(u32, u32) -> CommandOutput {
+    in_window(context, |window_index| match context.focused_webview_index(window_index) {
+        Some(webview_index) => action(window_index, webview_index),
+        None => CommandOutput::error(CommandError::ErrorDuringExecution, "No webview is focused"),
+    })
+}
+
+fn run_back(context: &dyn NativeContext, _: &[String]) -> CommandOutput {
+    in_webview(context, |window_index, webview_index| {
+        context.go_back(window_index, webview_index);
+        CommandOutput::success()
+    })
+}
+
+fn run_forward(context: &dyn NativeContext, _: &[String]) -> CommandOutput {
+    in_webview(context, |window_index, webview_index| {
+        context.go_forward(window_index, webview_index);
+        CommandOutput::success()
+    })
+}
+
+fn run_buffer_new(context: &dyn NativeContext, arguments: &[String]) -> CommandOutput {
+    let target = arguments.first().cloned().or(context.engine().config.start_page());
+    match context.window_index() {
+        Some(window_index) => context.open_webview(window_index, target),
+        None => context.open_window(target),
+    }
+    CommandOutput::success()
+}
+
+fn run_window_new(context: &dyn NativeContext, arguments: &[String]) -> CommandOutput {
+    let target = arguments.first().cloned().or(context.engine().config.start_page());
+    context.open_window(target);
+    CommandOutput::success()
+}
+
+fn run_buffer_next(context: &dyn NativeContext, _: &[String]) -> CommandOutput {
+    in_webview(context, |window_index, webview_index| {
+        let webview_count = context.webview_count(window_index);
+        context.focus_webview(window_index, (webview_index + 1) % webview_count.max(1));
+        CommandOutput::success()
+    })
+}
+
+fn run_buffer_prev(context: &dyn NativeContext, _: &[String]) -> CommandOutput {
+    in_webview(context, |window_index, webview_index| {
+        let webview_count = context.webview_count(window_index);
+        let target = if webview_index == 0 { webview_count.max(1) - 1 } else { webview_index - 1 };
+        context.focus_webview(window_index, target);
+        CommandOutput::success()
+    })
+}
+
+fn run_cancel(context: &dyn NativeContext, _: &[String]) -> CommandOutput {
+    context.engine().cancel_commands();
+    CommandOutput::success()
+}
+
+fn run_close(context: &dyn NativeContext, _: &[String]) -> CommandOutput {
+    in_webview(context, |window_index, webview_index| {
+        if context.webview_count(window_index) > 1 {
+            context.close_webview(window_index, webview_index);
+        } else {
+            context.close_window(window_index);
+        }
+        CommandOutput::success()
+    })
+}
+
+fn run_copy(context: &dyn NativeContext, _: &[String]) -> CommandOutput {
+    in_webview(context, |window_index, webview_index| {
+        context.copy(&context.uri(window_index, webview_index));
+        CommandOutput::success()
+    })
+}
+
+fn run_end_find(context: &dyn NativeContext, _: &[String]) -> CommandOutput {
+    in_webview(context, |window_index, webview_index| {
+        context.hide_find_results(window_index, webview_index);
+        CommandOutput::success()
+    })
+}
+
+fn run_find(context: &dyn NativeContext, arguments: &[String]) -> CommandOutput {
+    in_webview(context, |window_index, webview_index| {
+        if !arguments.is_empty() {
+            context.find_string(window_index, webview_index, &arguments.join(" "));
+        }
+        // Keep the query in the command bar to search again
+        CommandOutput::completion(false)
+    })
+}
+
+fn run_focus_bar(context: &dyn NativeContext, _: &[String]) -> CommandOutput {
+    in_window(context, |window_index| {
+        context.focus_window_area(window_index, WindowArea::CommandBar);
+        CommandOutput::success()
+    })
+}
+
+fn run_focus_webview(context: &dyn NativeContext, _: &[String]) -> CommandOutput {
+    in_window(context, |window_index| {
+        context.focus_window_area(window_index, WindowArea::WebView);
+        CommandOutput::success()
+    })
+}
+
+fn run_toggle_bar(context: &dyn NativeContext, _: &[String]) -> CommandOutput {
+    in_window(context, |window_index| {
+        let visible = !context.command_field_visible(window_index);
+        context.set_command_field_visible(window_index, visible);
+        let area = if visible { WindowArea::CommandBar } else { WindowArea::WebView };
+        context.focus_window_area(window_index, area);
+        CommandOutput::success()
+    })
+}
+
+fn run_go(context: &dyn NativeContext, arguments: &[String]) -> CommandOutput {
+    in_webview(context, |window_index, webview_index| match arguments.first() {
+        Some(uri) => {
+            context.set_uri(window_index, webview_index, uri);
+            CommandOutput::success()
+        },
+        None => CommandOutput::error(CommandError::InvalidArguments, "No URL specified"),
+    })
+}
+
+fn run_reload(context: &dyn NativeContext, arguments: &[String]) -> CommandOutput {
+    in_webview(context, |window_index, webview_index| {
+        match arguments.first().map(|arg| arg.as_str()) {
+            None => context.reload_webview(window_index, webview_index, false),
+            Some("f") | Some("force") if arguments.len() == 1 =>
+                context.reload_webview(window_index, webview_index, true),
+            _ => return CommandOutput::error(CommandError::InvalidArguments,
+                                             "Usage: reload [force]"),
+        }
+        CommandOutput::success()
+    })
+}
+
+fn complete_reload(_: &dyn NativeContext, arguments: &[String]) -> Vec<Completion> {
+    let partial = arguments.last().map(|arg| arg.as_str()).unwrap_or("");
+    if arguments.len() < 2 && "force".starts_with(partial) {
+        vec![Completion::described("force", "Reload without content filters")]
+    } else {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_by_name() {
+        let builtins = Builtins::default();
+        for name in builtins.names() {
+            assert_eq!(Some(name), builtins.get(name).map(|command| command.name()));
+        }
+        assert!(builtins.get("missing").is_none());
+        assert!(builtins.get(help::HELP_COMMAND).is_some());
+    }
+
+    #[test]
+    fn register_command() {
+        let mut builtins = Builtins::new();
+        builtins.register(Box::new(FunctionCommand::new("noop", "Does nothing",
+                                                        |_, _| CommandOutput::success())));
+        assert_eq!(vec!["noop"], builtins.names());
+        assert_eq!(Some("Does nothing"), builtins.get("noop").map(|command| command.describe()));
+    }
+}
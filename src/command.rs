@@ -1,73 +1,748 @@
+use std::error;
+use std::fmt;
 use std::path::Path;
-use std::fs::{File,metadata,read_dir};
+use std::fs::{File,Metadata,metadata,read_dir};
 
+use builtin::Builtins;
 use config::Config;
 use ui::BrowserConfiguration;
 
+/// Argument which ends quote and escape processing for the remainder of the
+/// command text
+const ARGUMENT_TERMINATOR: &str = "--";
+
+#[derive(Debug,PartialEq)]
+pub struct ParseError {
+    message: String,
+    reason: ParseErrorReason,
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum ParseErrorReason {
+    TrailingEscape,
+    UnterminatedQuote,
+}
+
+impl error::Error for ParseError {
+
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ParseError {
+
+    fn new(reason: ParseErrorReason, quote: char) -> ParseError {
+        let message = match reason {
+            ParseErrorReason::TrailingEscape =>
+                String::from("Command text ends with an escape character"),
+            ParseErrorReason::UnterminatedQuote =>
+                format!("Missing closing quote ({}) in command text", quote),
+        };
+        ParseError { reason, message }
+    }
+
+    /// The cause of the failure to parse
+    pub fn reason(&self) -> ParseErrorReason {
+        self.reason
+    }
+}
+
+impl fmt::Display for ParseError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// How a command in a sequence depends on the outcome of the command
+/// preceding it
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum SequenceOperator {
+    /// Run regardless of the outcome of the previous command (`;`)
+    Always,
+    /// Run only if the previous command succeeded (`&&`)
+    OnSuccess,
+    /// Run only if the previous command failed (`||`)
+    OnFailure,
+}
+
 /// A representation of a script which executes and returns a boolean value
-/// indicating success
+/// indicating success, or of a built-in command
 #[derive(Debug,Clone)]
 pub struct Command {
+    /// The path to the command file, or an empty string for a built-in
+    /// command
     pub path: String,
     pub arguments: Vec<String>,
+    /// The name of the built-in command, if the command is not a file
+    pub builtin: Option<String>,
+    /// The names of the subdirectories containing the command file within
+    /// the search path, such as `["bookmark"]` for `bookmark/save.lua`
+    pub namespace: Vec<String>,
 }
 
 impl Command {
 
-    /// Parse a command name and arguments into an instance of Command
-    pub fn parse(input: &str, config: &Config, suffix: &str) -> Option<Self> {
-        let mut components = input.split_whitespace();
-        components.next()
-            .and_then(|name| config.resolved_command_name(name))
-            .and_then(|name| resolve_command(config.command_search_paths(), &name, suffix))
-            .and_then(|path| {
-                Some(Command {
-                    path: path,
-                    arguments: components.map(|arg| String::from(arg)).collect(),
-                })
-            })
-    }
-
-    pub fn list_commands(prefix: &str, config: &Config) -> Vec<String> {
-        let mut entries: Vec<String> = vec![];
+    /// Parse a command name and arguments into an instance of Command. Command
+    /// files in the search paths take precedence over built-in commands with
+    /// the same name.
+    ///
+    /// Leading arguments which name subdirectories of the search paths are
+    /// resolved as nested commands, so `bookmark save` runs
+    /// `bookmark/save.lua`, preferring the longest match. If no subcommand
+    /// file matches, the command file named after the first word is used.
+    ///
+    /// Command files may have any of the file extensions in `suffixes`. Where
+    /// a search path contains command files with the same name and different
+    /// extensions, the extension named by `commands.interpreter` is preferred,
+    /// followed by the order of `suffixes`.
+    pub fn parse(input: &str, config: &Config, builtins: &Builtins, suffixes: &[&str]) -> Option<Self> {
+        let components = match tokenize(input) {
+            Ok(components) => components,
+            Err(err) => {
+                info!("Unable to parse command text: {}", err);
+                return None
+            }
+        };
+        let mut components = components.into_iter();
+        let name = components.next().and_then(|name| config.resolved_command_name(&name))?;
+        let arguments: Vec<String> = components.collect();
+        let suffixes = ordered_suffixes(config, suffixes);
+        let library_paths = config.command_library_paths();
+        if let Some(command) = resolve_nested_command(config, &name, &arguments, &suffixes, &library_paths) {
+            return Some(command);
+        }
+        if let Some(path) = resolve_command(config, &name, &suffixes, &library_paths) {
+            return Some(Command { path, arguments, builtin: None, namespace: vec![] });
+        }
+        builtins.get(&name).map(|builtin| {
+            Command { path: String::new(), arguments, builtin: Some(String::from(builtin.name())), namespace: vec![] }
+        })
+    }
+
+    /// Names of the commands and namespaces directly within a namespace in
+    /// the search paths, sorted and excluding disabled commands
+    pub fn list_namespace(namespace: &[String], config: &Config, suffixes: &[&str]) -> Vec<String> {
+        if !namespace.iter().all(|name| is_namespace_component(name)) {
+            return vec![];
+        }
+        let mut names: Vec<String> = vec![];
+        let library_paths = config.command_library_paths();
         for search_path in config.command_search_paths() {
-            if let Ok(contents) = read_dir(search_path) {
-                for entry in contents {
-                    if let Ok(entry) = entry {
-                        let path = entry.path();
-                        if path.is_file() {
-                            if let Some(stem) = path.file_stem().and_then(|p| p.to_str()) {
-                                if stem.starts_with(prefix) {
-                                    entries.push(String::from(stem));
-                                }
-                            }
+            let dir = namespace.iter().fold(Path::new(&search_path).to_path_buf(), |dir, name| dir.join(name));
+            if let Ok(contents) = read_dir(dir) {
+                for path in contents.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+                    let name = if is_library_path(&path, &library_paths) {
+                        None
+                    } else if path.is_dir() {
+                        path.file_name().and_then(|name| name.to_str())
+                    } else if has_suffix(&path, suffixes) {
+                        path.file_stem().and_then(|stem| stem.to_str())
+                    } else {
+                        None
+                    };
+                    if let Some(name) = name.filter(|name| is_namespace_component(name)) {
+                        let mut full_name = namespace.to_vec();
+                        full_name.push(String::from(name));
+                        if !config.command_disabled(&full_name.join(" ")) {
+                            names.push(String::from(name));
                         }
                     }
                 }
             }
         }
-        entries.sort();
-        entries
+        names.sort();
+        names.dedup();
+        names
     }
 
-    /// A File handle to the command path
+    /// Whether a subdirectory of any search path matches a namespace, other
+    /// than a library path
+    pub fn is_namespace(namespace: &[String], config: &Config) -> bool {
+        is_namespace_dir(namespace, config, &config.command_library_paths())
+    }
+
+    /// All enabled commands in the search paths with any of the file
+    /// extensions in `suffixes`, including nested commands in subdirectories
+    /// other than the library paths, and all enabled built-in commands,
+    /// sorted by name. Where several search paths contain a
+    /// command with the same name, only the command which would be resolved
+    /// first is included.
+    pub fn list_available(config: &Config, builtins: &Builtins, suffixes: &[&str]) -> Vec<Command> {
+        let mut commands: Vec<Command> = vec![];
+        let suffixes = ordered_suffixes(config, suffixes);
+        let library_paths = config.command_library_paths();
+        for search_path in config.command_search_paths() {
+            collect_commands(Path::new(&search_path), &[], config, &suffixes, &library_paths, &mut commands);
+        }
+        for name in builtins.names() {
+            let exists = commands.iter().any(|command| command.name() == name);
+            if !exists && !config.command_disabled(name) {
+                commands.push(Command {
+                    path: String::new(),
+                    arguments: vec![],
+                    builtin: Some(String::from(name)),
+                    namespace: vec![],
+                });
+            }
+        }
+        commands.sort_by_key(|command| command.name());
+        commands
+    }
+
+    /// The name of the command, based on the command file name or built-in
+    /// command. Nested command names include the namespace, separated by
+    /// spaces, such as `bookmark save`.
+    pub fn name(&self) -> String {
+        if let Some(ref builtin) = self.builtin {
+            return builtin.clone();
+        }
+        let stem = Path::new(&self.path).file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(String::from)
+            .unwrap_or_default();
+        let mut words = self.namespace.clone();
+        words.push(stem);
+        words.join(" ")
+    }
+
+    /// A File handle to the command path, if the command is not built-in
     pub fn file(&self) -> Option<File> {
+        if self.builtin.is_some() {
+            return None;
+        }
         File::open(&self.path).ok()
     }
 }
 
+/// Split command text into words, following shell-style quoting rules
+///
+/// * Words are separated by whitespace
+/// * Text within single quotes is taken literally
+/// * Text within double quotes is taken literally except for `\"` and `\\`
+/// * A backslash outside of quotes escapes the following character
+/// * All text following a standalone `--` is split on whitespace without
+///   any quote or escape processing, except that a backslash before `;`,
+///   `&` or `|` is removed
+pub fn tokenize(input: &str) -> Result<Vec<String>, ParseError> {
+    let mut words: Vec<String> = vec![];
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = input.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some((_, '\'')) => break,
+                        Some((_, c)) => word.push(c),
+                        None => return Err(ParseError::new(ParseErrorReason::UnterminatedQuote, '\'')),
+                    }
+                }
+            },
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) if c == '"' || c == '\\' => word.push(c),
+                            Some((_, c)) => { word.push('\\'); word.push(c); },
+                            None => return Err(ParseError::new(ParseErrorReason::UnterminatedQuote, '"')),
+                        },
+                        Some((_, c)) => word.push(c),
+                        None => return Err(ParseError::new(ParseErrorReason::UnterminatedQuote, '"')),
+                    }
+                }
+            },
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some((_, c)) => word.push(c),
+                    None => return Err(ParseError::new(ParseErrorReason::TrailingEscape, '\\')),
+                }
+            },
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(word.clone());
+                    word.clear();
+                    in_word = false;
+                }
+            },
+            c => {
+                if !in_word && c == '-' && is_terminator(&input[index..]) {
+                    let remainder = &input[index + ARGUMENT_TERMINATOR.len()..];
+                    words.extend(remainder.split_whitespace().map(unescape_separators));
+                    return Ok(words);
+                }
+                in_word = true;
+                word.push(c);
+            },
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Split command text into a sequence of commands separated by `;`, `&&`, or
+/// `||`, ignoring separators within quotes or following an escape character,
+/// including after a standalone `--`. The text of each command is otherwise
+/// unchanged, and empty commands are skipped.
+pub fn split_sequence(input: &str) -> Result<Vec<(SequenceOperator, String)>, ParseError> {
+    let mut sequence: Vec<(SequenceOperator, String)> = vec![];
+    let mut operator = SequenceOperator::Always;
+    let mut start = 0;
+    let mut terminated = false;
+    let mut word_start = true;
+    let mut chars = input.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if terminated && c == '\\' && chars.peek().map(|&(_, c)| is_separator(c)).unwrap_or(false) {
+            chars.next();
+            continue;
+        }
+        let separator = match c {
+            ';' => Some((SequenceOperator::Always, 1)),
+            '&' if chars.peek().map(|&(_, c)| c) == Some('&') => Some((SequenceOperator::OnSuccess, 2)),
+            '|' if chars.peek().map(|&(_, c)| c) == Some('|') => Some((SequenceOperator::OnFailure, 2)),
+            _ => None,
+        };
+        if let Some((next_operator, length)) = separator {
+            push_sequence_item(&mut sequence, operator, &input[start..index]);
+            if length > 1 {
+                chars.next();
+            }
+            operator = next_operator;
+            start = index + length;
+            terminated = false;
+            word_start = true;
+            continue;
+        }
+        if terminated {
+            continue;
+        }
+        match c {
+            '\'' => loop {
+                match chars.next() {
+                    Some((_, '\'')) => break,
+                    Some(_) => (),
+                    None => return Err(ParseError::new(ParseErrorReason::UnterminatedQuote, '\'')),
+                }
+            },
+            '"' => loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => { chars.next(); },
+                    Some(_) => (),
+                    None => return Err(ParseError::new(ParseErrorReason::UnterminatedQuote, '"')),
+                }
+            },
+            '\\' if chars.next().is_none() => {
+                return Err(ParseError::new(ParseErrorReason::TrailingEscape, '\\'));
+            },
+            '-' if word_start && is_terminator(&input[index..]) => terminated = true,
+            _ => (),
+        }
+        word_start = c.is_whitespace();
+    }
+    push_sequence_item(&mut sequence, operator, &input[start..]);
+    Ok(sequence)
+}
+
+/// Whether alias text contains any argument placeholders expanded by
+/// `expand_placeholders`
+pub fn has_placeholders(alias: &str) -> bool {
+    let mut chars = alias.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$' {
+            match chars.peek() {
+                Some(&next) if next == '@' || next == '{' || next == '$' || next.is_ascii_digit() => return true,
+                _ => (),
+            }
+        }
+    }
+    false
+}
+
+/// Substitute arguments for placeholders in alias text
+///
+/// * `$1` to `$9` expand to the argument at a position
+/// * `${N}` expands to the argument at position `N`
+/// * `${N:-default}` expands to the argument at position `N`, or `default`
+///   if there is no argument at that position
+/// * `${N@}` expands to all arguments starting at position `N`
+/// * `$@` expands to all arguments
+/// * `$$` expands to `$`
+///
+/// Placeholders without a matching argument expand to nothing. Arguments are
+/// escaped so that each remains a single argument when the expanded text is
+/// tokenized, including within quotes.
+///
+/// ## Examples
+///
+/// ```
+/// use webkitten::command::expand_placeholders;
+///
+/// let arguments = vec![String::from("kattrali"), String::from("my repo")];
+/// assert_eq!("go https://github.com/kattrali",
+///            expand_placeholders("go https://github.com/$1", &arguments));
+/// assert_eq!("search kattrali my\\ repo", expand_placeholders("search $@", &arguments));
+/// assert_eq!("find \"kattrali\"", expand_placeholders("find \"${1:-me}\"", &arguments[..1]));
+/// ```
+pub fn expand_placeholders(alias: &str, arguments: &[String]) -> String {
+    let mut output = String::new();
+    let mut context = QuoteContext::Unquoted;
+    let mut word_start = true;
+    let mut index = 0;
+    while index < alias.len() {
+        let text = &alias[index..];
+        let c = text.chars().next().unwrap();
+        if c == '$' {
+            if text.starts_with("$$") {
+                output.push('$');
+                index += 2;
+                word_start = false;
+                continue;
+            }
+            if let Some((values, length)) = parse_placeholder(text, arguments) {
+                output.push_str(&quote_values(&values, context));
+                index += length;
+                word_start = false;
+                continue;
+            }
+        }
+        output.push(c);
+        index += c.len_utf8();
+        match context {
+            QuoteContext::Unquoted => match c {
+                '\'' => context = QuoteContext::Single,
+                '"' => context = QuoteContext::Double,
+                '\\' => if let Some(next) = alias[index..].chars().next() {
+                    output.push(next);
+                    index += next.len_utf8();
+                },
+                '-' if word_start && is_terminator(text) => context = QuoteContext::Terminated,
+                _ => (),
+            },
+            QuoteContext::Single => if c == '\'' {
+                context = QuoteContext::Unquoted;
+            },
+            QuoteContext::Double => match c {
+                '"' => context = QuoteContext::Unquoted,
+                '\\' => if let Some(next) = alias[index..].chars().next() {
+                    output.push(next);
+                    index += next.len_utf8();
+                },
+                _ => (),
+            },
+            QuoteContext::Terminated => match c {
+                '\\' => if let Some(next) = alias[index..].chars().next().filter(|c| is_separator(*c)) {
+                    output.push(next);
+                    index += next.len_utf8();
+                },
+                c if is_separator(c) => context = QuoteContext::Unquoted,
+                _ => (),
+            },
+        }
+        word_start = c.is_whitespace() || c == ';' || c == '&' || c == '|';
+    }
+    output
+}
+
+/// The quoting rules in effect at a position in command text
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum QuoteContext {
+    Unquoted,
+    Single,
+    Double,
+    /// Following an argument terminator, where only sequence separators
+    /// are escaped
+    Terminated,
+}
+
+/// Parse a placeholder at the start of text, returning the arguments it
+/// expands to and the length of the placeholder
+fn parse_placeholder(text: &str, arguments: &[String]) -> Option<(Vec<String>, usize)> {
+    let rest = &text[1..];
+    if rest.starts_with('@') {
+        return Some((arguments.to_vec(), 2));
+    }
+    if let Some(digit) = rest.chars().next().and_then(|c| c.to_digit(10)) {
+        if digit == 0 {
+            return None;
+        }
+        return Some((arguments.get(digit as usize - 1).cloned().into_iter().collect(), 2));
+    }
+    if !rest.starts_with('{') {
+        return None;
+    }
+    let end = rest.find('}')?;
+    let body = &rest[1..end];
+    let length = end + 2;
+    let digits = body.chars().take_while(|c| c.is_ascii_digit()).count();
+    let position = match body[..digits].parse::<usize>() {
+        Ok(position) if position > 0 => position,
+        _ => return None,
+    };
+    let modifier = &body[digits..];
+    let argument = arguments.get(position - 1).cloned();
+    if modifier.is_empty() {
+        Some((argument.into_iter().collect(), length))
+    } else if modifier == "@" {
+        Some((arguments.iter().skip(position - 1).cloned().collect(), length))
+    } else if let Some(default) = modifier.strip_prefix(":-") {
+        let value = argument.unwrap_or(String::from(default));
+        Some((vec![value], length))
+    } else {
+        None
+    }
+}
+
+/// Join arguments for substitution into command text, escaped so that each
+/// remains a single argument in a quote context
+fn quote_values(values: &[String], context: QuoteContext) -> String {
+    match context {
+        QuoteContext::Unquoted => values.iter()
+            .map(|value| escape_unquoted(value))
+            .collect::<Vec<String>>()
+            .join(" "),
+        QuoteContext::Single => format!("'{}'", escape_unquoted(&values.join(" "))),
+        QuoteContext::Double => values.join(" ").replace("\\", "\\\\").replace("\"", "\\\""),
+        QuoteContext::Terminated => values.iter()
+            .map(|value| escape_separators(value))
+            .collect::<Vec<String>>()
+            .join(" "),
+    }
+}
+
+/// Escape the characters which separate commands in a sequence, the only
+/// escapes following an argument terminator
+fn escape_separators(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if is_separator(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Remove the escapes of separator characters from text following an
+/// argument terminator
+fn unescape_separators(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(&next) if c == '\\' && is_separator(next) => (),
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Whether a character is part of an operator separating commands in a
+/// sequence
+pub fn is_separator(c: char) -> bool {
+    c == ';' || c == '&' || c == '|'
+}
+
+/// Escape characters which would otherwise split or end an argument
+fn escape_unquoted(value: &str) -> String {
+    let mut escaped = String::new();
+    for (index, c) in value.chars().enumerate() {
+        let special = c.is_whitespace() || c == '\'' || c == '"' || c == '\\'
+            || c == ';' || c == '&' || c == '|' || (index == 0 && c == '-');
+        if special {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn push_sequence_item(sequence: &mut Vec<(SequenceOperator, String)>,
+                      operator: SequenceOperator, text: &str) {
+    let text = text.trim();
+    if !text.is_empty() {
+        sequence.push((operator, String::from(text)));
+    }
+}
+
+/// Whether text begins with a standalone argument terminator
+fn is_terminator(text: &str) -> bool {
+    text.starts_with(ARGUMENT_TERMINATOR) && text[ARGUMENT_TERMINATOR.len()..].chars()
+        .next()
+        .map(|c| c.is_whitespace())
+        .unwrap_or(true)
+}
+
 /// Iterate over search paths returning the first file path in search paths
-/// with the provided name
-fn resolve_command(search_paths: Vec<String>, name: &str, suffix: &str) -> Option<String> {
+/// with the provided name, trying each suffix in order within a search path.
+/// Files in the library paths are skipped.
+fn resolve_command(config: &Config, name: &str, suffixes: &[&str], library_paths: &[String]) -> Option<String> {
     if name.is_empty() {
         return None
     }
-    let mut ordered_paths: Vec<String> = search_paths.iter()
-        .filter_map(|path| join_paths(&path, &format!("{}.{}", name, suffix)))
-        .filter(|path| metadata(&path).is_ok())
-        .collect();
-    ordered_paths.reverse();
-    return ordered_paths.pop();
+    config.command_search_paths().iter()
+        .flat_map(|path| suffixes.iter()
+                  .filter_map(move |suffix| join_paths(path, &file_name(name, suffix))))
+        .find(|path| {
+            let path = Path::new(path);
+            is_command_file(path) && !is_library_path(path, library_paths)
+        })
+}
+
+/// The file name of a command with a file extension, or of an executable
+/// without a file extension if the extension is empty
+fn file_name(name: &str, suffix: &str) -> String {
+    if suffix.is_empty() {
+        String::from(name)
+    } else {
+        format!("{}.{}", name, suffix)
+    }
+}
+
+/// Whether a path is a command file, which must be executable if it has no
+/// file extension
+fn is_command_file(path: &Path) -> bool {
+    match metadata(path) {
+        Ok(info) => info.is_file() && (path.extension().is_some() || is_executable(&info)),
+        Err(_) => false,
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(info: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    info.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_: &Metadata) -> bool {
+    true
+}
+
+/// File extensions ordered by preference, starting with the extension named
+/// by `commands.interpreter` if present
+fn ordered_suffixes<'a>(config: &Config, suffixes: &[&'a str]) -> Vec<&'a str> {
+    let mut ordered = suffixes.to_vec();
+    if let Some(interpreter) = config.command_interpreter() {
+        ordered.sort_by_key(|suffix| *suffix != interpreter);
+    }
+    ordered
+}
+
+/// Whether a path is a command file with one of the file extensions in
+/// `suffixes`, where an empty extension matches executables without one
+fn has_suffix(path: &Path, suffixes: &[&str]) -> bool {
+    let extension = match path.extension() {
+        Some(extension) => extension.to_str(),
+        None => Some(""),
+    };
+    extension.map(|extension| suffixes.contains(&extension)).unwrap_or(false)
+        && is_command_file(path)
+}
+
+/// Find the nested command with the longest name matching a command name
+/// followed by leading arguments
+fn resolve_nested_command(config: &Config, name: &str, arguments: &[String], suffixes: &[&str],
+                          library_paths: &[String]) -> Option<Command> {
+    let mut namespace = vec![String::from(name)];
+    let mut command: Option<Command> = None;
+    for (index, leaf) in arguments.iter().enumerate() {
+        if !is_namespace_component(leaf) || !is_namespace_dir(&namespace, config, library_paths) {
+            break;
+        }
+        let full_name = format!("{} {}", namespace.join(" "), leaf);
+        let relative_path = format!("{}/{}", namespace.join("/"), leaf);
+        if !config.command_disabled(&full_name) {
+            if let Some(path) = resolve_command(config, &relative_path, suffixes, library_paths) {
+                command = Some(Command {
+                    path,
+                    arguments: arguments[index + 1..].to_vec(),
+                    builtin: None,
+                    namespace: namespace.clone(),
+                });
+            }
+        }
+        namespace.push(leaf.clone());
+    }
+    command
+}
+
+/// Add enabled command files in a directory and its subdirectories to a list
+/// of commands, skipping names which are already present. Files with the same
+/// name are ordered by the preference of their extension in `suffixes`.
+fn collect_commands(dir: &Path, namespace: &[String], config: &Config, suffixes: &[&str],
+                    library_paths: &[String], commands: &mut Vec<Command>) {
+    let contents = match read_dir(dir) {
+        Ok(contents) => contents,
+        Err(_) => return,
+    };
+    let mut paths: Vec<_> = contents.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect();
+    paths.sort_by_key(|path| {
+        let rank = path.extension().map_or(Some(""), |e| e.to_str())
+            .and_then(|extension| suffixes.iter().position(|suffix| *suffix == extension));
+        (path.file_stem().map(|stem| stem.to_os_string()), rank)
+    });
+    for path in paths {
+        if is_library_path(&path, library_paths) {
+            continue;
+        }
+        if path.is_dir() {
+            if let Some(name) = path.file_name().and_then(|name| name.to_str()).filter(|name| is_namespace_component(name)) {
+                let mut nested = namespace.to_vec();
+                nested.push(String::from(name));
+                collect_commands(&path, &nested, config, suffixes, library_paths, commands);
+            }
+            continue;
+        }
+        if !has_suffix(&path, suffixes) {
+            continue;
+        }
+        if let (Some(stem), Some(path)) = (path.file_stem().and_then(|p| p.to_str()), path.to_str()) {
+            let command = Command {
+                path: String::from(path),
+                arguments: vec![],
+                builtin: None,
+                namespace: namespace.to_vec(),
+            };
+            let name = command.name();
+            let exists = commands.iter().any(|command| command.name() == name);
+            if is_namespace_component(stem) && !exists && !config.command_disabled(&name) {
+                commands.push(command);
+            }
+        }
+    }
+}
+
+/// Whether a subdirectory of any search path matches a namespace, other than
+/// one of the library paths
+fn is_namespace_dir(namespace: &[String], config: &Config, library_paths: &[String]) -> bool {
+    !namespace.is_empty()
+        && namespace.iter().all(|name| is_namespace_component(name))
+        && config.command_search_paths().iter().any(|search_path| {
+            let dir = namespace.iter()
+                .fold(Path::new(search_path).to_path_buf(), |dir, name| dir.join(name));
+            dir.is_dir() && !is_library_path(&dir, library_paths)
+        })
+}
+
+/// Whether a path is within any of the library paths, so is a module rather
+/// than a command
+fn is_library_path(path: &Path, library_paths: &[String]) -> bool {
+    library_paths.iter().any(|library| path.starts_with(library))
+}
+
+/// Whether a word can name a command file or namespace directory, excluding
+/// hidden files and path separators
+fn is_namespace_component(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains('/') && !name.contains('\\')
 }
 
 /// Join a directory and file name into a string path if possible
@@ -81,18 +756,15 @@ mod tests {
     use super::*;
     use config::Config;
     use ui::BrowserConfiguration;
-    use std::env::temp_dir;
-    use std::fs::{File,remove_file};
-    use std::io::Write;
-    use std::path::Path;
+    use tests::{TestDir,create_ui};
+    use ui::{ApplicationUI,CommandError};
 
     #[test]
-    #[allow(unused_must_use)]
     fn resolve_by_filename() {
-        let (path, result) = create_command("hello.lua",
-                                            b"print(\"hello world\");",
+        let dir = TestDir::new("resolve-filename");
+        let (path, result) = create_command(&dir, "hello.lua",
+                                            "print(\"hello world\");",
                                             "hello world");
-        remove_file(Path::new(path.as_str().clone()));
         assert!(result.is_some());
 
         let command = result.unwrap();
@@ -100,19 +772,373 @@ mod tests {
         assert_eq!(command.path, path);
     }
 
-    #[allow(unused_must_use)]
-    fn create_command(name: &str, content: &[u8], invocation: &str) -> (String, Option<Command>) {
-        let dir = temp_dir();
-        let search_path = String::from(dir.clone().to_str().unwrap());
-        let file_path = Path::new(dir.to_str().unwrap()).join(name);
-        let mut file = File::create(file_path.as_path()).ok().unwrap();
-        assert!(file.write(content).is_ok());
-        file.flush();
-        let config = Config::parse(&format!(r#"
+    #[test]
+    fn tokenize_whitespace() {
+        let words = tokenize("  bookmark   save\tnews ").unwrap();
+        assert_eq!(vec!["bookmark", "save", "news"], words);
+    }
+
+    #[test]
+    fn tokenize_double_quotes() {
+        let words = tokenize(r#"bookmark save "My Page Title""#).unwrap();
+        assert_eq!(vec!["bookmark", "save", "My Page Title"], words);
+    }
+
+    #[test]
+    fn tokenize_single_quotes() {
+        let words = tokenize(r#"search 'say "hi" \now'"#).unwrap();
+        assert_eq!(vec!["search", r#"say "hi" \now"#], words);
+    }
+
+    #[test]
+    fn tokenize_escapes() {
+        let words = tokenize(r#"search two\ words "a \"quote\" \n" it\'s"#).unwrap();
+        assert_eq!(vec!["search", "two words", r#"a "quote" \n"#, "it's"], words);
+    }
+
+    #[test]
+    fn tokenize_adjacent_quotes() {
+        let words = tokenize(r#"echo pre"fix"'ed' "" x"#).unwrap();
+        assert_eq!(vec!["echo", "prefixed", "", "x"], words);
+    }
+
+    #[test]
+    fn tokenize_terminator() {
+        let words = tokenize(r#"search "a b" -- "c d" \e"#).unwrap();
+        assert_eq!(vec!["search", "a b", "\"c", "d\"", "\\e"], words);
+    }
+
+    #[test]
+    fn tokenize_dashes_within_words() {
+        let words = tokenize("go --help a--b").unwrap();
+        assert_eq!(vec!["go", "--help", "a--b"], words);
+    }
+
+    #[test]
+    fn tokenize_unterminated_quote() {
+        let err = tokenize(r#"bookmark save "My Page"#).err().unwrap();
+        assert_eq!(ParseErrorReason::UnterminatedQuote, err.reason());
+        let err = tokenize("search 'oops").err().unwrap();
+        assert_eq!(ParseErrorReason::UnterminatedQuote, err.reason());
+    }
+
+    #[test]
+    fn tokenize_trailing_escape() {
+        let err = tokenize("search oops\\").err().unwrap();
+        assert_eq!(ParseErrorReason::TrailingEscape, err.reason());
+    }
+
+    #[test]
+    fn split_single_command() {
+        let sequence = split_sequence("go example.com").unwrap();
+        assert_eq!(vec![(SequenceOperator::Always, String::from("go example.com"))], sequence);
+    }
+
+    #[test]
+    fn split_operators() {
+        let sequence = split_sequence("buffernew; go example.com && toggle-bar || reload").unwrap();
+        assert_eq!(vec![(SequenceOperator::Always, String::from("buffernew")),
+                        (SequenceOperator::Always, String::from("go example.com")),
+                        (SequenceOperator::OnSuccess, String::from("toggle-bar")),
+                        (SequenceOperator::OnFailure, String::from("reload"))],
+                   sequence);
+    }
+
+    #[test]
+    fn split_ignores_quoted_separators() {
+        let sequence = split_sequence(r#"search "a;b" 'c && d' e\;f | g & h; reload;"#).unwrap();
+        assert_eq!(vec![(SequenceOperator::Always, String::from(r#"search "a;b" 'c && d' e\;f | g & h"#)),
+                        (SequenceOperator::Always, String::from("reload"))],
+                   sequence);
+    }
+
+    #[test]
+    fn split_after_terminator() {
+        let sequence = split_sequence("search -- it's; reload").unwrap();
+        assert_eq!(vec![(SequenceOperator::Always, String::from("search -- it's")),
+                        (SequenceOperator::Always, String::from("reload"))],
+                   sequence);
+    }
+
+    #[test]
+    fn split_unterminated_quote() {
+        let err = split_sequence("search 'a; reload").err().unwrap();
+        assert_eq!(ParseErrorReason::UnterminatedQuote, err.reason());
+    }
+
+    #[test]
+    fn resolve_quoted_arguments() {
+        let dir = TestDir::new("resolve-quoted");
+        let (_, result) = create_command(&dir, "quoted.lua",
+                                         "print(\"hello world\");",
+                                         "quoted 'hello world' again");
+        assert_eq!(vec![String::from("hello world"), String::from("again")],
+                   result.unwrap().arguments);
+    }
+
+    fn create_command(dir: &TestDir, name: &str, content: &str, invocation: &str) -> (String, Option<Command>) {
+        let file_path = dir.write(name, content);
+        let config = create_namespace(dir, &[], "");
+        let result = Command::parse(invocation, &config, &Builtins::default(), &["lua"]);
+        (String::from(file_path.to_str().unwrap()), result)
+    }
+
+    fn create_namespace(dir: &TestDir, files: &[&str], options: &str) -> Config {
+        for file_name in files {
+            dir.write(file_name, "");
+        }
+        Config::parse(&format!(r#"
             [commands]
             search-paths = ["{}"]
-        "#, search_path)).unwrap();
-        let result = Command::parse(invocation, &config, "lua");
-        return (String::from(file_path.to_str().unwrap()), result);
+            {}
+        "#, dir.path().display(), options)).unwrap()
+    }
+
+    #[test]
+    fn resolve_nested_command() {
+        let dir = TestDir::new("nested-resolve");
+        let config = create_namespace(&dir, &[
+            "bookmark.lua", "bookmark/save.lua", "bookmark/tag.lua", "bookmark/tag/add.lua",
+        ], "");
+        let command = Command::parse("bookmark save example.com", &config, &Builtins::default(), &["lua"]).unwrap();
+        assert_eq!("bookmark save", &command.name());
+        assert_eq!(arguments(&["example.com"]), command.arguments);
+        let command = Command::parse("bookmark tag add x", &config, &Builtins::default(), &["lua"]).unwrap();
+        assert_eq!("bookmark tag add", &command.name());
+        assert_eq!(arguments(&["x"]), command.arguments);
+        let command = Command::parse("bookmark tag remove x", &config, &Builtins::default(), &["lua"]).unwrap();
+        assert_eq!("bookmark tag", &command.name());
+        assert_eq!(arguments(&["remove", "x"]), command.arguments);
+    }
+
+    #[test]
+    fn resolve_nested_command_fallback() {
+        let dir = TestDir::new("nested-fallback");
+        let config = create_namespace(&dir, &[
+            "bookmark.lua", "bookmark/save.lua", "bookmark/.hidden.lua",
+        ], r#"disabled = ["bookmark save"]"#);
+        let command = Command::parse("bookmark save", &config, &Builtins::default(), &["lua"]).unwrap();
+        assert_eq!("bookmark", &command.name());
+        assert_eq!(arguments(&["save"]), command.arguments);
+        let command = Command::parse("bookmark ../bookmark", &config, &Builtins::default(), &["lua"]).unwrap();
+        assert_eq!("bookmark", &command.name());
+        assert_eq!("bookmark", &Command::parse("bookmark .hidden", &config, &Builtins::default(), &["lua"]).unwrap().name());
+    }
+
+    #[test]
+    fn list_nested_commands() {
+        let dir = TestDir::new("nested-list");
+        let config = create_namespace(&dir, &[
+            "bookmark/save.lua", "bookmark/open.lua", "bookmark/tag/add.lua", "bookmark/notes.txt",
+        ], r#"disabled = ["bookmark open"]"#);
+        let names: Vec<String> = Command::list_available(&config, &Builtins::default(), &["lua"]).iter()
+            .map(|command| command.name())
+            .filter(|name| name.starts_with("bookmark"))
+            .collect();
+        assert_eq!(arguments(&["bookmark save", "bookmark tag add"]), names);
+        assert_eq!(arguments(&["save", "tag"]),
+                   Command::list_namespace(&arguments(&["bookmark"]), &config, &["lua"]));
+        assert!(Command::is_namespace(&arguments(&["bookmark", "tag"]), &config));
+        assert!(!Command::is_namespace(&arguments(&["bookmark", "save"]), &config));
+    }
+
+    #[test]
+    fn skip_library_paths() {
+        let dir = TestDir::new("library-paths");
+        let config = create_namespace(&dir, &[
+            "go.lua", "lib/util.lua", "lib/webkitten/util.lua", "shared/helpers.lua", "shared.lua",
+        ], "");
+        assert!(Command::parse("lib util", &config, &Builtins::default(), &["lua"]).is_none());
+        assert!(!Command::is_namespace(&arguments(&["lib"]), &config));
+        assert_eq!(arguments(&["go", "shared", "shared helpers"]), command_names(&config));
+        let config = create_namespace(&dir, &[], &format!(
+            r#"library-paths = ["{}"]"#, dir.path().join("shared").display()));
+        assert_eq!("shared", &Command::parse("shared helpers", &config, &Builtins::default(), &["lua"]).unwrap().name());
+        assert!(Command::list_namespace(&arguments(&["shared"]), &config, &["lua"]).is_empty());
+        assert_eq!(arguments(&["go", "lib util", "lib webkitten util", "shared"]), command_names(&config));
+    }
+
+    fn command_names(config: &Config) -> Vec<String> {
+        Command::list_available(config, &Builtins::default(), &["lua"]).into_iter()
+            .filter(|command| command.builtin.is_none())
+            .map(|command| command.name())
+            .collect()
+    }
+
+    #[test]
+    fn resolve_preferred_interpreter() {
+        let dir = TestDir::new("interpreters");
+        let config = create_namespace(&dir, &["go.lua", "go.js", "back.js"], "");
+        assert!(Command::parse("go", &config, &Builtins::default(), &["lua", "js"]).unwrap().path.ends_with("go.lua"));
+        assert!(Command::parse("back", &config, &Builtins::default(), &["lua", "js"]).unwrap().path.ends_with("back.js"));
+        assert!(Command::parse("back", &config, &Builtins::default(), &["lua"]).unwrap().builtin.is_some());
+        let config = create_namespace(&dir, &[], r#"interpreter = "js""#);
+        assert!(Command::parse("go", &config, &Builtins::default(), &["lua", "js"]).unwrap().path.ends_with("go.js"));
+        let paths: Vec<String> = Command::list_available(&config, &Builtins::default(), &["lua", "js"]).into_iter()
+            .filter(|command| command.builtin.is_none())
+            .map(|command| command.path)
+            .collect();
+        assert_eq!(2, paths.len());
+        assert!(paths.iter().all(|path| path.ends_with(".js")));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn resolve_executables() {
+        use std::fs::{Permissions,set_permissions};
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TestDir::new("executables");
+        let config = create_namespace(&dir, &["open", "notes", "bookmark/save.lua"], "");
+        set_permissions(dir.path().join("open"), Permissions::from_mode(0o755)).unwrap();
+        set_permissions(dir.path().join("notes"), Permissions::from_mode(0o644)).unwrap();
+        let suffixes = ["lua", ""];
+        assert!(Command::parse("open x", &config, &Builtins::default(), &suffixes).unwrap().path.ends_with("open"));
+        assert!(Command::parse("notes", &config, &Builtins::default(), &suffixes).is_none());
+        assert!(Command::parse("bookmark", &config, &Builtins::default(), &suffixes).is_none());
+        assert!(Command::parse("open", &config, &Builtins::default(), &["lua"]).is_none());
+        let names: Vec<String> = Command::list_available(&config, &Builtins::default(), &suffixes).iter()
+            .filter(|command| command.builtin.is_none())
+            .map(|command| command.name())
+            .collect();
+        assert_eq!(arguments(&["bookmark save", "open"]), names);
+    }
+
+    fn arguments(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| String::from(*item)).collect()
+    }
+
+    #[test]
+    fn detect_placeholders() {
+        assert!(has_placeholders("go $1"));
+        assert!(has_placeholders("search $@"));
+        assert!(has_placeholders("search ${2:-x}"));
+        assert!(!has_placeholders("go example.com"));
+        assert!(!has_placeholders("echo $ home"));
+    }
+
+    #[test]
+    fn expand_positional_placeholders() {
+        let args = arguments(&["a", "b c"]);
+        assert_eq!("go a/b\\ c", &expand_placeholders("go $1/$2", &args));
+        assert_eq!("go ", &expand_placeholders("go $3", &args));
+        assert_eq!("go d", &expand_placeholders("go ${3:-d}", &args));
+        assert_eq!("go b\\ c", &expand_placeholders("go ${2@}", &args));
+        assert_eq!("cost $1", &expand_placeholders("cost $$1", &args));
+    }
+
+    #[test]
+    fn expand_placeholders_in_quotes() {
+        let args = arguments(&["it's", "\"x\""]);
+        let expanded = expand_placeholders("find '$1' \"$2\"", &args);
+        assert_eq!(arguments(&["find", "it's", "\"x\""]), tokenize(&expanded).unwrap());
+    }
+
+    #[test]
+    fn expand_placeholders_keep_arguments_separate() {
+        let args = arguments(&["a b", "c;d", "--"]);
+        let expanded = expand_placeholders("run $@", &args);
+        assert_eq!(arguments(&["run", "a b", "c;d", "--"]), tokenize(&expanded).unwrap());
+        assert_eq!(1, split_sequence(&expanded).unwrap().len());
+    }
+
+    #[test]
+    fn expand_placeholders_after_terminator() {
+        let args = arguments(&["a b"]);
+        assert_eq!("run -- a b", &expand_placeholders("run -- $1", &args));
+    }
+
+    #[test]
+    fn escape_separators_after_terminator() {
+        let args = arguments(&["a;reload", "b&&c", "d\\;e"]);
+        let expanded = expand_placeholders("search -- $@", &args);
+        let sequence = split_sequence(&expanded).unwrap();
+        assert_eq!(1, sequence.len());
+        assert_eq!(arguments(&["search", "a;reload", "b&&c", "d\\;e"]), tokenize(&sequence[0].1).unwrap());
+        let expanded = expand_placeholders("search -- $1; reload", &args);
+        assert_eq!(2, split_sequence(&expanded).unwrap().len());
+    }
+
+    const SEQUENCE_SCRIPTS: &[(&str, &str)] = &[
+        ("visit.lua", r#"
+            function run()
+                load_uri(0, 0, arguments[1])
+                return true
+            end
+        "#),
+        ("refuse.lua", r#"
+            function run()
+                return false
+            end
+        "#),
+    ];
+
+    #[test]
+    fn execute_sequence() {
+        let ui = create_ui("sequence-all", "", SEQUENCE_SCRIPTS);
+        let output = ui.execute_command(Some(0), "visit a.com; refuse; visit 'b;c.com'");
+        assert!(output.is_success());
+        assert_eq!(vec![String::from("a.com"), String::from("b;c.com")],
+                   *ui.loaded_uris.borrow());
+    }
+
+    #[test]
+    fn execute_conditional_sequence() {
+        let ui = create_ui("sequence-conditional", "", SEQUENCE_SCRIPTS);
+        let output = ui.execute_command(Some(0), "refuse && visit a.com || visit b.com");
+        assert!(output.is_success());
+        let output = ui.execute_command(Some(0), "visit c.com && refuse && visit d.com");
+        assert!(!output.is_success());
+        let output = ui.execute_command(Some(0), "missing && visit e.com");
+        assert_eq!(Some(CommandError::CommandNotFound), output.error);
+        assert_eq!(vec![String::from("b.com"), String::from("c.com")],
+                   *ui.loaded_uris.borrow());
+    }
+
+    #[test]
+    fn execute_sequence_alias() {
+        let ui = create_ui("sequence-alias", r#"
+            [commands.aliases]
+            both = "visit a.com; visit"
+            v = "visit"
+        "#, SEQUENCE_SCRIPTS);
+        let output = ui.execute_command(Some(0), "both b.com");
+        assert!(output.is_success());
+        ui.execute_command(Some(0), "v c.com");
+        assert_eq!(vec![String::from("a.com"), String::from("b.com"), String::from("c.com")],
+                   *ui.loaded_uris.borrow());
+    }
+
+    #[test]
+    fn execute_alias_with_separator_arguments() {
+        let ui = create_ui("sequence-alias-arguments", r#"
+            [commands.aliases]
+            raw = "visit -- $@"
+        "#, SEQUENCE_SCRIPTS);
+        assert!(ui.execute_command(Some(0), "raw 'a.com;visit' b.com").is_success());
+        assert!(ui.execute_command(Some(0), "raw 'c.com&&visit' d.com").is_success());
+        assert_eq!(vec![String::from("a.com;visit"), String::from("c.com&&visit")],
+                   *ui.loaded_uris.borrow());
+    }
+
+    #[test]
+    fn execute_alias_placeholders() {
+        let ui = create_ui("alias-placeholders", r#"
+            [commands.aliases]
+            gh = "go https://github.com/$1"
+            wp = "go 'https://en.wikipedia.org/wiki/${1:-Main Page}'"
+            both = "go $2; go ${1@}"
+        "#, &[]);
+        assert!(ui.execute_command(Some(0), "gh kattrali extra").is_success());
+        assert!(ui.execute_command(Some(0), "wp").is_success());
+        assert!(ui.execute_command(Some(0), "wp 'Lua (language)'").is_success());
+        assert!(ui.execute_command(Some(0), "both a b").is_success());
+        assert!(!ui.execute_command(Some(0), "both").is_success());
+        assert_eq!(vec![
+            "https://github.com/kattrali",
+            "https://en.wikipedia.org/wiki/Main Page",
+            "https://en.wikipedia.org/wiki/Lua (language)",
+            "b",
+            "a",
+        ], *ui.loaded_uris.borrow());
     }
 }
@@ -0,0 +1,157 @@
+//! Fuzzy matching and ranking of command names
+use std::collections::HashMap;
+
+/// Number of recent history items considered when boosting frequently and
+/// recently used commands
+pub const USAGE_WINDOW: usize = 100;
+
+/// Maximum score added to a candidate based on usage
+const MAX_USAGE_BOOST: i64 = 48;
+/// Score for each query character matched
+const MATCH_SCORE: i64 = 16;
+/// Bonus for a match directly following the previous match
+const CONSECUTIVE_BONUS: i64 = 12;
+/// Bonus for a match at the start of the candidate or of a word within it
+const WORD_START_BONUS: i64 = 10;
+/// Bonus for a candidate beginning with the query
+const PREFIX_BONUS: i64 = 24;
+/// Penalty for each candidate character skipped between matches
+const GAP_PENALTY: i64 = 1;
+
+/// Score how well a query matches a candidate, or `None` if the characters of
+/// the query do not appear in order within the candidate. Matching ignores
+/// case, and higher scores indicate a closer match.
+///
+/// ## Examples
+///
+/// ```
+/// use webkitten::completion::fuzzy_score;
+///
+/// assert!(fuzzy_score("bn", "buffernew").is_some());
+/// assert!(fuzzy_score("nb", "buffernew").is_none());
+/// assert!(fuzzy_score("buf", "buffernew") > fuzzy_score("bfn", "buffernew"));
+/// ```
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let query: Vec<char> = query.to_lowercase().chars().collect();
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    if query.is_empty() {
+        return Some(0);
+    }
+    let mut score = 0;
+    let mut query_index = 0;
+    let mut last_match: Option<usize> = None;
+    for (index, c) in candidate.iter().enumerate() {
+        if query_index == query.len() {
+            break;
+        }
+        if *c != query[query_index] {
+            continue;
+        }
+        score += MATCH_SCORE;
+        if index == 0 || is_word_separator(candidate[index - 1]) {
+            score += WORD_START_BONUS;
+        }
+        match last_match {
+            Some(last) if last + 1 == index => score += CONSECUTIVE_BONUS,
+            Some(last) => score -= GAP_PENALTY * (index - last - 1) as i64,
+            None => score -= GAP_PENALTY * index as i64,
+        }
+        last_match = Some(index);
+        query_index += 1;
+    }
+    if query_index < query.len() {
+        return None;
+    }
+    if candidate.starts_with(&query) {
+        score += PREFIX_BONUS;
+    }
+    Some(score)
+}
+
+/// Score how frequently and recently each command name was used, given
+/// command text ordered from most recent. Each use is weighted by its
+/// position within `USAGE_WINDOW`.
+pub fn usage_scores(recent: &[String]) -> HashMap<String, i64> {
+    let mut scores: HashMap<String, i64> = HashMap::new();
+    for (index, text) in recent.iter().take(USAGE_WINDOW).enumerate() {
+        if let Some(name) = text.split_whitespace().next() {
+            let weight = (USAGE_WINDOW - index) as i64;
+            *scores.entry(String::from(name)).or_insert(0) += weight;
+        }
+    }
+    scores
+}
+
+/// Filter candidates to those matching a query, ordered by match score
+/// boosted by usage. Candidates are de-duplicated, keeping the first
+/// occurrence, and ties keep the original order of candidates.
+pub fn rank(query: &str, candidates: Vec<String>, usage: &HashMap<String, i64>) -> Vec<String> {
+    let mut scored: Vec<(i64, usize, String)> = vec![];
+    for (index, candidate) in candidates.into_iter().enumerate() {
+        if scored.iter().any(|(_, _, name)| *name == candidate) {
+            continue;
+        }
+        if let Some(score) = fuzzy_score(query, &candidate) {
+            let boost = usage.get(&candidate)
+                .map(|usage| (usage / 10).min(MAX_USAGE_BOOST))
+                .unwrap_or(0);
+            scored.push((score + boost, index, candidate));
+        }
+    }
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored.into_iter().map(|(_, _, name)| name).collect()
+}
+
+fn is_word_separator(c: char) -> bool {
+    c == '-' || c == '_' || c == '.' || c.is_whitespace()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    fn names(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| String::from(*item)).collect()
+    }
+
+    #[test]
+    fn match_subsequence() {
+        assert!(fuzzy_score("tgb", "toggle-bar").is_some());
+        assert!(fuzzy_score("TB", "toggle-bar").is_some());
+        assert!(fuzzy_score("bart", "toggle-bar").is_none());
+        assert_eq!(Some(0), fuzzy_score("", "go"));
+    }
+
+    #[test]
+    fn prefer_word_starts() {
+        assert!(fuzzy_score("fb", "focus-bar") > fuzzy_score("fb", "buffernew-fab"));
+    }
+
+    #[test]
+    fn rank_by_score() {
+        let ranked = rank("new", names(&["windownew", "bufferprev", "buffernew", "new-tab"]),
+                          &HashMap::new());
+        assert_eq!(names(&["new-tab", "buffernew", "windownew"]), ranked);
+    }
+
+    #[test]
+    fn rank_removes_duplicates() {
+        let ranked = rank("go", names(&["go", "go", "google"]), &HashMap::new());
+        assert_eq!(names(&["go", "google"]), ranked);
+    }
+
+    #[test]
+    fn rank_boosts_usage() {
+        let usage = usage_scores(&names(&["buffernew", "buffernew example.com", "go x"]));
+        let ranked = rank("bn", names(&["buffernext", "buffernew"]), &usage);
+        assert_eq!(names(&["buffernew", "buffernext"]), ranked);
+    }
+
+    #[test]
+    fn usage_weighted_by_recency() {
+        let usage = usage_scores(&names(&["go a", "reload", "go b"]));
+        assert_eq!(Some(&((USAGE_WINDOW + USAGE_WINDOW - 2) as i64)), usage.get("go"));
+        assert_eq!(Some(&((USAGE_WINDOW - 1) as i64)), usage.get("reload"));
+    }
+}
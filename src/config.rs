@@ -1,9 +1,13 @@
 //! Configuration manipulation and handling for common browser options
-use std::fs::File;
-use std::io::Read;
+use std::error;
+use std::fmt;
+use std::fs::{File,canonicalize,rename};
+use std::io::{self,Read,Write};
 use std::collections::HashMap;
+use std::path::{Path,PathBuf};
+use std::sync::{RwLock,RwLockReadGuard,RwLockWriteGuard};
 
-use toml::Value;
+use toml::{self,Value};
 
 use ui::BrowserConfiguration;
 
@@ -17,6 +21,9 @@ start-page = "https://delisa.fuller.li"
 
 [new-frame]
 opens-in-focused-window = false
+
+[commands.sandbox]
+user-content = "full"
 "#;
 
 /// Placeholder used in webkitten configuration to represent the configuration
@@ -25,6 +32,14 @@ const CONFIG_DIR: &'static str = "CONFIG_DIR";
 
 const HOME: &'static str = "HOME";
 
+/// Extension replacing that of the configuration file to find the file of
+/// options saved while running
+const OVERRIDES_EXTENSION: &str = "overrides.toml";
+
+/// Tables of options which cannot change what command scripts may access,
+/// other than `general.config-dir`, which changes the paths they use
+const UNRESTRICTED_TABLES: [&str; 3] = ["general", "sites", "window"];
+
 /// Configuration option storage and lookup
 ///
 /// ## Examples
@@ -105,15 +120,73 @@ const HOME: &'static str = "HOME";
 /// let path = config.lookup_site_str("http://example.co.uk/old", "dependencies.external-path");
 /// assert_eq!("/path/to/bin", &path.unwrap());
 /// ```
+///
+/// Changing an option while running
+///
+/// ```
+/// use webkitten::config::Config;
+/// use webkitten::ui::BrowserConfiguration;
+///
+/// let config = Config::parse(r#"
+/// [general]
+/// allow-javascript = true
+/// "#).unwrap();
+/// config.set_bool(r#"sites."example.com".general.allow-javascript"#, false).unwrap();
+/// assert_eq!(Some(true), config.lookup_site_bool("http://example.org", "general.allow-javascript"));
+/// assert_eq!(Some(false), config.lookup_site_bool("http://example.com", "general.allow-javascript"));
+/// ```
 pub struct Config {
-    value: Value
+    value: RwLock<Value>
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum ConfigErrorReason {
+    /// The key is empty or has invalid quoting
+    InvalidKey,
+    /// A part of the key is set to a value which is not a table
+    NotATable,
+    /// The configuration file could not be read or parsed
+    InvalidFile,
+    /// The configuration file could not be written
+    WriteFailed,
+}
+
+#[derive(Debug,PartialEq)]
+pub struct ConfigError {
+    message: String,
+    reason: ConfigErrorReason,
+}
+
+impl error::Error for ConfigError {
+
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ConfigError {
+
+    fn new(reason: ConfigErrorReason, message: String) -> ConfigError {
+        ConfigError { reason, message }
+    }
+
+    /// The cause of the failure to change the configuration
+    pub fn reason(&self) -> ConfigErrorReason {
+        self.reason
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
 }
 
 impl BrowserConfiguration for Config {
 
     fn parse(raw_input: &str) -> Option<Self> {
         match raw_input.parse() {
-            Ok(value) => Some(Config { value: value }),
+            Ok(value) => Some(Config { value: RwLock::new(value) }),
             Err(errors) => {
                 for err in errors { error!("Failed to parse toml: {}", err); }
                 None
@@ -122,14 +195,11 @@ impl BrowserConfiguration for Config {
     }
 
     fn lookup_bool<'a>(&'a self, key: &'a str) -> Option<bool> {
-        self.lookup(key)
-            .and_then(|value| value.as_bool())
+        self.lookup(key, |value| value.as_bool())
     }
 
     fn lookup_raw_str<'a>(&'a self, key: &'a str) -> Option<String> {
-        self.lookup(key)
-            .and_then(|value| value.as_str())
-            .and_then(|value| Some(String::from(value)))
+        self.lookup(key, |value| value.as_str().map(String::from))
     }
 
     fn lookup_str<'a>(&'a self, key: &'a str) -> Option<String> {
@@ -138,35 +208,28 @@ impl BrowserConfiguration for Config {
     }
 
     fn lookup_integer<'a>(&'a self, key: &'a str) -> Option<i64> {
-        self.lookup(key)
-            .and_then(|value| value.as_integer())
+        self.lookup(key, |value| value.as_integer())
     }
 
     fn lookup_str_table(&self, key: &str) -> Option<HashMap<String, String>> {
-        if let Some(table) = self.lookup(key).and_then(|value| value.as_table()) {
+        self.lookup(key, |value| value.as_table().map(|table| {
             let mut map: HashMap<String, String> = HashMap::new();
             for (key, raw_value) in table {
                 if let Some(value) = raw_value.as_str() {
                     map.insert(key.to_owned(), value.to_owned());
                 }
             }
-            return Some(map);
-        }
-        None
+            map
+        }))
     }
 
     fn lookup_str_vec(&self, key: &str) -> Option<Vec<String>> {
-        self.lookup(key)
-            .and_then(|value| value.as_slice())
-            .and_then(|values| {
-                let mut str_values: Vec<String> = vec![];
-                for value in values {
-                    if let Some(value) = value.as_str() {
-                        str_values.push(self.parse_path(value))
-                    }
-                }
-                Some(str_values)
-            })
+        self.lookup(key, |value| value.as_slice().map(|values| {
+            values.iter()
+                .filter_map(|value| value.as_str())
+                .map(String::from)
+                .collect::<Vec<String>>()
+        })).map(|values| values.iter().map(|value| self.parse_path(value)).collect())
     }
 }
 
@@ -179,9 +242,9 @@ impl Config {
 
     /// Reload cached configuration from disk returns true if parsing is
     /// successful
-    pub fn load(&mut self, path: &str) -> bool {
-        if let Some(update) = Config::parse_file(path) {
-            self.value = update.value;
+    pub fn load(&self, path: &str) -> bool {
+        if let Some(update) = Config::open(path) {
+            *self.write_value() = update.into_value();
             true
         } else {
             false
@@ -196,9 +259,146 @@ impl Config {
             .and_then(|_| Config::parse(buffer.as_str()))
     }
 
-    /// Look up the raw TOML value for a key
-    fn lookup<'a>(&'a self, key: &'a str) -> Option<&Value> {
-        self.value.lookup(&key.clone())
+    /// Parse the configuration file at a path, applying any options saved to
+    /// its overrides file while running
+    pub fn open(path: &str) -> Option<Self> {
+        let config = Config::parse_file(path)?;
+        let overrides_path = Config::overrides_path(path);
+        if Path::new(&overrides_path).exists() {
+            match Config::parse_file(&overrides_path) {
+                Some(overrides) => merge_value(&mut config.write_value(), overrides.into_value()),
+                None => warn!("Ignoring unreadable configuration overrides: {}", overrides_path),
+            }
+        }
+        Some(config)
+    }
+
+    /// The path of the file holding options saved while running for the
+    /// configuration file at a path, such as `config.overrides.toml` for
+    /// `config.toml`
+    pub fn overrides_path(path: &str) -> String {
+        Path::new(path).with_extension(OVERRIDES_EXTENSION).to_string_lossy().into_owned()
+    }
+
+    /// Set a boolean option, replacing any existing value of the key
+    pub fn set_bool(&self, key: &str, value: bool) -> Result<(), ConfigError> {
+        self.set_value(key, Value::Boolean(value))
+    }
+
+    /// Set a string option, replacing any existing value of the key
+    pub fn set_str(&self, key: &str, value: &str) -> Result<(), ConfigError> {
+        self.set_value(key, Value::String(String::from(value)))
+    }
+
+    /// Set a string array option, replacing any existing value of the key
+    pub fn set_str_vec(&self, key: &str, values: &[String]) -> Result<(), ConfigError> {
+        let values = values.iter().map(|value| Value::String(value.clone())).collect();
+        self.set_value(key, Value::Array(values))
+    }
+
+    /// Apply a change to the overrides file of the configuration file at a
+    /// path, creating it if needed. The configuration file itself is left
+    /// unchanged, keeping its comments and formatting.
+    pub fn update_overrides<F>(path: &str, change: F) -> Result<(), ConfigError>
+            where F: FnOnce(&Config) -> Result<(), ConfigError> {
+        let overrides_path = Config::overrides_path(path);
+        let overrides = if Path::new(&overrides_path).exists() {
+            Config::parse_file(&overrides_path).ok_or_else(|| {
+                ConfigError::new(ConfigErrorReason::InvalidFile,
+                                 format!("Failed to parse configuration overrides: {}", overrides_path))
+            })?
+        } else {
+            Config { value: RwLock::new(Value::Table(toml::Table::new())) }
+        };
+        change(&overrides)?;
+        overrides.write_file(&overrides_path).map_err(|err| {
+            ConfigError::new(ConfigErrorReason::WriteFailed,
+                             format!("Failed to write configuration overrides {}: {}", overrides_path, err))
+        })
+    }
+
+    /// Whether a key is or contains an option which could change what command
+    /// scripts may access. Only options within `general`, `sites`, and
+    /// `window` are excluded, apart from `general.config-dir`.
+    pub fn controls_command_access(key: &str) -> bool {
+        let parts = match toml::Parser::new(key).lookup() {
+            Some(parts) => parts,
+            None => return true,
+        };
+        match (parts.first().map(|part| part.as_str()), parts.get(1).map(|part| part.as_str())) {
+            (Some("general"), None) => true,
+            (Some("general"), Some(option)) => option == "config-dir",
+            (Some(table), _) => !UNRESTRICTED_TABLES.contains(&table),
+            (None, _) => true,
+        }
+    }
+
+    /// Write options to a temporary file which then replaces the file at a
+    /// path, following symbolic links so that a linked file is updated
+    /// rather than replaced
+    fn write_file(&self, path: &str) -> io::Result<()> {
+        let path = canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        let temp_path = path.with_extension("toml.tmp");
+        {
+            let mut file = File::create(&temp_path)?;
+            file.write_all(self.read_value().to_string().as_bytes())?;
+            file.sync_all()?;
+        }
+        rename(&temp_path, &path)
+    }
+
+    /// Set the value of a key, creating any missing tables in the key
+    fn set_value(&self, key: &str, value: Value) -> Result<(), ConfigError> {
+        let invalid_key = || {
+            ConfigError::new(ConfigErrorReason::InvalidKey,
+                             format!("Invalid configuration key: {}", key))
+        };
+        let mut parts = toml::Parser::new(key).lookup().ok_or_else(&invalid_key)?;
+        let name = parts.pop().ok_or_else(&invalid_key)?;
+        let mut root = self.write_value();
+        let mut table = match *root {
+            Value::Table(ref mut table) => table,
+            _ => unreachable!(),
+        };
+        for part in parts {
+            let current = table;
+            let entry = current.entry(part.clone())
+                .or_insert_with(|| Value::Table(toml::Table::new()));
+            table = match *entry {
+                Value::Table(ref mut table) => table,
+                _ => return Err(ConfigError::new(ConfigErrorReason::NotATable,
+                    format!("Cannot set {}: {} is not a table", key, part))),
+            };
+        }
+        table.insert(name, value);
+        Ok(())
+    }
+
+    /// Look up the raw TOML value for a key, converting it using `f`
+    fn lookup<F, T>(&self, key: &str, f: F) -> Option<T>
+            where F: FnOnce(&Value) -> Option<T> {
+        self.read_value().lookup(key).and_then(f)
+    }
+
+    fn read_value(&self) -> RwLockReadGuard<'_, Value> {
+        match self.value.read() {
+            Ok(value) => value,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn write_value(&self) -> RwLockWriteGuard<'_, Value> {
+        match self.value.write() {
+            Ok(value) => value,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self.value.into_inner() {
+            Ok(value) => value,
+            Err(poisoned) => poisoned.into_inner(),
+        }
     }
 
     fn parse_path(&self, value: &str) -> String {
@@ -221,11 +421,34 @@ impl Config {
     }
 }
 
+/// Merge options into a value, replacing existing options except for tables,
+/// which are merged in turn
+fn merge_value(value: &mut Value, options: Value) {
+    match (value, options) {
+        (&mut Value::Table(ref mut table), Value::Table(options)) => {
+            for (key, option) in options {
+                match table.get_mut(&key) {
+                    Some(existing) => merge_value(existing, option),
+                    None => { table.insert(key, option); },
+                }
+            }
+        },
+        (value, option) => *value = option,
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
-    use super::Config;
-    use ui::{BrowserConfiguration,BufferEvent};
+    use std::fs::File;
+    use std::io::Read;
+    use std::time::Duration;
+
+    use super::{Config,ConfigErrorReason};
+    use history::{DEFAULT_HISTORY_SIZE,HistoryStrategy};
+    use script::SandboxProfile;
+    use tests::{TestDir,create_ui};
+    use ui::{ApplicationUI,BrowserConfiguration,BufferEvent};
 
     #[test]
     fn lookup_fail_uri_commands() {
@@ -276,4 +499,222 @@ mod tests {
         assert_eq!(1, commands.len());
         assert_eq!(String::from("frut"), commands[0]);
     }
+
+    #[test]
+    fn lookup_cancel_keybinding() {
+        let config = Config::parse(r#"
+        [commands.aliases]
+        stop = "cancel"
+        [commands.keybindings]
+        "go example.com" = "cmd e"
+        stop = "ctrl g"
+        "#).unwrap();
+        assert_eq!(Some(('g', 1 << 18)), config.cancel_keybinding());
+        assert_eq!(None, Config::parse("").unwrap().cancel_keybinding());
+    }
+
+    #[test]
+    fn lookup_command_time_limit() {
+        assert_eq!(None, Config::parse("").unwrap().command_time_limit());
+        let config = Config::parse("[commands]\ntime-limit = 0").unwrap();
+        assert_eq!(None, config.command_time_limit());
+        let config = Config::parse("[commands]\ntime-limit = 200").unwrap();
+        assert_eq!(Some(Duration::from_millis(200)), config.command_time_limit());
+    }
+
+    #[test]
+    fn default_sandbox_profiles() {
+        let config = Config::default().unwrap();
+        assert_eq!(Some(SandboxProfile::Full), config.command_sandbox("user-content"));
+        assert_eq!(None, config.command_sandbox("enforce-https"));
+    }
+
+    #[test]
+    fn lookup_command_history_strategy() {
+        assert_eq!(HistoryStrategy::SaveLast(DEFAULT_HISTORY_SIZE),
+                   Config::parse("").unwrap().command_history_strategy());
+        let config = Config::parse("[commands.history]\nsize = 20").unwrap();
+        assert_eq!(HistoryStrategy::SaveLast(20), config.command_history_strategy());
+        let config = Config::parse("[commands.history]\nsize = -1").unwrap();
+        assert_eq!(HistoryStrategy::SaveLast(DEFAULT_HISTORY_SIZE),
+                   config.command_history_strategy());
+    }
+
+    #[test]
+    fn lookup_command_file_extensions() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(vec!["lua"], config.command_file_extensions(vec!["lua", ""]));
+        let config = Config::parse(r#"
+        [commands]
+        executables = true
+        "#).unwrap();
+        assert_eq!(vec!["lua", ""], config.command_file_extensions(vec!["lua", ""]));
+    }
+
+    #[test]
+    fn set_values_creating_tables() {
+        let config = Config::parse(r#"
+        [general]
+        private-browsing = false
+        "#).unwrap();
+        config.set_bool("general.private-browsing", true).unwrap();
+        config.set_str(r#"sites."example.com".window.start-page"#, "about:blank").unwrap();
+        config.set_str_vec("commands.on-load-uri", &[String::from("bob")]).unwrap();
+        assert_eq!(Some(true), config.lookup_bool("general.private-browsing"));
+        assert_eq!(Some(String::from("about:blank")),
+                   config.lookup_site_str("example.com/page.html", "window.start-page"));
+        assert_eq!(Some(vec![String::from("bob")]), config.lookup_str_vec("commands.on-load-uri"));
+    }
+
+    #[test]
+    fn set_value_within_other_value() {
+        let config = Config::parse(r#"
+        [general]
+        private-browsing = false
+        "#).unwrap();
+        let err = config.set_bool("general.private-browsing.enabled", true).unwrap_err();
+        assert_eq!(ConfigErrorReason::NotATable, err.reason());
+        let err = config.set_bool("", true).unwrap_err();
+        assert_eq!(ConfigErrorReason::InvalidKey, err.reason());
+    }
+
+    #[test]
+    fn update_overrides() {
+        let dir = TestDir::new("config-update");
+        let contents = r#"
+        # Opened for new windows
+        [window]
+        start-page = "about:blank"
+        [general]
+        allow-javascript = true
+        "#;
+        let path = dir.write("config.toml", contents);
+        let path = path.to_str().unwrap();
+        Config::update_overrides(path, |config| config.set_bool("general.private-browsing", true)).unwrap();
+        Config::update_overrides(path, |config| config.set_bool("general.allow-javascript", false)).unwrap();
+        let mut saved = String::new();
+        File::open(path).unwrap().read_to_string(&mut saved).unwrap();
+        assert_eq!(contents, saved);
+        let config = Config::open(path).unwrap();
+        assert_eq!(Some(true), config.lookup_bool("general.private-browsing"));
+        assert_eq!(Some(false), config.lookup_bool("general.allow-javascript"));
+        assert_eq!(Some(String::from("about:blank")), config.start_page());
+    }
+
+    #[test]
+    fn detect_command_access_keys() {
+        assert!(Config::controls_command_access(r#"commands.sandbox."toggle""#));
+        assert!(Config::controls_command_access(r#""commands"."search-paths""#));
+        assert!(Config::controls_command_access("commands"));
+        assert!(Config::controls_command_access("general.config-dir"));
+        assert!(Config::controls_command_access("commands.aliases.g"));
+        assert!(Config::controls_command_access(r#"commands.on-text-change."/""#));
+        assert!(Config::controls_command_access("commands.disabled"));
+        assert!(Config::controls_command_access("unknown.option"));
+        assert!(!Config::controls_command_access("general.private-browsing"));
+        assert!(!Config::controls_command_access("window.width"));
+        assert!(!Config::controls_command_access(r#"sites."example.com".commands.sandbox"#));
+    }
+
+    #[test]
+    fn execute_with_config_changes() {
+        let ui = create_ui("config-changes", r#"
+            [commands.sandbox]
+            pure-toggle = "pure"
+        "#, &[("toggle.lua", r#"
+            function run()
+                local key = 'sites."example.com".general.allow-javascript'
+                local ok, message = set_bool(key, arguments[1] == "on", arguments[2] == "save")
+                set_strings("general.blocked", {"a\31b", "c"})
+                set_string("window.start-page", "about:blank")
+                set_command_field_text(1, tostring(ok) .. ":" .. message)
+                return ok
+            end
+        "#), ("nested-save.lua", r#"
+            function run()
+                return set_bool("window.start-page.nested", true, true)
+            end
+        "#), ("pure-toggle.lua", r#"
+            function run()
+                local ok, message = set_bool("general.private-browsing", true, true)
+                set_command_field_text(1, message)
+                return not ok
+            end
+        "#)]);
+        assert!(ui.execute_command(Some(0), "toggle on").is_success());
+        assert_eq!(Some(true), ui.engine.config.lookup_site_bool("http://example.com",
+                                                                 "general.allow-javascript"));
+        assert_eq!(Some(vec![String::from("a\u{1f}b"), String::from("c")]),
+                   ui.engine.config.lookup_str_vec("general.blocked"));
+        assert_eq!(Some(String::from("about:blank")), ui.engine.config.start_page());
+        assert!(ui.execute_command(Some(0), "toggle off save").is_success());
+        let config_path = ui.file_path("config.toml");
+        let saved = Config::open(config_path.to_str().unwrap()).unwrap();
+        assert_eq!(Some(false), saved.lookup_site_bool("http://example.com",
+                                                       "general.allow-javascript"));
+        assert_eq!(None, saved.start_page());
+        assert!(saved.lookup_str_vec("commands.search-paths").is_some());
+        assert!(!ui.execute_command(Some(0), "nested-save").is_success());
+        let saved = Config::open(config_path.to_str().unwrap()).unwrap();
+        assert_eq!(None, saved.lookup_bool("window.start-page.nested"));
+        assert!(ui.execute_command(Some(0), "pure-toggle").is_success());
+        assert!(ui.command_field_text(1).contains("full sandbox profile"));
+        assert_eq!(None, ui.engine.config.lookup_bool("general.private-browsing"));
+    }
+
+    #[test]
+    fn block_command_access_changes() {
+        let ui = create_ui("config-escalation", r#"
+            [commands.sandbox]
+            escalate = "pure"
+        "#, &[("escalate.lua", r#"
+            function run()
+                local ok, message = set_string('commands.sandbox."escalate"', "full")
+                local paths_ok = set_strings("commands.search-paths", {"/tmp"})
+                local table_ok = set_bool("commands", false)
+                local alias_ok = set_string("commands.aliases.go", "escalate")
+                set_command_field_text(1, message)
+                return not (ok or paths_ok or table_ok or alias_ok)
+            end
+        "#)]);
+        assert!(ui.execute_command(Some(0), "escalate").is_success());
+        assert!(ui.command_field_text(1).contains("full sandbox profile"));
+        assert_eq!(Some(SandboxProfile::Pure), ui.engine.config.command_sandbox("escalate"));
+        assert!(ui.engine.config.command_search_paths().iter().all(|path| path != "/tmp"));
+        assert_eq!(None, ui.engine.config.lookup_str("commands.aliases.go"));
+    }
+
+    #[test]
+    fn execute_with_config_lookups() {
+        let ui = create_ui("config-lookups", r#"
+            retries = 3
+            [commands.labels]
+            home = "example.com"
+            [sites."example.com".commands]
+            retries = 5
+            allowed = true
+            labels = ["a", "b"]
+        "#, &[("report.lua", r#"
+            function run()
+                local labels = lookup_table("commands.labels")
+                local site = "http://example.com/page"
+                set_command_field_text(1, table.concat({
+                    lookup_integer("commands.retries"),
+                    tostring(lookup_integer("commands.missing")),
+                    labels.home,
+                    lookup_string(config_file_path, "commands.changed"),
+                    lookup_string("commands.changed"),
+                    tostring(lookup_bool("commands.allowed")),
+                    tostring(lookup_site_bool(site, "commands.allowed")),
+                    table.concat(lookup_site_strings(site, "commands.labels"), "+"),
+                    lookup_site_string(site, "commands.changed"),
+                }, " "))
+                return true
+            end
+        "#)]);
+        ui.engine.config.set_str("commands.changed", "yes").unwrap();
+        assert!(ui.execute_command(Some(0), "report").is_success());
+        assert_eq!(String::from("3 nil example.com yes yes false true a+b yes"),
+                   ui.command_field_text(1));
+    }
 }
@@ -0,0 +1,236 @@
+//! Tracking of command text as it expands into other commands, guarding
+//! against aliases, default commands, prefix commands, and scripts which
+//! expand into themselves
+use std::error;
+use std::fmt;
+
+/// Maximum number of expansions within a single command
+pub const MAX_EXPANSION_DEPTH: usize = 24;
+
+/// The cause of command text expanding into other command text
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ExpansionKind {
+    /// Command text run from the command bar, a key binding, or a script
+    Command,
+    /// An alias in `commands.aliases`
+    Alias,
+    /// The command in `commands.default`, run when no command matches
+    Default,
+    /// A command in `commands.on-text-change`, run based on the first
+    /// character of the command text
+    Prefix,
+}
+
+/// A step in a chain of expansions
+#[derive(Debug,Clone,PartialEq)]
+pub struct Expansion {
+    pub kind: ExpansionKind,
+    /// Identifies the expansion when checking for cycles, such as an alias
+    /// name or command text
+    pub key: String,
+    /// The command text resulting from the expansion
+    pub text: String,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ExpansionErrorReason {
+    /// An expansion was repeated within the chain
+    Cycle,
+    /// The chain exceeded `MAX_EXPANSION_DEPTH`
+    DepthLimit,
+}
+
+#[derive(Debug,PartialEq)]
+pub struct ExpansionError {
+    message: String,
+    reason: ExpansionErrorReason,
+}
+
+impl error::Error for ExpansionError {
+
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ExpansionError {
+
+    fn new(reason: ExpansionErrorReason, chain: &[Expansion]) -> ExpansionError {
+        let description = match reason {
+            ExpansionErrorReason::Cycle => "Command expands into itself",
+            ExpansionErrorReason::DepthLimit => "Command expands too many times",
+        };
+        let chain: Vec<&str> = chain.iter().map(|expansion| expansion.text.as_str()).collect();
+        ExpansionError {
+            message: format!("{}: {}", description, chain.join(" -> ")),
+            reason,
+        }
+    }
+
+    /// The cause of the failure to expand
+    pub fn reason(&self) -> ExpansionErrorReason {
+        self.reason
+    }
+}
+
+impl fmt::Display for ExpansionError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The chain of expansions leading to the command currently running
+///
+/// ## Examples
+///
+/// ```
+/// use webkitten::expansion::{ExpansionContext,ExpansionKind,ExpansionErrorReason};
+///
+/// let mut context = ExpansionContext::new();
+/// context.enter(ExpansionKind::Command, "home", "home").unwrap();
+/// context.enter(ExpansionKind::Alias, "home", "go example.com; home").unwrap();
+/// let err = context.enter(ExpansionKind::Alias, "home", "go example.com; home");
+/// assert_eq!(ExpansionErrorReason::Cycle, err.unwrap_err().reason());
+/// ```
+#[derive(Debug)]
+pub struct ExpansionContext {
+    chain: Vec<Expansion>,
+}
+
+impl Default for ExpansionContext {
+
+    fn default() -> Self {
+        ExpansionContext::new()
+    }
+}
+
+impl ExpansionContext {
+
+    pub fn new() -> Self {
+        ExpansionContext { chain: vec![] }
+    }
+
+    /// Add an expansion to the chain, failing if an expansion of the same
+    /// kind and key is already in the chain or the chain is too long
+    pub fn enter(&mut self, kind: ExpansionKind, key: &str, text: &str) -> Result<(), ExpansionError> {
+        let expansion = Expansion {
+            kind,
+            key: String::from(key),
+            text: String::from(text),
+        };
+        let repeated = self.chain.iter()
+            .any(|item| item.kind == expansion.kind && item.key == expansion.key);
+        let reason = if repeated {
+            Some(ExpansionErrorReason::Cycle)
+        } else if self.chain.len() >= MAX_EXPANSION_DEPTH {
+            Some(ExpansionErrorReason::DepthLimit)
+        } else {
+            None
+        };
+        self.chain.push(expansion);
+        let result = match reason {
+            Some(reason) => Err(ExpansionError::new(reason, &self.chain)),
+            None => return Ok(()),
+        };
+        self.chain.pop();
+        result
+    }
+
+    /// Remove the most recent expansion from the chain
+    pub fn exit(&mut self) {
+        self.chain.pop();
+    }
+
+    /// The number of expansions in the chain
+    pub fn depth(&self) -> usize {
+        self.chain.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tests::create_ui;
+    use ui::{ApplicationUI,CommandError};
+
+    #[test]
+    fn enter_and_exit() {
+        let mut context = ExpansionContext::new();
+        assert!(context.enter(ExpansionKind::Command, "o x", "o x").is_ok());
+        assert!(context.enter(ExpansionKind::Alias, "o", "go x").is_ok());
+        assert_eq!(2, context.depth());
+        context.exit();
+        assert!(context.enter(ExpansionKind::Alias, "o", "go x").is_ok());
+    }
+
+    #[test]
+    fn repeated_keys_of_different_kinds() {
+        let mut context = ExpansionContext::new();
+        assert!(context.enter(ExpansionKind::Command, "o", "o").is_ok());
+        assert!(context.enter(ExpansionKind::Alias, "o", "go").is_ok());
+    }
+
+    #[test]
+    fn report_cycle_chain() {
+        let mut context = ExpansionContext::new();
+        context.enter(ExpansionKind::Command, "a", "a").unwrap();
+        context.enter(ExpansionKind::Alias, "a", "b 1").unwrap();
+        context.enter(ExpansionKind::Alias, "b", "a 1").unwrap();
+        let err = context.enter(ExpansionKind::Alias, "a", "b 1 1").unwrap_err();
+        assert_eq!(ExpansionErrorReason::Cycle, err.reason());
+        assert_eq!("Command expands into itself: a -> b 1 -> a 1 -> b 1 1", &format!("{}", err));
+        assert_eq!(3, context.depth());
+    }
+
+    #[test]
+    fn limit_depth() {
+        let mut context = ExpansionContext::new();
+        for index in 0..MAX_EXPANSION_DEPTH {
+            context.enter(ExpansionKind::Command, &format!("{}", index), "x").unwrap();
+        }
+        let err = context.enter(ExpansionKind::Command, "last", "x").unwrap_err();
+        assert_eq!(ExpansionErrorReason::DepthLimit, err.reason());
+    }
+
+    #[test]
+    fn execute_alias_loop() {
+        let ui = create_ui("alias-loop", r#"
+            [commands.aliases]
+            ping = "pong $@"
+            pong = "ping $@ again"
+        "#, &[]);
+        let output = ui.execute_command(Some(0), "ping x");
+        assert_eq!(Some(CommandError::ExpansionLoop), output.error);
+        let message = output.message.unwrap();
+        assert!(message.contains("ping x -> pong x -> ping x again -> pong x again"));
+        assert_eq!(Some(message), ui.command_errors.borrow().get(&0).cloned());
+        assert!(ui.execute_command(Some(0), "go example.com").is_success());
+    }
+
+    #[test]
+    fn execute_script_loop() {
+        let ui = create_ui("script-loop", "", &[("again.lua", r#"
+            function run()
+                local success, message = run_command(0, "again")
+                set_command_field_text(1, message)
+                return success
+            end
+        "#)]);
+        let output = ui.execute_command(Some(0), "again");
+        assert!(!output.is_success());
+        assert!(ui.command_field_text(1).starts_with("Command expands into itself: again -> again"));
+    }
+
+    #[test]
+    fn execute_default_prefix_loop() {
+        let ui = create_ui("default-loop", r#"
+            default = "find"
+            [commands.on-text-change]
+            "f" = "find"
+        "#, &[]);
+        let output = ui.execute_command(Some(0), "fox");
+        assert_eq!(Some(CommandError::ExpansionLoop), output.error);
+        assert!(output.message.unwrap().ends_with("fox -> find ox -> find ind ox"));
+    }
+}
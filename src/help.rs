@@ -0,0 +1,341 @@
+//! Built-in help describing the commands available in the search paths
+use std::env::temp_dir;
+use std::fs::{File,OpenOptions};
+use std::io::{self,Write};
+use std::path::{Path,PathBuf};
+use std::process;
+use std::time::{SystemTime,UNIX_EPOCH};
+
+use url::Url;
+
+use builtin::{Builtins,NativeCommand,NativeContext};
+use command::Command;
+use config::Config;
+use script::{ScriptCache,ScriptingEngine};
+use ui::{BrowserConfiguration,CommandError,CommandOutput,Completion};
+
+/// Name of the built-in help command
+pub const HELP_COMMAND: &str = "help";
+
+/// File name used for the generated help page
+pub const HELP_FILE_NAME: &str = "help.html";
+
+/// Number of names tried when creating a help page in the temporary directory
+const TEMP_FILE_ATTEMPTS: u32 = 16;
+
+/// Documentation for a single command
+#[derive(Debug,Clone,PartialEq)]
+pub struct CommandHelp {
+    /// The name used to invoke the command
+    pub name: String,
+    /// The summary provided by the command, if any
+    pub description: Option<String>,
+    /// Names in `commands.aliases` which invoke the command
+    pub aliases: Vec<String>,
+    /// Key chords in `commands.keybindings` which invoke the command
+    pub keybindings: Vec<String>,
+}
+
+impl CommandHelp {
+
+    /// A one-line summary of the command suitable for the command bar
+    pub fn summary(&self) -> String {
+        let mut summary = self.name.clone();
+        if let Some(ref description) = self.description {
+            summary.push_str(": ");
+            summary.push_str(description);
+        }
+        if !self.aliases.is_empty() {
+            summary.push_str(&format!(" (aliases: {})", self.aliases.join(", ")));
+        }
+        if !self.keybindings.is_empty() {
+            summary.push_str(&format!(" (keys: {})", self.keybindings.join(", ")));
+        }
+        summary
+    }
+}
+
+/// The built-in help command, which shows a summary of a single command in
+/// the command bar, or opens a page describing all commands if no command
+/// name is specified
+pub struct HelpCommand;
+
+impl NativeCommand for HelpCommand {
+
+    fn name(&self) -> &str {
+        HELP_COMMAND
+    }
+
+    fn describe(&self) -> &str {
+        "Show the description, aliases, and keys of a command, or of all commands"
+    }
+
+    fn run(&self, context: &dyn NativeContext, arguments: &[String]) -> CommandOutput {
+        if !arguments.is_empty() {
+            let name = arguments.join(" ");
+            return match context.command_help(&name) {
+                Some(entry) => {
+                    let mut output = CommandOutput::success();
+                    output.message = Some(entry.summary());
+                    output
+                },
+                None => CommandOutput::error(CommandError::InvalidArguments,
+                                             format!("No help found for '{}'", name)),
+            }
+        }
+        let html = render_html(&context.all_command_help());
+        let uri = match write_page(context.engine().data_dir(), &html) {
+            Ok(path) => match Url::from_file_path(&path) {
+                Ok(uri) => uri.to_string(),
+                Err(_) => return CommandOutput::error(CommandError::ErrorDuringExecution,
+                                                      format!("Invalid help page path: {}", path.display())),
+            },
+            Err(err) => return CommandOutput::error(CommandError::ErrorDuringExecution,
+                                                    format!("Unable to write help page: {}", err)),
+        };
+        match context.window_index() {
+            Some(index) => context.open_webview(index, Some(uri)),
+            None => context.open_window(Some(uri)),
+        }
+        CommandOutput::success()
+    }
+
+    fn complete(&self, context: &dyn NativeContext, arguments: &[String]) -> Vec<Completion> {
+        if arguments.len() < 2 {
+            let partial = arguments.last().map(|arg| arg.as_str()).unwrap_or("");
+            context.command_name_completions(partial)
+        } else {
+            vec![]
+        }
+    }
+}
+
+/// Help for every enabled command in the search paths and built-in command,
+/// sorted by name
+pub fn all_commands<S: ScriptingEngine>(config: &Config, scripts: &ScriptCache, builtins: &Builtins) -> Vec<CommandHelp> {
+    Command::list_available(config, builtins, &config.command_file_extensions(S::file_extensions())).iter()
+        .map(|command| describe::<S>(config, scripts, builtins, command.name(), Some(command)))
+        .collect()
+}
+
+/// Help for a single command, looked up by name or alias. Nested commands are
+/// looked up by their namespace and name, such as `bookmark save`.
+pub fn command<S: ScriptingEngine>(config: &Config, scripts: &ScriptCache, builtins: &Builtins,
+                                   name: &str) -> Option<CommandHelp> {
+    Command::parse(name, config, builtins, &config.command_file_extensions(S::file_extensions()))
+        .map(|command| describe::<S>(config, scripts, builtins, command.name(), Some(&command)))
+}
+
+/// Write a help page to the data directory of the engine, or to a new file in
+/// the temporary directory if there is none. Temporary files are always
+/// created rather than opened, so existing files and links are left alone.
+fn write_page(data_dir: Option<String>, html: &str) -> io::Result<PathBuf> {
+    if let Some(dir) = data_dir {
+        let path = Path::new(&dir).join(HELP_FILE_NAME);
+        File::create(&path)?.write_all(html.as_bytes())?;
+        return Ok(path);
+    }
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or(0);
+    for attempt in 0..TEMP_FILE_ATTEMPTS {
+        let name = format!("webkitten-help-{}-{}-{}.html", process::id(), stamp, attempt);
+        let path = temp_dir().join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(html.as_bytes())?;
+                return Ok(path);
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no unused temporary file name"))
+}
+
+/// Render help for commands as an HTML document
+pub fn render_html(entries: &[CommandHelp]) -> String {
+    let mut rows = String::new();
+    for entry in entries {
+        rows.push_str(&format!(r#"
+      <tr>
+        <td><code>{}</code></td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+      </tr>"#,
+            escape_html(&entry.name),
+            escape_html(entry.description.as_deref().unwrap_or("")),
+            escape_html(&entry.aliases.join(", ")),
+            escape_html(&entry.keybindings.join(", "))));
+    }
+    format!(r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <title>Commands</title>
+    <style>
+      body {{ font-family: sans-serif; margin: 2em; }}
+      table {{ border-collapse: collapse; width: 100%; }}
+      th, td {{ border-bottom: 1px solid #ddd; padding: 0.4em; text-align: left; }}
+    </style>
+  </head>
+  <body>
+    <h1>Commands</h1>
+    <table>
+      <tr><th>Name</th><th>Description</th><th>Aliases</th><th>Keys</th></tr>{}
+    </table>
+  </body>
+</html>
+"#, rows)
+}
+
+fn describe<S: ScriptingEngine>(config: &Config, scripts: &ScriptCache, builtins: &Builtins,
+                                name: String, command: Option<&Command>) -> CommandHelp {
+    let builtin = command.and_then(|command| command.builtin.as_ref())
+        .and_then(|name| builtins.get(name));
+    let description = if let Some(builtin) = builtin {
+        Some(String::from(builtin.describe()))
+    } else {
+        command.and_then(|command| scripts.load(&command.path).ok())
+            .and_then(|script| match S::describe(&script) {
+                Ok(description) => Some(description),
+                Err(err) => {
+                    warn!("Unable to describe {}: {}", name, err);
+                    None
+                }
+            })
+    };
+    CommandHelp {
+        aliases: aliases(config, &name),
+        keybindings: keybindings(config, &name),
+        description,
+        name,
+    }
+}
+
+/// Names of aliases which resolve to a command name
+fn aliases(config: &Config, name: &str) -> Vec<String> {
+    let mut aliases: Vec<String> = config.lookup_str_table("commands.aliases")
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, target)| invokes(target, name))
+        .map(|(alias, _)| alias)
+        .collect();
+    aliases.sort();
+    aliases
+}
+
+/// Key chords bound to command text which resolves to a command name
+fn keybindings(config: &Config, name: &str) -> Vec<String> {
+    let mut chords: Vec<String> = config.lookup_str_table("commands.keybindings")
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(text, _)| {
+            let mut words = text.split_whitespace().map(String::from);
+            words.next()
+                .and_then(|command| config.resolved_command_name(&command))
+                .map(|command| invokes(&format!("{} {}", command, words.collect::<Vec<_>>().join(" ")), name))
+                .unwrap_or(false)
+        })
+        .map(|(_, chord)| chord)
+        .collect();
+    chords.sort();
+    chords
+}
+
+/// Whether command text begins with the words of a command name
+fn invokes(text: &str, name: &str) -> bool {
+    let mut words = text.split_whitespace();
+    name.split_whitespace().all(|part| words.next() == Some(part))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builtin::Builtins;
+    use config::Config;
+    use script::{LuaEngine,ScriptCache};
+    use tests::TestDir;
+    use ui::BrowserConfiguration;
+
+    fn create_config(name: &str, options: &str) -> (TestDir, Config) {
+        let dir = TestDir::new(name);
+        for &(file_name, description) in &[("go.lua", "Open a web page"), ("reload.lua", "Reload")] {
+            dir.write(file_name, &format!("function description() return \"{}\" end", description));
+        }
+        let config = Config::parse(&format!(r#"
+            [commands]
+            search-paths = ["{}"]
+            {}
+        "#, dir.path().display(), options)).unwrap();
+        (dir, config)
+    }
+
+    #[test]
+    fn describe_all_commands() {
+        let (_dir, config) = create_config("help-all", r#"
+            disabled = ["reload"]
+            [commands.aliases]
+            o = "go"
+            r = "reload"
+            [commands.keybindings]
+            "go example.com" = "cmd e"
+            "o example.org" = "cmd shift e"
+        "#);
+        let entries = all_commands::<LuaEngine>(&config, &ScriptCache::new(), &Builtins::default());
+        assert!(entries.iter().all(|entry| entry.name != "reload"));
+        assert!(entries.iter().any(|entry| entry.name == HELP_COMMAND));
+        let entry = entries.iter().find(|entry| entry.name == "go").unwrap();
+        assert_eq!(&CommandHelp {
+            name: String::from("go"),
+            description: Some(String::from("Open a web page")),
+            aliases: vec![String::from("o")],
+            keybindings: vec![String::from("cmd e"), String::from("cmd shift e")],
+        }, entry);
+    }
+
+    #[test]
+    fn describe_command_by_alias() {
+        let (_dir, config) = create_config("help-alias", r#"
+            [commands.aliases]
+            o = "go"
+        "#);
+        let scripts = ScriptCache::new();
+        let builtins = Builtins::default();
+        let entry = command::<LuaEngine>(&config, &scripts, &builtins, "o").unwrap();
+        assert_eq!("go: Open a web page (aliases: o)", &entry.summary());
+        assert!(command::<LuaEngine>(&config, &scripts, &builtins, "missing").is_none());
+        assert!(command::<LuaEngine>(&config, &scripts, &builtins, HELP_COMMAND).is_some());
+    }
+
+    #[test]
+    fn write_page_to_data_dir() {
+        let dir = TestDir::new("help page");
+        let path = write_page(Some(dir.path().to_string_lossy().into_owned()), "<p>help</p>").unwrap();
+        assert_eq!(dir.path().join(HELP_FILE_NAME), path);
+        assert!(Url::from_file_path(&path).unwrap().to_string().contains("help%20page"));
+        let path = write_page(None, "<p>help</p>").unwrap();
+        assert!(path.starts_with(temp_dir()));
+        let other = write_page(None, "<p>help</p>").unwrap();
+        assert_ne!(path, other);
+        let _ = ::std::fs::remove_file(path);
+        let _ = ::std::fs::remove_file(other);
+    }
+
+    #[test]
+    fn render_escaped_html() {
+        let html = render_html(&[CommandHelp {
+            name: String::from("go"),
+            description: Some(String::from("Open <a> page")),
+            aliases: vec![],
+            keybindings: vec![],
+        }]);
+        assert!(html.contains("Open &lt;a&gt; page"));
+    }
+}
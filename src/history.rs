@@ -0,0 +1,341 @@
+//! Command bar history storage and navigation
+use std::fs::{File,rename};
+use std::io::{self,Read,Write};
+use std::time::{Duration,Instant};
+
+/// Default number of items retained when saving the last items of history
+pub const DEFAULT_HISTORY_SIZE: usize = 500;
+
+/// The shortest time between saves when items are added, so that commands
+/// run in quick succession are saved together
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Which items are retained when new items are added to a history
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum HistoryStrategy {
+    /// Retain every item
+    SaveAll,
+    /// Retain no items
+    SaveNone,
+    /// Retain only the most recent items, up to a maximum count
+    SaveLast(usize),
+}
+
+impl HistoryStrategy {
+
+    /// Parse a strategy name (`all`, `none` or `last`), using `size` as the
+    /// item limit for `last`
+    pub fn parse(name: &str, size: Option<usize>) -> Option<Self> {
+        match name {
+            "all" => Some(HistoryStrategy::SaveAll),
+            "none" => Some(HistoryStrategy::SaveNone),
+            "last" => Some(HistoryStrategy::SaveLast(size.unwrap_or(DEFAULT_HISTORY_SIZE))),
+            _ => None,
+        }
+    }
+}
+
+/// A list of textual items, ordered from oldest to most recent and optionally
+/// persisted to a file. Items added within a few seconds of the last save are
+/// saved by the next save, `flush()`, or when the history is dropped.
+///
+/// ## Examples
+///
+/// ```
+/// use webkitten::history::{History,HistoryStrategy};
+///
+/// let mut history = History::new(HistoryStrategy::SaveLast(2), None);
+/// history.push("go example.com");
+/// history.push("find kittens");
+/// history.push("go example.org");
+/// assert_eq!(2, history.len());
+/// assert_eq!(Some("go example.org"), history.item(0));
+/// assert_eq!(Some("find kittens"), history.item(1));
+/// assert_eq!(Some("go example.org"), history.find_prefix("go", 0));
+/// ```
+#[derive(Debug)]
+pub struct History {
+    items: Vec<String>,
+    strategy: HistoryStrategy,
+    path: Option<String>,
+    unsaved: bool,
+    saved_at: Option<Instant>,
+}
+
+impl History {
+
+    /// Create a history, loading any items previously saved to `path`
+    pub fn new(strategy: HistoryStrategy, path: Option<String>) -> Self {
+        let mut history = History { items: vec![], strategy, path, unsaved: false, saved_at: None };
+        history.load();
+        history.apply_strategy();
+        history
+    }
+
+    /// Add an item as the most recent in history, ignoring empty text and
+    /// repetitions of the most recent item
+    pub fn push(&mut self, item: &str) {
+        let item = item.trim().replace("\n", " ");
+        if item.is_empty() || self.items.last() == Some(&item) {
+            return;
+        }
+        self.items.push(item);
+        self.apply_strategy();
+        self.unsaved = true;
+        if self.saved_at.map(|time| time.elapsed() >= SAVE_INTERVAL).unwrap_or(true) {
+            self.save();
+        }
+    }
+
+    /// Save any items added since the last save
+    pub fn flush(&mut self) {
+        if self.unsaved {
+            self.save();
+        }
+    }
+
+    /// Remove and return the most recent item
+    pub fn pop(&mut self) -> Option<String> {
+        let item = self.items.pop();
+        if item.is_some() {
+            self.save();
+        }
+        item
+    }
+
+    /// Remove all items
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.save();
+    }
+
+    /// Change the strategy used to retain items, discarding any items which
+    /// no longer fit
+    pub fn set_strategy(&mut self, strategy: HistoryStrategy) {
+        self.strategy = strategy;
+        if self.apply_strategy() {
+            self.save();
+        }
+    }
+
+    /// The number of items in history
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// `true` if there are no items in history
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The item at an index, where `0` is the most recent item
+    pub fn item(&self, index: usize) -> Option<&str> {
+        self.items.iter().rev().nth(index).map(|item| item.as_str())
+    }
+
+    /// Up to `count` items, starting with the most recent
+    pub fn recent(&self, count: usize) -> Vec<String> {
+        self.items.iter().rev().take(count).cloned().collect()
+    }
+
+    /// The item at an index among items beginning with `prefix`, where `0` is
+    /// the most recent match
+    pub fn find_prefix(&self, prefix: &str, index: usize) -> Option<&str> {
+        self.items.iter().rev()
+            .filter(|item| item.starts_with(prefix))
+            .nth(index)
+            .map(|item| item.as_str())
+    }
+
+    /// Trim items to fit the strategy, returning `true` if any were removed
+    fn apply_strategy(&mut self) -> bool {
+        let limit = match self.strategy {
+            HistoryStrategy::SaveAll => return false,
+            HistoryStrategy::SaveNone => 0,
+            HistoryStrategy::SaveLast(size) => size,
+        };
+        if self.items.len() > limit {
+            let excess = self.items.len() - limit;
+            self.items.drain(..excess);
+            return true;
+        }
+        false
+    }
+
+    fn load(&mut self) {
+        if let Some(ref path) = self.path {
+            let mut contents = String::new();
+            if let Ok(mut file) = File::open(path) {
+                if file.read_to_string(&mut contents).is_ok() {
+                    self.items = contents.lines()
+                        .filter(|line| !line.is_empty())
+                        .map(String::from)
+                        .collect();
+                }
+            }
+        }
+    }
+
+    fn save(&mut self) {
+        self.unsaved = false;
+        self.saved_at = Some(Instant::now());
+        if let Some(ref path) = self.path {
+            if let Err(err) = self.write_file(path) {
+                warn!("Unable to save history ({}): {}", path, err);
+            }
+        }
+    }
+
+    /// Write items to a temporary file which then replaces the history file,
+    /// so an interrupted write never leaves a partial file
+    fn write_file(&self, path: &str) -> io::Result<()> {
+        let mut contents = self.items.join("\n");
+        contents.push('\n');
+        let temp_path = format!("{}.tmp", path);
+        {
+            let mut file = File::create(&temp_path)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+        }
+        rename(&temp_path, path)
+    }
+}
+
+impl Drop for History {
+
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use script::LuaEngine;
+    use tests::{MockUI,TestDir,create_ui};
+    use ui::{ApplicationUI,EventHandler};
+
+    #[test]
+    fn push_skips_repeated_items() {
+        let mut history = History::new(HistoryStrategy::SaveAll, None);
+        history.push("reload");
+        history.push("reload");
+        history.push("  ");
+        assert_eq!(1, history.len());
+    }
+
+    #[test]
+    fn pop_and_clear() {
+        let mut history = History::new(HistoryStrategy::SaveAll, None);
+        history.push("go example.com");
+        history.push("reload");
+        assert_eq!(Some(String::from("reload")), history.pop());
+        assert_eq!(Some("go example.com"), history.item(0));
+        history.clear();
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn save_none_strategy() {
+        let mut history = History::new(HistoryStrategy::SaveNone, None);
+        history.push("reload");
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn change_strategy_trims_items() {
+        let mut history = History::new(HistoryStrategy::SaveAll, None);
+        for item in &["a", "b", "c", "d"] {
+            history.push(item);
+        }
+        history.set_strategy(HistoryStrategy::SaveLast(2));
+        assert_eq!(vec![String::from("d"), String::from("c")], history.recent(5));
+    }
+
+    #[test]
+    fn find_by_prefix() {
+        let mut history = History::new(HistoryStrategy::SaveAll, None);
+        for item in &["go a.com", "find a", "go b.com", "reload"] {
+            history.push(item);
+        }
+        assert_eq!(Some("go b.com"), history.find_prefix("go", 0));
+        assert_eq!(Some("go a.com"), history.find_prefix("go", 1));
+        assert_eq!(None, history.find_prefix("go", 2));
+        assert_eq!(Some("reload"), history.find_prefix("", 0));
+    }
+
+    #[test]
+    fn parse_strategy() {
+        assert_eq!(Some(HistoryStrategy::SaveLast(10)), HistoryStrategy::parse("last", Some(10)));
+        assert_eq!(Some(HistoryStrategy::SaveLast(DEFAULT_HISTORY_SIZE)),
+                   HistoryStrategy::parse("last", None));
+        assert_eq!(Some(HistoryStrategy::SaveNone), HistoryStrategy::parse("none", None));
+        assert_eq!(None, HistoryStrategy::parse("some", None));
+    }
+
+    #[test]
+    fn persist_items() {
+        let dir = TestDir::new("history-persist");
+        let path = String::from(dir.path().join("command-history").to_str().unwrap());
+        {
+            let mut history = History::new(HistoryStrategy::SaveAll, Some(path.clone()));
+            history.push("go example.com");
+            history.push("reload");
+        }
+        let history = History::new(HistoryStrategy::SaveLast(1), Some(path.clone()));
+        assert_eq!(vec![String::from("reload")], history.recent(5));
+    }
+
+    #[test]
+    fn batch_saves() {
+        let dir = TestDir::new("history-batch");
+        let path = String::from(dir.path().join("command-history").to_str().unwrap());
+        let mut history = History::new(HistoryStrategy::SaveAll, Some(path.clone()));
+        history.push("go example.com");
+        history.push("reload");
+        let saved = History::new(HistoryStrategy::SaveAll, Some(path.clone()));
+        assert_eq!(vec![String::from("go example.com")], saved.recent(5));
+        history.flush();
+        let saved = History::new(HistoryStrategy::SaveAll, Some(path.clone()));
+        assert_eq!(vec![String::from("reload"), String::from("go example.com")],
+                   saved.recent(5));
+    }
+
+    #[test]
+    fn record_command_history() {
+        let ui = create_ui("history-record", r#"
+            [commands.history]
+            strategy = "all"
+        "#, &[("noop.lua", r#"
+            function run()
+                return true
+            end
+        "#)]);
+        ui.engine.submit_command::<MockUI, LuaEngine>(&ui, Some(0), "noop one");
+        ui.engine.submit_command::<MockUI, LuaEngine>(&ui, Some(0), "missing");
+        ui.execute_command(Some(0), "noop unsubmitted");
+        *ui.private.borrow_mut() = true;
+        ui.engine.submit_command::<MockUI, LuaEngine>(&ui, Some(0), "noop secret");
+        assert_eq!(vec![String::from("missing"), String::from("noop one")],
+                   ui.command_history(5));
+        assert_eq!(Some(String::from("noop one")), ui.command_history_match("noop", 0));
+    }
+
+    #[test]
+    fn script_command_history() {
+        let ui = create_ui("history-script", r#"
+            [commands.history]
+            strategy = "all"
+        "#, &[("recent.lua", r#"
+            function run()
+                local items = command_history(2)
+                set_command_field_text(0, table.concat(items, ","))
+                return false
+            end
+        "#)]);
+        ui.engine.submit_command::<MockUI, LuaEngine>(&ui, Some(0), "first");
+        ui.engine.submit_command::<MockUI, LuaEngine>(&ui, Some(0), "second");
+        ui.engine.submit_command::<MockUI, LuaEngine>(&ui, Some(0), "recent");
+        assert_eq!(String::from("recent,second"), ui.command_field_text(0));
+    }
+}
@@ -4,39 +4,87 @@ extern crate getopts;
 #[macro_use]
 extern crate log;
 extern crate dirs;
+extern crate serde_json;
+extern crate wait_timeout;
 
+pub mod argspec;
+pub mod builtin;
 pub mod command;
+pub mod completion;
 pub mod config;
+pub mod expansion;
+pub mod help;
+pub mod history;
 pub mod ui;
 pub mod optparse;
 pub mod script;
+pub mod store;
+pub mod timer;
 mod keybinding;
 
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc,Mutex};
+use std::time::Duration;
+
+use argspec::ArgumentSpec;
+use builtin::{Builtins,UIContext};
+use expansion::{ExpansionContext,ExpansionKind};
 use ui::*;
-use script::ScriptingEngine;
+use script::{Invocation,Script,ScriptCache,ScriptingEngine};
+use history::History;
+use store::Store;
+use timer::{TimerOwner,Timers};
 
 /// Application identifier for apps built with webkitten core
 pub const WEBKITTEN_APP_ID: &'static str = "me.delisa.Webkitten";
 /// Application title for apps built with webkitten core
 pub const WEBKITTEN_TITLE: &'static str = "Webkitten";
+/// File name used to save command history when `commands.history.path` is
+/// unset
+const HISTORY_FILE_NAME: &str = "command-history";
+/// Directory used to save values stored by commands
+const STORE_DIR_NAME: &str = "store";
+/// Shortest delay between runs of a repeating timer, in milliseconds
+const MINIMUM_TIMER_INTERVAL: u64 = 10;
 
 /// The core of a webkitten application. The engine handles configuration options
 /// and responding to lifecycle and user events from the UI.
 pub struct Engine {
     pub config: config::Config,
     run_config: optparse::RunConfiguration,
+    history: Mutex<History>,
+    scripts: ScriptCache,
+    builtins: Builtins,
+    store: Store,
+    timers: Mutex<Timers>,
+    expansions: Mutex<ExpansionContext>,
 }
 
 impl Engine {
 
     /// Create a new application engine
     pub fn new(runtime: optparse::RunConfiguration) -> Option<Self> {
-        config::Config::parse_file(&runtime.path).and_then(|config| {
+        config::Config::open(&runtime.path).map(|config| {
             info!("Creating application engine with config path: {}", &runtime.path);
-            Some(Engine {
+            let history = History::new(config.command_history_strategy(),
+                                       history_path(&config, &runtime.path));
+            let scripts = ScriptCache::new();
+            scripts.set_limits(config.command_time_limit(), config.command_instruction_limit());
+            scripts.set_library_paths(config.command_library_paths());
+            let store = Store::new(data_dir(&config, &runtime.path)
+                                   .map(|dir| Path::new(&dir).join(STORE_DIR_NAME)));
+            Engine {
                 config: config,
-                run_config: runtime
-            })
+                run_config: runtime,
+                history: Mutex::new(history),
+                scripts,
+                builtins: Builtins::default(),
+                store,
+                timers: Mutex::new(Timers::new()),
+                expansions: Mutex::new(ExpansionContext::new()),
+            }
         })
     }
 
@@ -45,67 +93,483 @@ impl Engine {
         &self.run_config.start_pages
     }
 
+    /// Command files read while running commands, completing command text,
+    /// and handling buffer events
+    pub fn scripts(&self) -> &ScriptCache {
+        &self.scripts
+    }
+
+    /// Commands implemented natively, used where no command file matches
+    pub fn builtins(&self) -> &Builtins {
+        &self.builtins
+    }
+
+    /// The directory for files saved by the engine, `general.config-dir` or
+    /// the directory containing the configuration file
+    pub fn data_dir(&self) -> Option<String> {
+        data_dir(&self.config, &self.run_config.path)
+    }
+
+    /// Values saved by commands between invocations
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Names of enabled commands and aliases matching a query, ranked by how
+    /// closely each name matches and how frequently and recently it was used
+    pub fn command_names_matching<S: ScriptingEngine>(&self, query: &str) -> Vec<String> {
+        let mut candidates: Vec<String> = command::Command::list_available(&self.config, &self.builtins, &self.file_extensions::<S>())
+            .iter()
+            .map(|command| command.namespace.first().cloned().unwrap_or_else(|| command.name()))
+            .collect();
+        let mut aliases: Vec<String> = self.config.lookup_str_table("commands.aliases")
+            .unwrap_or_default()
+            .into_keys()
+            .filter(|alias| self.config.resolved_command_name(alias).is_some())
+            .collect();
+        aliases.sort();
+        candidates.extend(aliases);
+        let usage = completion::usage_scores(&self.command_history(completion::USAGE_WINDOW));
+        completion::rank(query, candidates, &usage)
+    }
+
+    /// Completions for the names of commands and aliases matching a query,
+    /// described by the command each name invokes
+    pub fn command_name_completions<S: ScriptingEngine>(&self, query: &str) -> Vec<Completion> {
+        self.command_names_matching::<S>(query).into_iter()
+            .map(|name| {
+                let description = command::Command::parse(&name, &self.config, &self.builtins, &self.file_extensions::<S>())
+                    .and_then(|command| match command.builtin {
+                        Some(name) => self.builtins.get(&name).map(|builtin| String::from(builtin.describe())),
+                        None => self.scripts.load(&command.path).ok()
+                            .and_then(|script| S::describe(&script).ok()),
+                    });
+                match description {
+                    Some(description) => Completion::described(name, description),
+                    None => Completion::new(name),
+                }
+            })
+            .collect()
+    }
+
+    /// Stop any command scripts which are currently running. Commands run on
+    /// the thread handling UI events, so when called from a command this only
+    /// affects the scripts which ran it. Input received while scripts run is
+    /// handled by the interrupt check instead.
+    pub fn cancel_commands(&self) {
+        self.scripts.cancel();
+    }
+
+    /// Set the function called periodically while command scripts run,
+    /// which stops them when it returns true. The UI checks for input bound
+    /// to the `cancel` command, which it cannot otherwise handle until the
+    /// scripts finish.
+    pub fn set_interrupt_check(&self, check: fn() -> bool) {
+        self.scripts.set_interrupt_check(check);
+    }
+
     /// Reload configuration from path
     pub fn reload(&mut self) -> bool {
-        self.config.load(&self.run_config.path)
+        if self.config.load(&self.run_config.path) {
+            self.apply_config();
+            return true;
+        }
+        false
     }
 
-    fn use_argument_completion(&self, prefix: &str) -> bool {
-        prefix.contains(" ")
+    /// Change configuration options while running, also saving the change
+    /// to the overrides file alongside the configuration file if `persist`
+    /// is set and the change succeeds
+    pub fn update_config<F>(&self, persist: bool, change: F) -> Result<(), config::ConfigError>
+            where F: Fn(&config::Config) -> Result<(), config::ConfigError> {
+        change(&self.config)?;
+        self.apply_config();
+        if persist {
+            config::Config::update_overrides(&self.run_config.path, &change)?;
+        }
+        Ok(())
     }
-}
 
-impl EventHandler for Engine {
+    /// Update state derived from configuration options
+    fn apply_config(&self) {
+        self.scripts.set_limits(self.config.command_time_limit(),
+                                self.config.command_instruction_limit());
+        self.scripts.set_library_paths(self.config.command_library_paths());
+        if let Ok(mut history) = self.history.lock() {
+            history.set_strategy(self.config.command_history_strategy());
+        }
+    }
 
-    fn on_new_frame_request<T, S>(&self, ui: &T, window_index: u32, uri: &str)
+    /// Add command text to history unless it was run from a private webview.
+    /// Only text submitted from the command bar is recorded, not commands run
+    /// by keybindings, timers, hooks, or other commands.
+    fn record_command<T, S>(&self, ui: &T, window_index: Option<u32>, text: &str)
         where T: ApplicationUI<S>,
               S: ScriptingEngine {
-        if self.config.new_frame_uses_focused_window() {
-            ui.open_webview::<_, config::Config>(window_index, Some(uri), None);
-        } else {
-            ui.open_window::<_, config::Config>(Some(uri), None);
+        let private = window_index
+            .and_then(|index| ui.focused_webview_index(index)
+                      .map(|webview_index| ui.is_private_webview(index, webview_index)))
+            .unwrap_or(false);
+        if !private {
+            if let Ok(mut history) = self.history.lock() {
+                history.push(text);
+            }
         }
     }
 
-    fn execute_command<T, S>(&self, ui: &T, window_index: Option<u32>, text: &str)
+    fn run_command<T, S>(&self, ui: &T, target: CommandTarget, text: &str) -> CommandOutput
         where T: ApplicationUI<S>,
               S: ScriptingEngine {
-        if let Some(text) = self.config.command_matching_prefix(text) {
-            return self.execute_command(ui, window_index, &text);
-        } else if let Some(command) = command::Command::parse(text, &self.config, S::file_extension()) {
+        if text.trim().is_empty() {
+            return CommandOutput::error(CommandError::NoCommandSpecified,
+                                        "No command specified");
+        }
+        if let Some(expanded) = self.config.command_matching_prefix(text) {
+            let prefix: String = text.chars().take(1).collect();
+            return self.run_expansion(ui, target, ExpansionKind::Prefix, &prefix, &expanded);
+        }
+        match command::split_sequence(text) {
+            Err(err) => return CommandOutput::error(CommandError::InvalidArguments, format!("{}", err)),
+            Ok(ref sequence) if sequence.len() > 1 => return self.run_sequence(ui, target, sequence),
+            _ => (),
+        }
+        if let Some(expanded) = self.expand_alias(text) {
+            info!("Expanded alias: {}", expanded);
+            let name = text.split_whitespace().next().unwrap_or(text);
+            return self.run_expansion(ui, target, ExpansionKind::Alias, name, &expanded);
+        }
+        if let Some(command) = command::Command::parse(text, &self.config, &self.builtins, &self.file_extensions::<S>()) {
+            if let Some(builtin) = command.builtin.as_ref().and_then(|name| self.builtins.get(name)) {
+                info!("Found built-in command match: {}", builtin.name());
+                let output = builtin.run(&UIContext::<T, S>::new(ui, self, target.window_index), &command.arguments);
+                if let (true, Some(index)) = (output.is_success(), target.output_window()) {
+                    ui.set_command_field_text(index, output.message.as_deref().unwrap_or(""))
+                }
+                return output;
+            }
             info!("Found command match: {}", command.path);
-            if let Some(file) = command.file() {
-                match S::execute::<T, S>(file, command.arguments, ui, &self.run_config.path) {
-                    Err(err) => warn!("{}", err),
-                    Ok(success) => if let (true, Some(index)) = (success, window_index) {
-                        ui.set_command_field_text(index, "")
+            if let Ok(script) = self.load_command(&command) {
+                if let Err(output) = self.validate_arguments::<S>(&script, &command) {
+                    return output;
+                }
+                let result = S::execute::<T, S>(&script, command.arguments.clone(), ui,
+                                                &self.invocation(&command).in_window(target.window_index));
+                self.save_store();
+                return match result {
+                    Err(err) => {
+                        warn!("{}", err);
+                        CommandOutput::error(CommandError::ErrorDuringExecution, err.summary())
+                    },
+                    Ok(success) => {
+                        if let (true, Some(index)) = (success, target.output_window()) {
+                            ui.set_command_field_text(index, "")
+                        }
+                        CommandOutput::completion(success)
                     }
                 }
             }
+            return CommandOutput::error(CommandError::ErrorDuringExecution,
+                                        format!("Unable to open command file: {}", command.path));
         } else if let Some(default) = self.config.default_command() {
             if !text.starts_with(&default) {
-                let mut command = String::from(default);
+                let mut command = default.clone();
                 command.push_str(" ");
                 command.push_str(text);
                 info!("Running the default command: {}", command);
-                return self.execute_command(ui, window_index, &command);
+                return self.run_expansion(ui, target, ExpansionKind::Default, &default, &command);
             }
         }
+        let name = text.split_whitespace().next().unwrap_or(text);
+        CommandOutput::error(CommandError::CommandNotFound,
+                             format!("No command found matching '{}'", name))
+    }
+
+    /// Check the arguments of a command against the argument spec declared by
+    /// its command file, if any
+    fn validate_arguments<S: ScriptingEngine>(&self, script: &Script, command: &command::Command)
+        -> Result<(), CommandOutput> {
+        let spec = match self.argument_spec::<S>(script) {
+            Ok(Some(spec)) => spec,
+            Ok(None) => return Ok(()),
+            Err(err) => return Err(CommandOutput::error(CommandError::ErrorDuringExecution, err)),
+        };
+        spec.validate(&command.arguments).map_err(|err| {
+            CommandOutput::error(CommandError::InvalidArguments,
+                                 format!("{}. Usage: {}", err, spec.usage(&command.name())))
+        })
+    }
+
+    /// The script of a command file
+    fn load_command(&self, command: &command::Command) -> io::Result<Arc<Script>> {
+        self.scripts.load(&command.path)
+    }
+
+    /// The name and sandbox profile of a command, passed to the scripting
+    /// engine along with its script
+    fn invocation(&self, command: &command::Command) -> Invocation {
+        let name = command.name();
+        let sandbox = self.config.command_sandbox(&name);
+        Invocation::new(self.run_config.path.as_str(), name).with_sandbox(sandbox)
+    }
+
+    /// Show the failure of a command in the window it ran for, unless it ran
+    /// within another command, which handles the failure instead
+    fn report_error<T, S>(&self, ui: &T, target: CommandTarget, output: &CommandOutput)
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        let nested = self.expansions.lock().map(|context| context.depth() > 0).unwrap_or(false);
+        match (output.error, output.message.as_ref(), target.output_window()) {
+            (Some(CommandError::NoCommandSpecified), _, _) => (),
+            (Some(_), Some(message), Some(index)) if !nested => ui.show_command_error(index, message),
+            _ => (),
+        }
+    }
+
+    /// Write the values changed by a script to the command store
+    fn save_store(&self) {
+        if let Err(err) = self.store.flush() {
+            warn!("Unable to save store: {}", err);
+        }
+    }
+
+    /// The file extensions of command files to resolve, including
+    /// executables only when enabled in the configuration
+    fn file_extensions<S: ScriptingEngine>(&self) -> Vec<&'static str> {
+        self.config.command_file_extensions(S::file_extensions())
+    }
+
+    /// The argument spec declared by a command file, if any
+    fn argument_spec<S: ScriptingEngine>(&self, script: &Script) -> Result<Option<ArgumentSpec>, String> {
+        match S::argument_spec(script) {
+            Ok(Some(spec)) => ArgumentSpec::parse(&spec).map(Some)
+                .map_err(|err| format!("{}: {}", script.path(), err)),
+            Ok(None) => Ok(None),
+            Err(err) => {
+                warn!("Unable to read argument spec: {}", err);
+                Ok(None)
+            },
+        }
+    }
+
+    /// Run command text expanded from other command text, failing if the
+    /// same expansion is already in progress or there are too many expansions
+    /// in progress
+    fn run_expansion<T, S>(&self, ui: &T, target: CommandTarget, kind: ExpansionKind,
+                           key: &str, text: &str) -> CommandOutput
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        let entered = match self.expansions.lock() {
+            Ok(mut context) => context.enter(kind, key, text),
+            Err(_) => Ok(()),
+        };
+        if let Err(err) = entered {
+            warn!("{}", err);
+            return CommandOutput::error(CommandError::ExpansionLoop, format!("{}", err));
+        }
+        let output = self.run_command(ui, target, text);
+        if let Ok(mut context) = self.expansions.lock() {
+            context.exit();
+        }
+        output
+    }
+
+    /// Run each command in a sequence, skipping commands where the operator
+    /// does not match the outcome of the previous command. Returns the output
+    /// of the last command run.
+    fn run_sequence<T, S>(&self, ui: &T, target: CommandTarget,
+                          sequence: &[(command::SequenceOperator, String)]) -> CommandOutput
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        let mut output = CommandOutput::success();
+        for &(operator, ref text) in sequence {
+            let run = match operator {
+                command::SequenceOperator::Always => true,
+                command::SequenceOperator::OnSuccess => output.is_success(),
+                command::SequenceOperator::OnFailure => !output.is_success(),
+            };
+            if run {
+                output = self.run_command(ui, target, text);
+            } else {
+                info!("Skipping command in sequence: {}", text);
+            }
+        }
+        output
+    }
+
+    /// Replace the command name with the matching alias in `commands.aliases`
+    /// if the alias is more than a single command name, such as a command
+    /// with arguments or a sequence of commands. Any argument placeholders in
+    /// the alias are replaced with the arguments, otherwise the arguments are
+    /// appended to the alias.
+    fn expand_alias(&self, text: &str) -> Option<String> {
+        let text = text.trim_start();
+        let (name, arguments) = match text.find(char::is_whitespace) {
+            Some(index) => (&text[..index], &text[index..]),
+            None => (text, ""),
+        };
+        self.config.lookup_str(&format!("commands.aliases.{}", name))
+            .and_then(|alias| {
+                let alias = alias.trim();
+                let placeholders = command::has_placeholders(alias);
+                if !placeholders && alias.split_whitespace().count() < 2 && !alias.contains(';') {
+                    return None;
+                }
+                if alias.split_whitespace().next() == Some(name) {
+                    warn!("Skipping alias which refers to itself: {}", name);
+                    return None;
+                }
+                if !placeholders {
+                    return Some(format!("{}{}", alias, arguments));
+                }
+                command::tokenize(arguments).ok()
+                    .map(|arguments| command::expand_placeholders(alias, &arguments))
+            })
+    }
+
+    fn use_argument_completion(&self, prefix: &str) -> bool {
+        prefix.contains(" ")
+    }
+
+    /// Completions for the names of nested commands and namespaces when the
+    /// words preceding the final word of the command text name a namespace
+    fn subcommand_completions<S: ScriptingEngine>(&self, prefix: &str) -> Vec<Completion> {
+        let mut words = match command::tokenize(prefix) {
+            Ok(words) => words,
+            Err(_) => return vec![],
+        };
+        if prefix.ends_with(char::is_whitespace) {
+            words.push(String::new());
+        }
+        let partial = match words.pop() {
+            Some(partial) => partial,
+            None => return vec![],
+        };
+        if let Some(name) = words.first().cloned() {
+            match self.config.lookup_str(&format!("commands.aliases.{}", name)) {
+                Some(ref alias) if command::has_placeholders(alias) || alias.contains(command::is_separator) => {
+                    return vec![];
+                },
+                Some(alias) => match command::tokenize(&alias) {
+                    Ok(ref expanded) if !expanded.is_empty() && !self.config.command_disabled(&expanded[0]) => {
+                        words.splice(..1, expanded.iter().cloned());
+                    },
+                    _ => return vec![],
+                },
+                None => if self.config.command_disabled(&name) {
+                    return vec![];
+                },
+            }
+        }
+        if !command::Command::is_namespace(&words, &self.config) {
+            return vec![];
+        }
+        let names = command::Command::list_namespace(&words, &self.config, &self.file_extensions::<S>());
+        completion::rank(&partial, names, &HashMap::new()).into_iter()
+            .map(|name| {
+                let text = format!("{} {}", words.join(" "), name);
+                let description = command::Command::parse(&text, &self.config, &self.builtins, &self.file_extensions::<S>())
+                    .filter(|command| command.builtin.is_none() && command.arguments.is_empty())
+                    .and_then(|command| self.scripts.load(&command.path).ok())
+                    .and_then(|script| S::describe(&script).ok());
+                match description {
+                    Some(description) => Completion::described(name, description),
+                    None => Completion::new(name),
+                }
+            })
+            .collect()
+    }
+}
+
+impl EventHandler for Engine {
+
+    fn on_new_frame_request<T, S>(&self, ui: &T, window_index: u32, uri: &str)
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        if self.config.new_frame_uses_focused_window() {
+            ui.open_webview::<_, config::Config>(window_index, Some(uri), None);
+        } else {
+            ui.open_window::<_, config::Config>(Some(uri), None);
+        }
+    }
+
+    fn execute_command<T, S>(&self, ui: &T, window_index: Option<u32>, text: &str) -> CommandOutput
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        let target = CommandTarget::window(window_index);
+        let output = self.run_expansion(ui, target, ExpansionKind::Command, text, text);
+        self.report_error(ui, target, &output);
+        output
+    }
+
+    fn on_command_text_change<T, S>(&self, ui: &T, window_index: u32, text: &str)
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        if self.config.command_matching_prefix(text).is_some() {
+            let output = self.run_command(ui, CommandTarget::background(window_index), text);
+            if let Some(message) = output.error.and(output.message) {
+                info!("Text change command failed: {}", message);
+            }
+        }
+    }
+
+    fn submit_command<T, S>(&self, ui: &T, window_index: Option<u32>, text: &str) -> CommandOutput
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        self.record_command(ui, window_index, text);
+        self.execute_command(ui, window_index, text)
     }
 
     fn close<T, S>(&self, _ui: &T)
         where T: ApplicationUI<S>,
-              S: ScriptingEngine {}
+              S: ScriptingEngine {
+        if let Ok(mut history) = self.history.lock() {
+            history.flush();
+        }
+    }
 
-    fn command_completions<T, S>(&self, ui: &T, prefix: &str) -> Vec<String>
+    fn command_history(&self, count: usize) -> Vec<String> {
+        self.history.lock()
+            .map(|history| history.recent(count))
+            .unwrap_or(vec![])
+    }
+
+    fn command_history_match(&self, prefix: &str, index: usize) -> Option<String> {
+        self.history.lock().ok()
+            .and_then(|history| history.find_prefix(prefix, index).map(String::from))
+    }
+
+    fn command_completions<T, S>(&self, ui: &T, prefix: &str) -> Vec<Completion>
         where T: ApplicationUI<S>,
               S: ScriptingEngine {
         if self.use_argument_completion(prefix) {
-            if let Some(command) = command::Command::parse(prefix, &self.config, S::file_extension()) {
+            let completions = self.subcommand_completions::<S>(prefix);
+            if !completions.is_empty() {
+                return completions;
+            }
+            if let Some(command) = command::Command::parse(prefix, &self.config, &self.builtins, &self.file_extensions::<S>()) {
                 info!("Found command match for completion: {}", prefix);
-                if let Some(file) = command.file() {
+                if let Some(builtin) = command.builtin.as_ref().and_then(|name| self.builtins.get(name)) {
+                    let mut arguments = command.arguments.clone();
+                    if prefix.ends_with(char::is_whitespace) {
+                        arguments.push(String::new());
+                    }
+                    return builtin.complete(&UIContext::<T, S>::new(ui, self, None), &arguments);
+                }
+                if let Ok(script) = self.load_command(&command) {
+                    if let Ok(Some(spec)) = self.argument_spec::<S>(&script) {
+                        let mut arguments = command.arguments.clone();
+                        if prefix.ends_with(char::is_whitespace) {
+                            arguments.push(String::new());
+                        }
+                        let values = spec.completions(&arguments);
+                        if !values.is_empty() {
+                            return values.into_iter().map(Completion::new).collect();
+                        }
+                    }
                     info!("Completing command text using {}", command.path);
-                    return match S::autocomplete::<T, S>(file, command.arguments, prefix, ui, &self.run_config.path) {
+                    let result = S::autocomplete::<T, S>(&script, command.arguments.clone(), prefix, ui, &self.invocation(&command));
+                    self.save_store();
+                    return match result {
                         Err(err) => {
                             warn!("{}", err);
                             vec![]
@@ -115,21 +579,711 @@ impl EventHandler for Engine {
                 }
             }
         }
-        command::Command::list_commands(prefix, &self.config)
+        self.command_name_completions::<S>(prefix.trim())
     }
 
     fn on_buffer_event<T, S>(&self, ui: &T, window_index: u32, webview_index: u32, uri: Option<&str>, event: BufferEvent)
         where T: ApplicationUI<S>,
               S: ScriptingEngine {
         for name in self.config.on_buffer_event_commands(&event) {
-            if let Some(command) = command::Command::parse(&name, &self.config, S::file_extension()) {
-                if let Some(file) = command.file() {
-                    match S::on_buffer_event::<T, S>(file, ui, &self.run_config.path, window_index, webview_index, uri, &event) {
-                        Err(err) => warn!("{}", err),
-                        Ok(_) => (),
+            if let Some(command) = command::Command::parse(&name, &self.config, &self.builtins, &self.file_extensions::<S>()) {
+                if command.builtin.is_some() {
+                    continue;
+                }
+                if let Ok(script) = self.load_command(&command) {
+                    if let Err(err) = S::on_buffer_event::<T, S>(&script, ui, &self.invocation(&command), window_index, webview_index, uri, &event) {
+                        warn!("{}", err);
                     }
                 }
             }
         }
+        self.save_store();
+    }
+
+    fn set_timer<T, S>(&self, ui: &T, command: &str, owner: TimerOwner, delay: Duration, repeat: bool) -> u32
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        let delay = if repeat { delay.max(Duration::from_millis(MINIMUM_TIMER_INTERVAL)) } else { delay };
+        let id = match self.timers.lock() {
+            Ok(mut timers) => timers.add(command, owner, if repeat { Some(delay) } else { None }),
+            Err(_) => return 0,
+        };
+        info!("Scheduling timer {} in {:?}: {}", id, delay, command);
+        ui.schedule_timer(id, delay);
+        id
+    }
+
+    fn clear_timer(&self, timer_id: u32) {
+        if let Ok(mut timers) = self.timers.lock() {
+            timers.remove(timer_id);
+        }
+    }
+
+    fn on_timer<T, S>(&self, ui: &T, timer_id: u32)
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        let timer = self.timers.lock().ok().and_then(|mut timers| {
+            let timer = timers.get(timer_id).cloned()?;
+            let is_open = owner_exists::<T, S>(ui, timer.owner);
+            if timer.interval.is_none() || !is_open {
+                timers.remove(timer_id);
+            }
+            Some(timer).filter(|_| is_open)
+        });
+        if let Some(timer) = timer {
+            if let Some(interval) = timer.interval {
+                ui.schedule_timer(timer_id, interval);
+            }
+            info!("Running timer {}: {}", timer_id, timer.command);
+            let target = CommandTarget::background(timer.owner.window_index());
+            let output = self.run_expansion(ui, target, ExpansionKind::Command, &timer.command, &timer.command);
+            if let Some(message) = output.error.and(output.message) {
+                warn!("Timer {} failed: {}", timer_id, message);
+            }
+        }
+    }
+
+    fn on_javascript_result<T, S>(&self, ui: &T, window_index: u32, webview_index: u32,
+                                  command: &str, result: JavaScriptResult)
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        if let Err(ref message) = result {
+            info!("JavaScript failed in ({}, {}): {}", window_index, webview_index, message);
+        }
+        let script = command::Command::parse(command, &self.config, &self.builtins, &self.file_extensions::<S>())
+            .filter(|command| command.builtin.is_none())
+            .and_then(|command| self.load_command(&command).ok().map(|script| (script, command)));
+        match script {
+            Some((script, command)) => {
+                let invocation = self.invocation(&command);
+                if let Err(err) = S::on_javascript_result::<T, S>(&script, command.arguments, ui, &invocation,
+                                                                  window_index, webview_index, &result) {
+                    warn!("{}", err);
+                }
+                self.save_store();
+            },
+            None => warn!("No command found for JavaScript result: {}", command),
+        }
+    }
+
+    fn on_window_close(&self, window_index: u32) {
+        if let Ok(mut timers) = self.timers.lock() {
+            timers.close_window(window_index);
+        }
+    }
+
+    fn on_webview_close(&self, window_index: u32, webview_index: u32) {
+        if let Ok(mut timers) = self.timers.lock() {
+            timers.close_webview(window_index, webview_index);
+        }
+    }
+}
+
+/// The window a command runs for and whether its outcome is shown in that
+/// window's command field
+#[derive(Clone,Copy)]
+struct CommandTarget {
+    window_index: Option<u32>,
+    shows_output: bool,
+}
+
+impl CommandTarget {
+
+    /// A command entered or run for a window, showing its outcome there
+    fn window(window_index: Option<u32>) -> Self {
+        CommandTarget { window_index, shows_output: true }
+    }
+
+    /// A command run for a window without changing its command field, such
+    /// as a timer command
+    fn background(window_index: u32) -> Self {
+        CommandTarget { window_index: Some(window_index), shows_output: false }
+    }
+
+    /// The window to show the outcome of the command in, if any
+    fn output_window(&self) -> Option<u32> {
+        self.window_index.filter(|_| self.shows_output)
+    }
+}
+
+/// Whether the window or buffer owning a timer is open
+fn owner_exists<T, S>(ui: &T, owner: TimerOwner) -> bool
+    where T: ApplicationUI<S>,
+          S: ScriptingEngine {
+    match owner {
+        TimerOwner::Window(window_index) => ui.webview_count(window_index) > 0,
+        TimerOwner::Webview(window_index, webview_index) => webview_index < ui.webview_count(window_index),
+    }
+}
+
+/// The path of the command history file from `commands.history.path`,
+/// falling back to a file in `general.config-dir` or alongside the
+/// configuration file
+fn history_path(config: &config::Config, config_path: &str) -> Option<String> {
+    if let Some(path) = config.command_history_path() {
+        return Some(path);
+    }
+    data_dir(config, config_path)
+        .map(|dir| String::from(Path::new(&dir).join(HISTORY_FILE_NAME).to_string_lossy()))
+}
+
+/// The directory for files saved by the engine, `general.config-dir` or the
+/// directory containing the configuration file
+fn data_dir(config: &config::Config, config_path: &str) -> Option<String> {
+    config.config_dir()
+        .or(Path::new(config_path).parent()
+            .and_then(|dir| dir.to_str())
+            .map(String::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::env::temp_dir;
+    use std::fs::{File,create_dir_all,remove_dir_all};
+    use std::io::Write;
+    use std::path::{Path,PathBuf};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize,Ordering};
+    use std::time::Duration;
+
+    use super::Engine;
+    use config::{Config,ConfigError};
+    use optparse::RunConfiguration;
+    use script::LuaEngine;
+    use store::Store;
+    use timer::TimerOwner;
+    use ui::*;
+
+    /// A uniquely named temporary directory holding the files of a test,
+    /// removed when dropped
+    pub struct TestDir {
+        path: PathBuf,
+    }
+
+    impl TestDir {
+
+        pub fn new(name: &str) -> TestDir {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let path = temp_dir().join(format!("webkitten-{}-{}-{}", name, process::id(),
+                                               COUNT.fetch_add(1, Ordering::SeqCst)));
+            create_dir_all(&path).unwrap();
+            TestDir { path }
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        /// Write a file within the directory, creating any parent
+        /// directories, and return its path
+        pub fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.path.join(name);
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent).unwrap();
+            }
+            File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TestDir {
+
+        #[allow(unused_must_use)]
+        fn drop(&mut self) {
+            remove_dir_all(&self.path);
+        }
+    }
+
+    /// A headless UI recording changes made by commands
+    pub struct MockUI {
+        pub engine: Engine,
+        pub command_text: RefCell<HashMap<u32, String>>,
+        pub command_errors: RefCell<HashMap<u32, String>>,
+        pub loaded_uris: RefCell<Vec<String>>,
+        pub private: RefCell<bool>,
+        pub scheduled_timers: RefCell<Vec<(u32, Duration)>>,
+        pub closed_windows: RefCell<Vec<u32>>,
+        /// The directory of the configuration file and command scripts,
+        /// removed after the engine is dropped
+        pub dir: Option<TestDir>,
+    }
+
+    impl ApplicationUI<LuaEngine> for MockUI {
+
+        fn new(engine: Engine) -> Option<Self> {
+            Some(MockUI {
+                engine,
+                command_text: RefCell::new(HashMap::new()),
+                command_errors: RefCell::new(HashMap::new()),
+                loaded_uris: RefCell::new(vec![]),
+                private: RefCell::new(false),
+                scheduled_timers: RefCell::new(vec![]),
+                closed_windows: RefCell::new(vec![]),
+                dir: None,
+            })
+        }
+
+        fn run(&self) {}
+
+        fn copy(&self, _text: &str) {}
+
+        fn execute_command(&self, window_index: Option<u32>, text: &str) -> CommandOutput {
+            self.engine.execute_command::<MockUI, LuaEngine>(self, window_index, text)
+        }
+
+        fn focused_window_index(&self) -> Option<u32> { Some(0) }
+
+        fn window_count(&self) -> u32 { 1 }
+
+        fn open_window<U, B>(&self, _uri: Option<U>, _config: Option<B>) -> u32
+            where U: Into<String>,
+                  B: BrowserConfiguration { 0 }
+
+        fn close_window(&self, index: u32) {
+            self.closed_windows.borrow_mut().push(index);
+        }
+
+        fn focus_window(&self, _index: u32) {}
+
+        fn focus_window_area(&self, _index: u32, _area: WindowArea) {}
+
+        fn toggle_window(&self, _index: u32, _visible: bool) {}
+
+        fn resize_window(&self, _window_index: u32, _width: u32, _height: u32) {}
+
+        fn command_field_text(&self, window_index: u32) -> String {
+            self.command_text.borrow().get(&window_index).cloned().unwrap_or(String::new())
+        }
+
+        fn set_command_field_text(&self, window_index: u32, text: &str) {
+            self.command_text.borrow_mut().insert(window_index, String::from(text));
+        }
+
+        fn command_field_visible(&self, _window_index: u32) -> bool { true }
+
+        fn set_command_field_visible(&self, _window_index: u32, _visible: bool) {}
+
+        fn show_command_error(&self, window_index: u32, message: &str) {
+            self.command_errors.borrow_mut().insert(window_index, String::from(message));
+        }
+
+        fn command_history(&self, count: u32) -> Vec<String> {
+            self.engine.command_history(count as usize)
+        }
+
+        fn command_history_match(&self, prefix: &str, index: u32) -> Option<String> {
+            self.engine.command_history_match(prefix, index as usize)
+        }
+
+        fn command_store(&self) -> &Store {
+            self.engine.store()
+        }
+
+        fn config(&self) -> &Config {
+            &self.engine.config
+        }
+
+        fn update_config<F>(&self, persist: bool, change: F) -> Result<(), ConfigError>
+                where F: Fn(&Config) -> Result<(), ConfigError> {
+            self.engine.update_config(persist, change)
+        }
+
+        fn set_timer(&self, command: &str, owner: TimerOwner, delay: Duration, repeat: bool) -> u32 {
+            self.engine.set_timer::<_, LuaEngine>(self, command, owner, delay, repeat)
+        }
+
+        fn clear_timer(&self, timer_id: u32) {
+            self.engine.clear_timer(timer_id);
+        }
+
+        fn schedule_timer(&self, timer_id: u32, delay: Duration) {
+            self.scheduled_timers.borrow_mut().push((timer_id, delay));
+        }
+
+        fn window_title(&self, _window_index: u32) -> String { String::new() }
+
+        fn set_window_title(&self, _window_index: u32, _title: &str) {}
+
+        fn focused_webview_index(&self, _window_index: u32) -> Option<u32> { Some(0) }
+
+        fn webview_count(&self, _window_index: u32) -> u32 { 1 }
+
+        fn open_webview<'a, U, B>(&self, _window_index: u32, _uri: Option<U>, _config: Option<B>)
+            where U: Into<String>,
+                  B: BrowserConfiguration {}
+
+        fn close_webview(&self, _window_index: u32, _webview_index: u32) {}
+
+        fn focus_webview(&self, _window_index: u32, _webview_index: u32) {}
+
+        fn reload_webview(&self, _window_index: u32, _webview_index: u32, _disable_filters: bool) {}
+
+        fn set_uri(&self, _window_index: u32, _webview_index: u32, uri: &str) {
+            self.loaded_uris.borrow_mut().push(String::from(uri));
+        }
+
+        fn go_back(&self, _window_index: u32, _webview_index: u32) -> bool { false }
+
+        fn go_forward(&self, _window_index: u32, _webview_index: u32) -> bool { false }
+
+        fn uri(&self, _window_index: u32, _webview_index: u32) -> String { String::new() }
+
+        fn is_private_webview(&self, _window_index: u32, _webview_index: u32) -> bool {
+            *self.private.borrow()
+        }
+
+        fn find_string(&self, _window_index: u32, _webview_index: u32, _query: &str) {}
+
+        fn hide_find_results(&self, _window_index: u32, _webview_index: u32) {}
+
+        fn webview_title(&self, _window_index: u32, _webview_index: u32) -> String { String::new() }
+
+        fn run_javascript(&self, _window_index: u32, _webview_index: u32, _script: &str) {}
+
+        /// Treat the snippet as the JSON result, or as an exception if it is
+        /// `throw`
+        fn evaluate_javascript(&self, window_index: u32, webview_index: u32, script: &str, command: &str) {
+            let result = match script {
+                "throw" => Err(String::from("Error: thrown")),
+                json => Ok(String::from(json)),
+            };
+            self.engine.on_javascript_result::<_, LuaEngine>(self, window_index, webview_index, command, result);
+        }
+
+        fn apply_styles(&self, _window_index: u32, _webview_index: u32, _styles: &str) {}
+    }
+
+    /// Create a directory of command scripts and a UI configured to search it
+    pub fn create_ui(name: &str, config: &str, scripts: &[(&str, &str)]) -> MockUI {
+        let dir = TestDir::new(name);
+        for &(file_name, contents) in scripts {
+            dir.write(file_name, contents);
+        }
+        let config_path = dir.write("config.toml", &format!(r#"
+            [commands]
+            search-paths = ["{}"]
+            {}
+        "#, dir.path().display(), config));
+        let run_config = RunConfiguration {
+            path: String::from(config_path.to_str().unwrap()),
+            start_pages: vec![],
+            exit_status: None,
+        };
+        let mut ui = Engine::new(run_config).and_then(MockUI::new).unwrap();
+        ui.dir = Some(dir);
+        ui
+    }
+
+    impl MockUI {
+
+        /// The path of a file in the directory of the configuration file
+        pub fn file_path(&self, name: &str) -> PathBuf {
+            self.dir.as_ref().unwrap().path().join(name)
+        }
+    }
+
+    /// The values of completions for command text
+    fn complete_values(ui: &MockUI, prefix: &str) -> Vec<String> {
+        ui.engine.command_completions::<MockUI, LuaEngine>(ui, prefix).into_iter()
+            .map(|completion| completion.value)
+            .collect()
+    }
+
+    #[test]
+    fn execute_empty_command() {
+        let ui = create_ui("execute-empty", "", &[]);
+        let output = ui.execute_command(Some(0), "  ");
+        assert_eq!(Some(CommandError::NoCommandSpecified), output.error);
+    }
+
+    #[test]
+    fn execute_missing_command() {
+        let ui = create_ui("execute-missing", "", &[]);
+        let output = ui.execute_command(Some(0), "nonexistent arg");
+        assert_eq!(Some(CommandError::CommandNotFound), output.error);
+        assert!(output.message.unwrap().contains("nonexistent"));
+    }
+
+    #[test]
+    fn execute_unterminated_quote() {
+        let ui = create_ui("execute-unterminated", "", &[]);
+        let output = ui.execute_command(Some(0), "bookmark save \"My Page");
+        assert_eq!(Some(CommandError::InvalidArguments), output.error);
+    }
+
+    #[test]
+    fn execute_failing_command() {
+        let ui = create_ui("execute-failing", "", &[("fail.lua", r#"
+            function run()
+                error("something broke")
+            end
+        "#)]);
+        let output = ui.execute_command(Some(0), "fail");
+        assert_eq!(Some(CommandError::ErrorDuringExecution), output.error);
+        assert_eq!(Some(String::from("fail.lua:3: script failed to execute: something broke")),
+                   output.message);
+        assert_eq!(output.message, ui.command_errors.borrow().get(&0).cloned());
+        assert_eq!(String::new(), ui.command_field_text(0));
+    }
+
+    #[test]
+    fn report_command_errors() {
+        let ui = create_ui("report-errors", r#"
+            [commands.on-text-change]
+            "/" = "fail"
+        "#, &[("fail.lua", r#"
+            function run()
+                error("something broke")
+            end
+        "#), ("outer.lua", r#"
+            function run()
+                run_command(0, "missing")
+                return true
+            end
+        "#)]);
+        ui.engine.on_command_text_change::<MockUI, LuaEngine>(&ui, 0, "/abc");
+        assert!(ui.command_errors.borrow().is_empty());
+        assert!(ui.execute_command(Some(0), "outer").is_success());
+        assert!(ui.command_errors.borrow().is_empty());
+        assert!(!ui.execute_command(None, "missing").is_success());
+        assert!(ui.command_errors.borrow().is_empty());
+        assert!(!ui.execute_command(Some(1), "missing").is_success());
+        assert_eq!(Some(String::from("No command found matching 'missing'")),
+                   ui.command_errors.borrow().get(&1).cloned());
+    }
+
+    #[test]
+    fn execute_successful_command() {
+        let ui = create_ui("execute-success", "", &[("go.lua", r#"
+            function run()
+                load_uri(0, 0, arguments[1])
+                return true
+            end
+        "#)]);
+        ui.set_command_field_text(0, "go example.com");
+        let output = ui.execute_command(Some(0), "go example.com");
+        assert!(output.is_success());
+        assert_eq!(vec![String::from("example.com")], *ui.loaded_uris.borrow());
+        assert_eq!(String::new(), ui.command_field_text(0));
+    }
+
+    #[test]
+    fn execute_nested_command_output() {
+        let ui = create_ui("execute-nested", "", &[("outer.lua", r#"
+            function run()
+                local success, message = run_command(0, "missing")
+                set_command_field_text(0, tostring(success) .. ":" .. message)
+                return false
+            end
+        "#)]);
+        let output = ui.execute_command(Some(0), "outer");
+        assert!(output.error.is_none());
+        assert!(!output.is_success());
+        assert_eq!(String::from("false:No command found matching 'missing'"),
+                   ui.command_field_text(0));
+    }
+
+    #[test]
+    fn execute_help_summary() {
+        let ui = create_ui("help-summary", r#"
+            [commands.aliases]
+            h = "help"
+        "#, &[("visit.lua", r#"
+            function description()
+                return "Visit a page"
+            end
+        "#)]);
+        let output = ui.execute_command(Some(0), "h visit");
+        assert!(output.is_success());
+        assert_eq!(String::from("visit: Visit a page"), ui.command_field_text(0));
+        let output = ui.execute_command(Some(0), "help missing");
+        assert_eq!(Some(CommandError::InvalidArguments), output.error);
+    }
+
+    #[test]
+    fn execute_builtin_command() {
+        let ui = create_ui("builtin", "", &[]);
+        let output = ui.execute_command(Some(0), "go example.com");
+        assert!(output.is_success());
+        assert_eq!(vec![String::from("example.com")], *ui.loaded_uris.borrow());
+        let output = ui.execute_command(Some(0), "go");
+        assert_eq!(Some(CommandError::InvalidArguments), output.error);
+    }
+
+    #[test]
+    fn execute_builtin_in_invoking_window() {
+        let ui = create_ui("builtin-window", "", &[("close-later.lua", r#"
+            function run()
+                run_command(1, "close")
+                set_command_field_text(3, tostring(set_timeout(10, "close")))
+                return true
+            end
+        "#)]);
+        assert!(ui.execute_command(Some(2), "close").is_success());
+        assert!(ui.execute_command(Some(2), "close-later").is_success());
+        let id: u32 = ui.command_field_text(3).parse().unwrap();
+        ui.engine.on_timer::<MockUI, LuaEngine>(&ui, id);
+        assert_eq!(vec![2, 1, 2], *ui.closed_windows.borrow());
+    }
+
+    #[test]
+    fn script_overrides_builtin_command() {
+        let ui = create_ui("builtin-override", "", &[("go.lua", r#"
+            function run()
+                return false
+            end
+        "#)]);
+        let output = ui.execute_command(Some(0), "go example.com");
+        assert!(!output.is_success());
+        assert!(ui.loaded_uris.borrow().is_empty());
+    }
+
+    #[test]
+    fn complete_builtin_arguments() {
+        let ui = create_ui("builtin-complete", "", &[]);
+        let completions = complete_values(&ui, "reload f");
+        assert_eq!(vec!["force"], completions);
+        let completions = complete_values(&ui, "help buffern");
+        assert_eq!(vec!["buffernew", "buffernext"], completions);
+    }
+
+    #[test]
+    #[allow(unused_must_use)]
+    fn execute_modified_script() {
+        let ui = create_ui("modified-script", "", &[("toggle.lua", r#"
+            function run()
+                return true
+            end
+        "#)]);
+        assert!(ui.execute_command(Some(0), "toggle").is_success());
+        assert_eq!(1, ui.engine.scripts().len());
+        let mut file = File::create(ui.file_path("toggle.lua")).ok().unwrap();
+        file.write_all(b"function run() return false end");
+        assert!(!ui.execute_command(Some(0), "toggle").is_success());
+    }
+
+    #[test]
+    fn complete_command_names() {
+        let ui = create_ui("complete-names", r#"
+            [commands.aliases]
+            bn = "buffernext"
+            [commands.history]
+            strategy = "all"
+        "#, &[("bufferinfo.lua", "function run() return true end")]);
+        let completions = complete_values(&ui, "bn");
+        assert_eq!(vec!["bn", "buffernew", "buffernext", "bufferinfo"], completions);
+        ui.engine.submit_command::<MockUI, LuaEngine>(&ui, Some(0), "bufferinfo");
+        let completions = complete_values(&ui, "bn");
+        assert_eq!(vec!["bn", "bufferinfo", "buffernew", "buffernext"], completions);
+    }
+
+    #[test]
+    fn complete_subcommand_names() {
+        let ui = create_ui("complete-subcommands", r#"
+            [commands.aliases]
+            bm = "bookmark"
+        "#, &[
+            ("bookmark/save.lua", r#"function description() return "Save a bookmark" end"#),
+            ("bookmark/open.lua", "function run() return true end"),
+            ("bookmark/tag/add.lua", "function run() return true end"),
+        ]);
+        assert_eq!(vec!["bookmark"], complete_values(&ui, "bookma"));
+        assert_eq!(vec!["open", "save", "tag"], complete_values(&ui, "bookmark "));
+        assert_eq!(vec!["save"], complete_values(&ui, "bm sa"));
+        assert_eq!(vec!["add"], complete_values(&ui, "bookmark tag "));
+        let completions = ui.engine.command_completions::<MockUI, LuaEngine>(&ui, "bookmark sa");
+        assert_eq!(Some(String::from("Save a bookmark")), completions[0].description);
+    }
+
+    #[test]
+    fn describe_placeholder_aliases() {
+        let ui = create_ui("placeholder-aliases", r#"
+            [commands.aliases]
+            gh = "go https://github.com/$1"
+            bt = "bookmark tag"
+        "#, &[("bookmark/tag/add.lua", "function run() return true end")]);
+        assert!(ui.execute_command(Some(0), "help gh").is_success());
+        assert_eq!(String::from("go: Open a web page (aliases: gh)"), ui.command_field_text(0));
+        let completions = ui.engine.command_completions::<MockUI, LuaEngine>(&ui, "gh");
+        assert_eq!(Some(String::from("gh")), completions.first().map(|c| c.value.clone()));
+        assert_eq!(Some(String::from("Open a web page")), completions[0].description);
+        assert!(complete_values(&ui, "gh ").is_empty());
+        assert_eq!(vec!["add"], complete_values(&ui, "bt "));
+    }
+
+    #[test]
+    fn execute_subcommand() {
+        let ui = create_ui("execute-subcommand", "", &[
+            ("bookmark.lua", "function run() return #arguments == 1 end"),
+            ("bookmark/save.lua", "function run() return #arguments == 0 end"),
+        ]);
+        assert!(ui.execute_command(Some(0), "bookmark save").is_success());
+        assert!(ui.execute_command(Some(0), "bookmark list").is_success());
+        assert!(!ui.execute_command(Some(0), "bookmark").is_success());
+    }
+
+    #[test]
+    fn validate_script_arguments() {
+        let ui = create_ui("validate-arguments", "", &[
+            ("buffer.lua", r#"
+                function argument_spec() return "index:int" end
+                function run() return tonumber(arguments[1]) > 0 end
+            "#),
+            ("broken.lua", r#"
+                function argument_spec() return "[a] b" end
+                function run() return true end
+            "#),
+        ]);
+        assert!(ui.execute_command(Some(0), "buffer 2").is_success());
+        let output = ui.execute_command(Some(0), "buffer two");
+        assert_eq!(Some(CommandError::InvalidArguments), output.error);
+        assert_eq!(Some(String::from("'index' must be a whole number, not 'two'. Usage: buffer index:int")),
+                   output.message);
+        let output = ui.execute_command(Some(0), "buffer");
+        assert_eq!(Some(CommandError::InvalidArguments), output.error);
+        let output = ui.execute_command(Some(0), "broken x");
+        assert_eq!(Some(CommandError::ErrorDuringExecution), output.error);
+    }
+
+    #[test]
+    fn complete_enum_arguments() {
+        let ui = create_ui("complete-enum", "", &[
+            ("reading-mode.lua", r#"
+                function argument_spec() return "mode:on|off|toggle [size:int]" end
+                function complete_command() return {"fallback"} end
+                function run() return true end
+            "#),
+        ]);
+        assert_eq!(vec!["on", "off"], complete_values(&ui, "reading-mode o"));
+        assert_eq!(vec!["on", "off", "toggle"], complete_values(&ui, "reading-mode "));
+        assert_eq!(vec!["fallback"], complete_values(&ui, "reading-mode on "));
+    }
+
+    #[test]
+    fn complete_script_arguments() {
+        let ui = create_ui("complete-script", "", &[
+            ("titles.lua", r#"
+                function complete_command()
+                    return {"one, two", {label="Three", value="3", description="Third"}, {value="4"}}
+                end
+            "#),
+            ("joined.lua", r#"
+                function complete_command()
+                    return "open,save"
+                end
+            "#)]);
+        let completions = ui.engine.command_completions::<MockUI, LuaEngine>(&ui, "titles ");
+        assert_eq!(vec![
+            Completion::new("one, two"),
+            Completion { label: String::from("Three"), value: String::from("3"),
+                         description: Some(String::from("Third")) },
+            Completion::new("4"),
+        ], completions);
+        assert_eq!(vec!["open", "save"], complete_values(&ui, "joined "));
+        let completions = ui.engine.command_completions::<MockUI, LuaEngine>(&ui, "he");
+        assert_eq!(Some(String::from("help")), completions.first().map(|c| c.value.clone()));
+        assert!(completions[0].description.is_some());
     }
 }
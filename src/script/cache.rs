@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::fs::{File,metadata};
+use std::io::{self,Read};
+use std::path::Path;
+use std::sync::{Arc,Mutex};
+use std::time::{Duration,SystemTime};
+
+use super::{ExecutionLimits,ScriptError};
+
+/// The contents of a command file along with details learned from evaluating
+/// it, valid until the file is modified
+#[derive(Debug)]
+pub struct Script {
+    path: String,
+    source: String,
+    modified: Option<SystemTime>,
+    size: u64,
+    description: Mutex<Option<String>>,
+    description_error: Mutex<Option<ScriptError>>,
+    argument_spec: Mutex<Option<Option<String>>>,
+    entry_points: Mutex<Option<Vec<String>>>,
+    limits: ExecutionLimits,
+    library_paths: Vec<String>,
+    chunk: Mutex<Option<Arc<Vec<u8>>>>,
+}
+
+impl Script {
+
+    /// Create a script from source text which is not read from a file
+    pub fn new<P: Into<String>, S: Into<String>>(path: P, source: S) -> Self {
+        let source = source.into();
+        Script {
+            path: path.into(),
+            size: source.len() as u64,
+            source,
+            modified: None,
+            description: Mutex::new(None),
+            description_error: Mutex::new(None),
+            argument_spec: Mutex::new(None),
+            entry_points: Mutex::new(None),
+            limits: ExecutionLimits::default(),
+            library_paths: vec![],
+            chunk: Mutex::new(None),
+        }
+    }
+
+    /// Read a script from a file. Invalid UTF-8 sequences are replaced in the
+    /// source text. Files without a file extension are run as executables
+    /// rather than evaluated, so their contents are not read.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let info = metadata(path)?;
+        let mut contents: Vec<u8> = vec![];
+        if Path::new(path).extension().is_some() {
+            File::open(path)?.read_to_end(&mut contents)?;
+        }
+        let mut script = Script::new(path, String::from_utf8_lossy(&contents).into_owned());
+        script.modified = info.modified().ok();
+        script.size = info.len();
+        Ok(script)
+    }
+
+    /// The path of the command file
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The source text of the command file
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The limits on evaluating the script
+    pub fn limits(&self) -> &ExecutionLimits {
+        &self.limits
+    }
+
+    /// The directories searched for modules required by the script
+    pub fn library_paths(&self) -> &[String] {
+        &self.library_paths
+    }
+
+    /// The source text compiled by a scripting engine, if previously
+    /// compiled, so the script can be evaluated without parsing it again
+    pub fn chunk(&self) -> Option<Arc<Vec<u8>>> {
+        self.chunk.lock().ok().and_then(|chunk| chunk.clone())
+    }
+
+    /// Save the compiled form of the source text
+    pub fn set_chunk(&self, chunk: Vec<u8>) -> Arc<Vec<u8>> {
+        let chunk = Arc::new(chunk);
+        if let Ok(mut value) = self.chunk.lock() {
+            *value = Some(chunk.clone());
+        }
+        chunk
+    }
+
+    /// The description of the command, if previously evaluated
+    pub fn description(&self) -> Option<String> {
+        self.description.lock().ok().and_then(|description| description.clone())
+    }
+
+    /// Save the result of evaluating the description of the command
+    pub fn set_description(&self, description: &str) {
+        if let Ok(mut value) = self.description.lock() {
+            *value = Some(String::from(description));
+        }
+    }
+
+    /// The error from evaluating the description of the command, if it failed
+    pub fn description_error(&self) -> Option<ScriptError> {
+        self.description_error.lock().ok().and_then(|err| err.clone())
+    }
+
+    /// Save the failure to evaluate the description of the command, so the
+    /// script is not evaluated again until the file changes
+    pub fn set_description_error(&self, err: ScriptError) {
+        if let Ok(mut value) = self.description_error.lock() {
+            *value = Some(err);
+        }
+    }
+
+    /// The argument spec declared by the command, or `None` if the script has
+    /// not yet been evaluated
+    pub fn argument_spec(&self) -> Option<Option<String>> {
+        self.argument_spec.lock().ok().and_then(|spec| spec.clone())
+    }
+
+    /// Save the argument spec found when evaluating the script, if any
+    pub fn set_argument_spec(&self, spec: Option<&str>) {
+        if let Ok(mut value) = self.argument_spec.lock() {
+            *value = Some(spec.map(String::from));
+        }
+    }
+
+    /// Whether the script defines an entry point, or `None` if the script has
+    /// not yet been evaluated
+    pub fn defines(&self, entry_point: &str) -> Option<bool> {
+        self.entry_points.lock().ok()
+            .and_then(|entry_points| entry_points.as_ref()
+                      .map(|names| names.iter().any(|name| name == entry_point)))
+    }
+
+    /// Whether the entry points defined by the script are known
+    pub fn has_entry_points(&self) -> bool {
+        self.entry_points.lock().map(|names| names.is_some()).unwrap_or(false)
+    }
+
+    /// Save the names of entry points found when evaluating the script
+    pub fn set_entry_points(&self, names: Vec<String>) {
+        if let Ok(mut entry_points) = self.entry_points.lock() {
+            *entry_points = Some(names);
+        }
+    }
+
+    /// Whether the file has changed since the script was read
+    fn is_stale(&self) -> bool {
+        match metadata(&self.path) {
+            Ok(info) => info.modified().ok() != self.modified || info.len() != self.size,
+            Err(_) => true,
+        }
+    }
+}
+
+/// Command files read from disk, keyed by path and reread when the
+/// modification time of the file changes
+///
+/// ## Examples
+///
+/// ```no_run
+/// use webkitten::script::ScriptCache;
+///
+/// let cache = ScriptCache::new();
+/// let script = cache.load("/usr/local/share/webkitten/go.lua").unwrap();
+/// assert!(script.source().contains("function run"));
+/// ```
+#[derive(Debug)]
+pub struct ScriptCache {
+    scripts: Mutex<HashMap<String, Arc<Script>>>,
+    limits: Mutex<ExecutionLimits>,
+    library_paths: Mutex<Vec<String>>,
+}
+
+impl Default for ScriptCache {
+
+    fn default() -> Self {
+        ScriptCache::new()
+    }
+}
+
+impl ScriptCache {
+
+    pub fn new() -> Self {
+        ScriptCache {
+            scripts: Mutex::new(HashMap::new()),
+            limits: Mutex::new(ExecutionLimits::default()),
+            library_paths: Mutex::new(vec![]),
+        }
+    }
+
+    /// The script at a path, reading the file if it has not been read before
+    /// or has been modified since it was last read
+    pub fn load(&self, path: &str) -> io::Result<Arc<Script>> {
+        if let Ok(scripts) = self.scripts.lock() {
+            if let Some(script) = scripts.get(path) {
+                if !script.is_stale() {
+                    return Ok(script.clone());
+                }
+            }
+        }
+        let mut script = Script::open(path)?;
+        script.limits = self.limits();
+        script.library_paths = self.library_paths.lock().map(|paths| paths.clone()).unwrap_or_default();
+        let script = Arc::new(script);
+        if let Ok(mut scripts) = self.scripts.lock() {
+            scripts.insert(String::from(path), script.clone());
+        }
+        Ok(script)
+    }
+
+    /// The limits on evaluating scripts loaded from the cache
+    pub fn limits(&self) -> ExecutionLimits {
+        self.limits.lock().map(|limits| limits.clone()).unwrap_or_default()
+    }
+
+    /// Change the limits on evaluating scripts, removing cached scripts so
+    /// that they are loaded again with the new limits
+    pub fn set_limits(&self, time_limit: Option<Duration>, instruction_limit: Option<u64>) {
+        if let Ok(mut limits) = self.limits.lock() {
+            *limits = limits.with_limits(time_limit, instruction_limit);
+        }
+        self.clear();
+    }
+
+    /// Change the directories searched for modules required by scripts,
+    /// removing cached scripts so that they are loaded again with the new
+    /// paths
+    pub fn set_library_paths(&self, paths: Vec<String>) {
+        if let Ok(mut library_paths) = self.library_paths.lock() {
+            *library_paths = paths;
+        }
+        self.clear();
+    }
+
+    /// Set the function called periodically while scripts loaded from the
+    /// cache run, which cancels them when it returns true
+    pub fn set_interrupt_check(&self, check: fn() -> bool) {
+        if let Ok(mut limits) = self.limits.lock() {
+            limits.interrupt_check = Some(check);
+        }
+        self.clear();
+    }
+
+    /// Stop any scripts loaded from the cache which are currently running
+    pub fn cancel(&self) {
+        self.limits().cancel();
+    }
+
+    /// The number of cached scripts
+    pub fn len(&self) -> usize {
+        self.scripts.lock().map(|scripts| scripts.len()).unwrap_or(0)
+    }
+
+    /// Whether no scripts are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all cached scripts
+    pub fn clear(&self) {
+        if let Ok(mut scripts) = self.scripts.lock() {
+            scripts.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use tests::TestDir;
+
+    fn write_script(dir: &TestDir, name: &str, contents: &str) -> String {
+        String::from(dir.write(name, contents).to_str().unwrap())
+    }
+
+    #[test]
+    fn reuse_unmodified_script() {
+        let dir = TestDir::new("cache-reuse");
+        let path = write_script(&dir, "reuse.lua", "function run() return true end");
+        let cache = ScriptCache::new();
+        let first = cache.load(&path).unwrap();
+        first.set_entry_points(vec![String::from("run")]);
+        let second = cache.load(&path).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(Some(true), second.defines("run"));
+        assert_eq!(Some(false), second.defines("description"));
+    }
+
+    #[test]
+    fn reload_modified_script() {
+        let dir = TestDir::new("cache-modified");
+        let path = write_script(&dir, "modified.lua", "function run() return true end");
+        let cache = ScriptCache::new();
+        let first = cache.load(&path).unwrap();
+        first.set_description("Run");
+        write_script(&dir, "modified.lua", "function run() return false end -- changed");
+        let second = cache.load(&path).unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert!(second.source().contains("changed"));
+        assert_eq!(None, second.description());
+        assert!(!second.has_entry_points());
+        assert_eq!(1, cache.len());
+    }
+
+    #[test]
+    fn skip_executable_contents() {
+        let dir = TestDir::new("cache-executable");
+        let path = write_script(&dir, "bookmark", "#!/bin/sh\necho");
+        let script = ScriptCache::new().load(&path).unwrap();
+        assert_eq!("", script.source());
+        write_script(&dir, "bookmark", "#!/bin/sh\necho changed");
+        assert!(script.is_stale());
+    }
+
+    #[test]
+    fn load_missing_script() {
+        let cache = ScriptCache::new();
+        assert!(cache.load("/nonexistent/webkitten/script.lua").is_err());
+        assert_eq!(0, cache.len());
+    }
+}
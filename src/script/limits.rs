@@ -0,0 +1,175 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize,Ordering};
+use std::time::Duration;
+
+/// The shortest time between calls to the interrupt check of running scripts
+pub const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Limits on evaluating scripts, shared by the scripts loaded from a cache so
+/// that running scripts can be cancelled from elsewhere
+#[derive(Debug,Clone)]
+pub struct ExecutionLimits {
+    /// The longest time a script may run before it is stopped
+    pub time_limit: Option<Duration>,
+    /// The largest number of instructions a script may run before it is
+    /// stopped
+    pub instruction_limit: Option<u64>,
+    /// A function called periodically while scripts run, cancelling them
+    /// when it returns true. The UI uses it to handle input which requests
+    /// cancellation, since the input is otherwise handled only once the
+    /// running scripts finish.
+    pub interrupt_check: Option<fn() -> bool>,
+    cancellations: Arc<AtomicUsize>,
+}
+
+impl ExecutionLimits {
+
+    pub fn new(time_limit: Option<Duration>, instruction_limit: Option<u64>) -> Self {
+        ExecutionLimits {
+            time_limit,
+            instruction_limit,
+            interrupt_check: None,
+            cancellations: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Limits sharing the interrupt check and cancellation requests of these
+    /// limits
+    pub fn with_limits(&self, time_limit: Option<Duration>, instruction_limit: Option<u64>) -> Self {
+        ExecutionLimits {
+            time_limit,
+            instruction_limit,
+            interrupt_check: self.interrupt_check,
+            cancellations: self.cancellations.clone(),
+        }
+    }
+
+    /// Stop all scripts which are currently running. Scripts started
+    /// afterwards are unaffected.
+    pub fn cancel(&self) {
+        self.cancellations.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Stop all scripts which are currently running if the interrupt check
+    /// requests it, returning whether it did
+    pub fn poll_interrupt(&self) -> bool {
+        let interrupted = self.interrupt_check.map(|check| check()).unwrap_or(false);
+        if interrupted {
+            self.cancel();
+        }
+        interrupted
+    }
+
+    /// A marker for the cancellation requests made so far, to be compared
+    /// with `is_cancelled_since`
+    pub fn cancellation_marker(&self) -> usize {
+        self.cancellations.load(Ordering::SeqCst)
+    }
+
+    /// Whether running scripts have been cancelled since a marker was taken
+    pub fn is_cancelled_since(&self, marker: usize) -> bool {
+        self.cancellations.load(Ordering::SeqCst) != marker
+    }
+}
+
+impl Default for ExecutionLimits {
+
+    fn default() -> Self {
+        ExecutionLimits::new(None, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use super::*;
+    use tests::create_ui;
+    use ui::{ApplicationUI,CommandError};
+
+    #[test]
+    fn cancel_after_marker() {
+        let limits = ExecutionLimits::default();
+        let marker = limits.cancellation_marker();
+        assert!(!limits.is_cancelled_since(marker));
+        limits.with_limits(None, Some(10)).cancel();
+        assert!(limits.is_cancelled_since(marker));
+        assert!(!limits.is_cancelled_since(limits.cancellation_marker()));
+    }
+
+    #[test]
+    fn cancel_on_interrupt() {
+        fn interrupt() -> bool { true }
+        let mut limits = ExecutionLimits::default();
+        let marker = limits.cancellation_marker();
+        assert!(!limits.poll_interrupt());
+        limits.interrupt_check = Some(interrupt);
+        assert!(limits.with_limits(None, None).poll_interrupt());
+        assert!(limits.is_cancelled_since(marker));
+    }
+
+    #[test]
+    fn execute_exceeding_time_limit() {
+        let ui = create_ui("time-limit", "time-limit = 50", &[("spin.lua", r#"
+            function run()
+                while true do end
+            end
+        "#)]);
+        let output = ui.execute_command(Some(0), "spin");
+        assert_eq!(Some(CommandError::ErrorDuringExecution), output.error);
+        assert!(output.message.unwrap().contains("script exceeded the time limit"));
+    }
+
+    #[test]
+    fn execute_handling_time_limit() {
+        let ui = create_ui("time-limit-pcall", "instruction-limit = 100000", &[("spin.lua", r#"
+            function run()
+                while true do
+                    pcall(function() while true do end end)
+                end
+            end
+        "#)]);
+        let output = ui.execute_command(Some(0), "spin");
+        assert_eq!(Some(CommandError::ErrorDuringExecution), output.error);
+        assert!(output.message.unwrap().contains("script exceeded the instruction limit"));
+    }
+
+    #[test]
+    fn execute_cancelled_command() {
+        let ui = create_ui("cancel", "time-limit = 0", &[("spin.lua", r#"
+            function run()
+                run_command(0, "cancel")
+                while true do end
+            end
+        "#), ("go.lua", r#"
+            function run()
+                return true
+            end
+        "#)]);
+        let output = ui.execute_command(Some(0), "spin");
+        assert_eq!(Some(CommandError::ErrorDuringExecution), output.error);
+        assert!(output.message.unwrap().contains("script was cancelled"));
+        assert!(ui.execute_command(Some(0), "go").is_success());
+    }
+
+    #[test]
+    fn execute_interrupted_command() {
+        static PRESSED: AtomicBool = AtomicBool::new(false);
+        fn cancel_pressed() -> bool {
+            PRESSED.swap(false, Ordering::SeqCst)
+        }
+        let ui = create_ui("interrupt", "time-limit = 0", &[("spin.lua", r#"
+            function run()
+                while true do end
+            end
+        "#), ("go.lua", r#"
+            function run()
+                return true
+            end
+        "#)]);
+        ui.engine.set_interrupt_check(cancel_pressed);
+        PRESSED.store(true, Ordering::SeqCst);
+        let output = ui.execute_command(Some(0), "spin");
+        assert!(output.message.unwrap().contains("script was cancelled"));
+        assert!(ui.execute_command(Some(0), "go").is_success());
+    }
+}
@@ -1,145 +1,818 @@
 extern crate hlua;
+extern crate lua52_sys as ffi;
 
-use std::error::Error;
-use std::fs::File;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::os::raw::{c_char,c_int,c_void};
+use std::slice;
+use std::sync::Arc;
+use std::time::{Duration,Instant};
 
-use self::hlua::{Lua,LuaError,function0,function1,function2,function3};
+use serde_json::{self,Value};
+
+use self::hlua::{AsMutLua,Lua,LuaError,LuaRead,LuaTable,function0,function1,function2,function3,function4};
 use self::hlua::any::AnyLuaValue;
 use self::hlua::functions_read::LuaFunction;
 
-use ui::{ApplicationUI,BrowserConfiguration,BufferEvent,WindowArea};
-use config::Config;
+use ui::{ApplicationUI,BrowserConfiguration,BufferEvent,Completion,JavaScriptResult,WindowArea};
+use timer::TimerOwner;
+use config::{Config,ConfigError};
 
-use super::{ScriptingEngine,ScriptError,ScriptResult,NOT_FOUND};
+use super::limits::INTERRUPT_POLL_INTERVAL;
+use super::{ExecutionLimits,Invocation,SandboxProfile,Script,ScriptingEngine,ScriptError,ScriptErrorKind,ScriptResult,NOT_FOUND};
 
 #[allow(dead_code)]
 pub struct LuaEngine;
 
 const FILE_EXTENSION: &'static str = "lua";
 
+/// Deepest nesting of arrays and objects in a JavaScript result converted to
+/// tables, matching the limit of the JSON parser
+const MAX_JSON_DEPTH: usize = 128;
+
+/// Number of instructions run between checks of the execution limits
+const LIMIT_CHECK_INTERVAL: i32 = 1000;
+
+thread_local! {
+    /// Budgets of Lua states with execution limits on this thread, keyed by
+    /// the address of the state
+    static BUDGETS: RefCell<HashMap<usize, Budget>> = RefCell::new(HashMap::new());
+}
+
+/// Resources used by a Lua state compared to its execution limits
+struct Budget {
+    limits: ExecutionLimits,
+    started: Instant,
+    polled: Instant,
+    cancellation_marker: usize,
+    instructions: u64,
+    exceeded: Option<(ScriptErrorKind, &'static [u8])>,
+}
+
+/// Details of the command or event a runtime is created for
+struct RuntimeContext {
+    config_path: String,
+    profile: SandboxProfile,
+    /// The store namespace of the command
+    namespace: String,
+    /// Whether the script runs for a private webview, keeping stored values
+    /// in memory only
+    private: bool,
+    /// The window or buffer owning timers created by the script
+    timer_owner: Option<TimerOwner>,
+}
+
+/// Stops evaluation in a Lua state which exceeds its execution limits until
+/// dropped
+struct LimitGuard {
+    state: usize,
+    /// The path of the script, recorded in errors
+    path: String,
+}
+
+/// Completions returned by `complete_command()`, which may be a
+/// comma-delimited string, a table of strings or of
+/// `{label=, value=, description=}` records, or `nil`
+struct CompletionResult(Vec<Completion>);
+
+/// A table of strings passed to a browser function, such as the values given
+/// to `set_strings()`
+struct StringList(Vec<String>);
+
+/// Removes standard library functions which access files, processes, or the
+/// environment, keeping the functions of `os` which read the time. `load()`
+/// only accepts source text, as precompiled chunks can escape the sandbox.
+const RESTRICT_LIBRARIES: &str = r#"
+os = {clock = os.clock, date = os.date, difftime = os.difftime, time = os.time}
+io, package, debug, require, dofile, loadfile = nil, nil, nil, nil, nil, nil
+local load_text = load
+load = function(chunk, name, mode, env)
+  return load_text(chunk, name, "t", env or _G)
+end
+"#;
+
+/// Replaces `require()` in restricted runtimes with a loader which only reads
+/// Lua source files in the library paths. Modules are evaluated using the
+/// restricted `load()` and cached for the lifetime of the runtime.
+const MODULE_LOADER: &str = r#"
+local loaded, read_module, load_text = {}, __read_module, load
+__read_module = nil
+function require(name)
+  name = tostring(name)
+  if loaded[name] == nil then
+    local path, source = read_module(name)
+    if path == "" then
+      error("module '" .. name .. "' not found in library paths", 2)
+    end
+    local chunk = assert(load_text(source, "@" .. path))
+    local result = chunk(name, path)
+    if result == nil then
+      result = true
+    end
+    loaded[name] = result
+  end
+  return loaded[name]
+end
+"#;
+
+/// Allows the configuration lookup functions to be called with the path of
+/// the configuration file before the key, as in earlier versions
+const CONFIG_LOOKUPS: &str = r#"
+for _, name in ipairs({"lookup_bool", "lookup_string", "lookup_strings"}) do
+  local lookup = _G[name]
+  _G[name] = function(path_or_key, key)
+    return lookup(key or path_or_key)
+  end
+end
+"#;
+
+/// Functions changing configuration options, normalizing arguments before
+/// calling the browser functions
+const CONFIG_SETTERS: &str = r#"
+function set_bool(key, value, persist)
+  return __set_config_bool(key, value == true, persist == true)
+end
+function set_string(key, value, persist)
+  return __set_config_string(key, tostring(value), persist == true)
+end
+function set_strings(key, values, persist)
+  local items = {}
+  for index, value in ipairs(values) do
+    items[index] = tostring(value)
+  end
+  return __set_config_strings(key, items, persist == true)
+end
+"#;
+
+/// Evaluates JavaScript, passing the result to the `on_javascript_result`
+/// event trigger of the calling command unless another command is given
+const JAVASCRIPT_EVALUATION: &str = r#"
+function evaluate_javascript(window_index, webview_index, script, command)
+  __evaluate_javascript(window_index, webview_index, script, command or "")
+end
+"#;
+
+/// Version of the functions in the `webkitten` table, increased when functions
+/// are added or changed
+const API_VERSION: u32 = 1;
+
+/// Collects the browser functions defined as globals into the `webkitten`
+/// table, grouped by what they act on. Functions unavailable in the sandbox
+/// profile are absent from the table. The globals are removed unless
+/// `__legacy_globals` is set.
+const API_NAMESPACE: &str = r#"
+local legacy_globals = __legacy_globals
+__legacy_globals = nil
+local globals = {
+  [""] = {NOT_FOUND = "NOT_FOUND", copy = "copy"},
+  buffer = {
+    count = "webview_count", focused_index = "focused_webview_index",
+    uri = "webview_uri", title = "webview_title", open = "open_webview",
+    open_custom = "open_custom_webview", close = "close_webview",
+    focus = "focus_webview", reload = "reload_webview", load_uri = "load_uri",
+    go_back = "go_back", go_forward = "go_forward", find = "find",
+    hide_find = "hide_find", run_javascript = "run_javascript",
+    evaluate_javascript = "evaluate_javascript", add_styles = "add_styles",
+  },
+  command = {
+    run = "run_command", history = "command_history",
+    history_match = "command_history_match",
+  },
+  config = {
+    path = "config_file_path", lookup_bool = "lookup_bool",
+    lookup_integer = "lookup_integer", lookup_string = "lookup_string",
+    lookup_strings = "lookup_strings", lookup_table = "lookup_table",
+    lookup_site_bool = "lookup_site_bool",
+    lookup_site_string = "lookup_site_string",
+    lookup_site_strings = "lookup_site_strings", set_bool = "set_bool",
+    set_string = "set_string", set_strings = "set_strings",
+  },
+  log = {info = "log_info", debug = "log_debug"},
+  store = {
+    get = "store_get", keys = "store_keys", set = "store_set",
+    delete = "store_delete",
+  },
+  timer = {
+    set_timeout = "set_timeout", set_interval = "set_interval",
+    clear = "clear_timer",
+  },
+  window = {
+    count = "window_count", focused_index = "focused_window_index",
+    title = "window_title", set_title = "set_window_title",
+    open = "open_window", open_custom = "open_custom_window",
+    close = "close_window", focus = "focus_window", hide = "hide_window",
+    show = "show_window", resize = "resize_window",
+    focus_webview = "focus_webview_in_window",
+    focus_command_bar = "focus_commandbar_in_window",
+    command_text = "command_field_text",
+    set_command_text = "set_command_field_text",
+    command_visible = "command_field_visible",
+    set_command_visible = "set_command_field_visible",
+  },
+}
+webkitten = {api_version = __api_version}
+__api_version = nil
+for module, names in pairs(globals) do
+  local functions = webkitten
+  if module ~= "" then
+    functions = {}
+    webkitten[module] = functions
+  end
+  for name, global in pairs(names) do
+    functions[name] = _G[global]
+    if not legacy_globals then
+      _G[global] = nil
+    end
+  end
+end
+"#;
+
+/// The start of the location in error messages from chunks evaluated by hlua
+const CHUNK_PREFIX: &str = "[string \"chunk\"]:";
+
+/// Event triggers which may be defined by a script
+const ENTRY_POINTS: &[&str] = &["argument_spec", "complete_command", "description",
+    "on_fail_uri", "on_focus", "on_javascript_result", "on_load_uri", "on_request_uri", "run"];
+
 impl ScriptingEngine for LuaEngine {
 
     fn file_extension() -> &'static str {
         FILE_EXTENSION
     }
 
-    fn describe(file: File) -> ScriptResult<String> {
-        let mut lua = Lua::new();
-        if let Err(err) = lua.execute_from_reader::<(), _>(file) {
-            Err(lua_to_script_error("script parsing failed", Some(err)))
+    fn describe(script: &Script) -> ScriptResult<String> {
+        if let Some(description) = script.description() {
+            return Ok(description);
+        }
+        check_entry_point(script, "description")?;
+        let mut lua = create_declaration_runtime(script)?;
+        let guard = LimitGuard::new(&mut lua, script);
+        evaluate_declarations(&mut lua, &guard, script)?;
+        let run: Option<LuaFunction<_>> = lua.get("description");
+        if let Some(mut run) = run {
+            let description = guard.resolve(resolve_script_output::<String>(run.call()))?;
+            script.set_description(&description);
+            Ok(description)
         } else {
-            let run: Option<LuaFunction<_>> = lua.get("description");
-            if let Some(mut run) = run {
-                resolve_script_output::<String>(run.call())
-            } else {
-                Err(lua_to_script_error("'description' method missing", None))
-            }
+            Err(missing_entry_point(script, "description"))
+        }
+    }
+
+    fn argument_spec(script: &Script) -> ScriptResult<Option<String>> {
+        if let Some(spec) = script.argument_spec() {
+            return Ok(spec);
+        }
+        if script.defines("argument_spec") == Some(false) {
+            script.set_argument_spec(None);
+            return Ok(None);
         }
+        let mut lua = create_declaration_runtime(script)?;
+        let guard = LimitGuard::new(&mut lua, script);
+        evaluate_declarations(&mut lua, &guard, script)?;
+        let spec: Option<LuaFunction<_>> = lua.get("argument_spec");
+        let spec = match spec {
+            Some(mut spec) => Some(guard.resolve(resolve_script_output::<String>(spec.call()))?),
+            None => None,
+        };
+        script.set_argument_spec(spec.as_deref());
+        Ok(spec)
     }
 
-    fn execute<T, S>(file: File, arguments: Vec<String>, ui: &T, config_path: &str) -> ScriptResult<bool>
+    fn execute<T, S>(script: &Script, arguments: Vec<String>, ui: &T, invocation: &Invocation) -> ScriptResult<bool>
         where T: ApplicationUI<S>,
               S: ScriptingEngine {
-        let mut lua = create_runtime::<T, S>(ui, config_path.to_owned());
+        check_entry_point(script, "run")?;
+        let context = RuntimeContext::command::<T, S>(ui, invocation);
+        let mut lua = create_runtime::<T, S>(ui, context)?;
+        let guard = LimitGuard::new(&mut lua, script);
         lua.set("arguments", arguments);
-        if let Err(err) = lua.execute_from_reader::<(), _>(file) {
-            Err(lua_to_script_error("script parsing failed", Some(err)))
+        guard.resolve(evaluate(&mut lua, script))?;
+        let run: Option<LuaFunction<_>> = lua.get("run");
+        if let Some(mut run) = run {
+            guard.resolve(resolve_script_output::<bool>(run.call()))
         } else {
-            let run: Option<LuaFunction<_>> = lua.get("run");
-            if let Some(mut run) = run {
-                resolve_script_output::<bool>(run.call())
-            } else {
-                Err(lua_to_script_error("'run' method missing", None))
-            }
+            Err(missing_entry_point(script, "run"))
         }
     }
 
-    fn autocomplete<T, S>(file: File, arguments: Vec<String>, prefix: &str, ui: &T, config_path: &str) -> ScriptResult<Vec<String>>
+    fn autocomplete<T, S>(script: &Script, arguments: Vec<String>, prefix: &str, ui: &T, invocation: &Invocation) -> ScriptResult<Vec<Completion>>
         where T: ApplicationUI<S>,
               S: ScriptingEngine {
-        let mut lua = create_runtime::<T, S>(ui, config_path.to_owned());
+        check_entry_point(script, "complete_command")?;
+        let context = RuntimeContext::command::<T, S>(ui, invocation);
+        let mut lua = create_runtime::<T, S>(ui, context)?;
+        let guard = LimitGuard::new(&mut lua, script);
         lua.set("prefix", prefix);
         lua.set("arguments", arguments);
-        if let Err(err) = lua.execute_from_reader::<(), _>(file) {
-            Err(lua_to_script_error("script parsing failed", Some(err)))
+        guard.resolve(evaluate(&mut lua, script))?;
+        let complete: Option<LuaFunction<_>> = lua.get("complete_command");
+        if let Some(mut complete) = complete {
+            guard.resolve(resolve_script_output::<CompletionResult>(complete.call()))
+                .map(|result| result.0)
         } else {
-            let complete: Option<LuaFunction<_>> = lua.get("complete_command");
-            if let Some(mut complete) = complete {
-                resolve_script_output::<AnyLuaValue>(complete.call())
-                    .and_then(|output| coerce_lua_array(output))
-            } else {
-                Err(lua_to_script_error("'complete_command' method missing", None))
-            }
+            Err(missing_entry_point(script, "complete_command"))
         }
     }
 
-    fn on_buffer_event<T, S>(file: File, ui: &T, config_path: &str, window_index: u32,
+    fn on_buffer_event<T, S>(script: &Script, ui: &T, invocation: &Invocation, window_index: u32,
                              webview_index: u32, requested_uri: Option<&str>,
                              event: &BufferEvent) -> ScriptResult<()>
         where T: ApplicationUI<S>,
               S: ScriptingEngine {
-        let mut lua = create_runtime::<T, S>(ui, config_path.to_owned());
+        let entry_point = match *event {
+            BufferEvent::Load => "on_load_uri",
+            BufferEvent::Focus => "on_focus",
+            BufferEvent::Request => "on_request_uri",
+            BufferEvent::Fail(_) => "on_fail_uri",
+        };
+        if script.defines(entry_point) == Some(false) {
+            return Err(missing_entry_point(script, entry_point));
+        }
+        let context = RuntimeContext::buffer_event::<T, S>(ui, invocation,
+                                                           window_index, webview_index);
+        let mut lua = create_runtime::<T, S>(ui, context)?;
+        let guard = LimitGuard::new(&mut lua, script);
         if let Some(requested_uri) = requested_uri {
             lua.set("requested_uri", requested_uri);
         }
+        if let BufferEvent::Fail(message) = event {
+            lua.set("error_message", message.clone());
+        }
         lua.set("webview_index", webview_index);
         lua.set("window_index", window_index);
-        if let Err(err) = lua.execute_from_reader::<(), _>(file) {
-            Err(lua_to_script_error("script parsing failed", Some(err)))
+        guard.resolve(evaluate(&mut lua, script))?;
+        let func: Option<LuaFunction<_>> = lua.get(entry_point);
+        if let Some(mut func) = func {
+            guard.resolve(resolve_script_output::<()>(func.call()))
         } else {
-            let func: Option<LuaFunction<_>> = match event {
-                &BufferEvent::Load => lua.get("on_load_uri"),
-                &BufferEvent::Focus => lua.get("on_focus"),
-                &BufferEvent::Request => lua.get("on_request_uri"),
-                &BufferEvent::Fail(ref message) => {
-                    lua.set("error_message", message.clone());
-                    lua.get("on_fail_uri")
-                },
-            };
-            if let Some(mut func) = func {
-                resolve_script_output::<()>(func.call())
-            } else {
-                Err(lua_to_script_error(&format!("{:?} event method missing", event), None))
+            Err(missing_entry_point(script, entry_point))
+        }
+    }
+
+    fn on_javascript_result<T, S>(script: &Script, arguments: Vec<String>, ui: &T, invocation: &Invocation,
+                                  window_index: u32, webview_index: u32,
+                                  result: &JavaScriptResult) -> ScriptResult<()>
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        check_entry_point(script, "on_javascript_result")?;
+        let context = RuntimeContext::javascript_result::<T, S>(ui, invocation,
+                                                                window_index, webview_index);
+        let mut lua = create_runtime::<T, S>(ui, context)?;
+        let guard = LimitGuard::new(&mut lua, script);
+        match *result {
+            Ok(ref json) => match serde_json::from_str(json) {
+                Ok(json) => set_json_global(&mut lua, b"javascript_result\0", &json),
+                Err(err) => lua.set("error_message", format!("Invalid JavaScript result: {}", err)),
+            },
+            Err(ref message) => lua.set("error_message", message.clone()),
+        }
+        lua.set("arguments", arguments);
+        lua.set("webview_index", webview_index);
+        lua.set("window_index", window_index);
+        guard.resolve(evaluate(&mut lua, script))?;
+        let func: Option<LuaFunction<_>> = lua.get("on_javascript_result");
+        if let Some(mut func) = func {
+            guard.resolve(resolve_script_output::<()>(func.call()))
+        } else {
+            Err(missing_entry_point(script, "on_javascript_result"))
+        }
+    }
+}
+
+/// Set a global variable to a JSON value, representing arrays and objects as
+/// tables. Arrays are indexed from 1 and `null` becomes `nil`.
+fn set_json_global(lua: &mut Lua, name: &'static [u8], json: &Value) {
+    // hlua represents a Lua context as the raw state pointer
+    let state: *mut ffi::lua_State = unsafe { mem::transmute(lua.as_mut_lua()) };
+    unsafe {
+        push_json(state, json, 0);
+        ffi::lua_setglobal(state, name.as_ptr() as *const c_char);
+    }
+}
+
+/// Push a JSON value onto the stack of a Lua state. Arrays and objects nested
+/// more than `MAX_JSON_DEPTH` levels deep, or too deeply for the stack, are
+/// pushed as `nil`.
+unsafe fn push_json(state: *mut ffi::lua_State, json: &Value, depth: usize) {
+    let nested = depth < MAX_JSON_DEPTH;
+    match *json {
+        Value::Number(ref value) => ffi::lua_pushnumber(state, value.as_f64().unwrap_or(0.0)),
+        Value::String(ref value) => push_str(state, value),
+        Value::Bool(value) => ffi::lua_pushboolean(state, value as c_int),
+        Value::Array(ref values) if nested && ffi::lua_checkstack(state, 2) != 0 => {
+            ffi::lua_createtable(state, values.len() as c_int, 0);
+            for (index, value) in values.iter().enumerate() {
+                push_json(state, value, depth + 1);
+                ffi::lua_rawseti(state, -2, (index + 1) as c_int);
+            }
+        },
+        Value::Object(ref values) if nested && ffi::lua_checkstack(state, 3) != 0 => {
+            ffi::lua_createtable(state, 0, values.len() as c_int);
+            for (key, value) in values {
+                push_str(state, key);
+                push_json(state, value, depth + 1);
+                ffi::lua_rawset(state, -3);
             }
+        },
+        _ => ffi::lua_pushnil(state),
+    }
+}
+
+unsafe fn push_str(state: *mut ffi::lua_State, value: &str) {
+    ffi::lua_pushlstring(state, value.as_ptr() as *const c_char, value.len());
+}
+
+impl RuntimeContext {
+
+    /// The context of a command, which runs for the focused webview of the
+    /// window it was run for, or of the focused window
+    fn command<T, S>(ui: &T, invocation: &Invocation) -> RuntimeContext
+            where T: ApplicationUI<S>,
+                  S: ScriptingEngine {
+        let window_index = invocation.window_index.or_else(|| ui.focused_window_index());
+        let webview_index = window_index.and_then(|index| ui.focused_webview_index(index));
+        let timer_owner = match (window_index, webview_index) {
+            (Some(window_index), Some(webview_index)) => Some(TimerOwner::Webview(window_index, webview_index)),
+            (Some(window_index), None) => Some(TimerOwner::Window(window_index)),
+            _ => None,
+        };
+        RuntimeContext {
+            config_path: invocation.config_path.clone(),
+            profile: invocation.sandbox.unwrap_or(SandboxProfile::Full),
+            namespace: invocation.command_name.clone(),
+            private: match timer_owner {
+                Some(TimerOwner::Webview(window_index, webview_index)) => ui.is_private_webview(window_index, webview_index),
+                _ => false,
+            },
+            timer_owner,
+        }
+    }
+
+    /// The context of the result of JavaScript evaluated for a command,
+    /// which runs with the sandbox profile of the command
+    fn javascript_result<T, S>(ui: &T, invocation: &Invocation,
+                               window_index: u32, webview_index: u32) -> RuntimeContext
+            where T: ApplicationUI<S>,
+                  S: ScriptingEngine {
+        RuntimeContext {
+            profile: invocation.sandbox.unwrap_or(SandboxProfile::Full),
+            ..RuntimeContext::buffer_event::<T, S>(ui, invocation, window_index, webview_index)
+        }
+    }
+
+    /// The context of an event trigger for a webview
+    fn buffer_event<T, S>(ui: &T, invocation: &Invocation,
+                          window_index: u32, webview_index: u32) -> RuntimeContext
+            where T: ApplicationUI<S>,
+                  S: ScriptingEngine {
+        RuntimeContext {
+            config_path: invocation.config_path.clone(),
+            profile: invocation.sandbox.unwrap_or(SandboxProfile::Pure),
+            namespace: invocation.command_name.clone(),
+            private: ui.is_private_webview(window_index, webview_index),
+            timer_owner: Some(TimerOwner::Webview(window_index, webview_index)),
         }
     }
 }
 
+impl LimitGuard {
+
+    /// Install a hook in a Lua state which raises an error when the state
+    /// exceeds the execution limits
+    fn new(lua: &mut Lua, script: &Script) -> LimitGuard {
+        let limits = script.limits();
+        // hlua represents a Lua context as the raw state pointer
+        let state: *mut ffi::lua_State = unsafe { mem::transmute(lua.as_mut_lua()) };
+        let budget = Budget {
+            limits: limits.clone(),
+            started: Instant::now(),
+            polled: Instant::now(),
+            cancellation_marker: limits.cancellation_marker(),
+            instructions: 0,
+            exceeded: None,
+        };
+        BUDGETS.with(|budgets| budgets.borrow_mut().insert(state as usize, budget));
+        unsafe { ffi::lua_sethook(state, check_limits, ffi::LUA_MASKCOUNT, LIMIT_CHECK_INTERVAL) };
+        LimitGuard { state: state as usize, path: String::from(script.path()) }
+    }
+
+    /// Replace the result of evaluating a script with an error if the state
+    /// exceeded its limits, even if the script handled the error raised by
+    /// the hook. Errors record the path of the script.
+    fn resolve<T>(&self, result: ScriptResult<T>) -> ScriptResult<T> {
+        let exceeded = BUDGETS.with(|budgets| {
+            budgets.borrow().get(&self.state).and_then(|budget| budget.exceeded)
+        });
+        let result = match exceeded {
+            Some((kind, message)) => Err(ScriptError::new(
+                kind, String::from_utf8_lossy(&message[..message.len() - 1]).into_owned())),
+            None => result,
+        };
+        result.map_err(|err| err.in_script(&self.path))
+    }
+}
+
+impl Drop for LimitGuard {
+
+    fn drop(&mut self) {
+        BUDGETS.with(|budgets| budgets.borrow_mut().remove(&self.state));
+    }
+}
+
+impl Budget {
+
+    /// The kind of limit exceeded and a nul-terminated message, if any
+    fn check(&self) -> Option<(ScriptErrorKind, &'static [u8])> {
+        if self.limits.is_cancelled_since(self.cancellation_marker) {
+            return Some((ScriptErrorKind::Cancelled, b"script was cancelled\0"));
+        }
+        if self.limits.time_limit.map(|limit| self.started.elapsed() > limit).unwrap_or(false) {
+            return Some((ScriptErrorKind::Timeout, b"script exceeded the time limit\0"));
+        }
+        if self.limits.instruction_limit.map(|limit| self.instructions > limit).unwrap_or(false) {
+            return Some((ScriptErrorKind::Timeout, b"script exceeded the instruction limit\0"));
+        }
+        None
+    }
+}
+
+/// Raise an error in a Lua state which has exceeded its execution limits. The
+/// error is raised on every check after the limits are first exceeded, so
+/// scripts cannot continue by catching it with `pcall()`.
+extern "C" fn check_limits(state: *mut ffi::lua_State, _: *mut ffi::lua_Debug) {
+    let exceeded = BUDGETS.with(|budgets| {
+        budgets.borrow_mut().get_mut(&(state as usize)).and_then(|budget| {
+            budget.instructions += LIMIT_CHECK_INTERVAL as u64;
+            if budget.polled.elapsed() >= INTERRUPT_POLL_INTERVAL {
+                budget.polled = Instant::now();
+                budget.limits.poll_interrupt();
+            }
+            if budget.exceeded.is_none() {
+                budget.exceeded = budget.check();
+            }
+            budget.exceeded
+        })
+    });
+    if let Some((_, message)) = exceeded {
+        // lua_error does not return, so nothing requiring cleanup may be in
+        // scope here. Checking after every instruction from now on raises
+        // the error again as soon as a script resumes after catching it.
+        unsafe {
+            ffi::lua_sethook(state, check_limits, ffi::LUA_MASKCOUNT, 1);
+            ffi::lua_pushstring(state, message.as_ptr() as *const c_char);
+            ffi::lua_error(state);
+        }
+    }
+}
+
+/// Evaluate the contents of a script, recording which event triggers it
+/// defines the first time it is evaluated
+fn evaluate(lua: &mut Lua, script: &Script) -> ScriptResult<()> {
+    let chunk = compile(script)?;
+    if let Err(err) = lua.execute_from_reader::<(), _>(&chunk[..]) {
+        return Err(lua_to_script_error("script evaluation failed", Some(err)));
+    }
+    if !script.has_entry_points() {
+        let mut names: Vec<String> = vec![];
+        for name in ENTRY_POINTS {
+            let func: Option<LuaFunction<_>> = lua.get(*name);
+            if func.is_some() {
+                names.push(String::from(*name));
+            }
+        }
+        script.set_entry_points(names);
+    }
+    Ok(())
+}
+
+/// The source text of a script compiled to a binary chunk, which is parsed
+/// only the first time the script is evaluated and then cached with it
+fn compile(script: &Script) -> ScriptResult<Arc<Vec<u8>>> {
+    if let Some(chunk) = script.chunk() {
+        return Ok(chunk);
+    }
+    let mut lua = Lua::new();
+    // hlua represents a Lua context as the raw state pointer
+    let state: *mut ffi::lua_State = unsafe { mem::transmute(lua.as_mut_lua()) };
+    let mut chunk: Vec<u8> = vec![];
+    {
+        let _function = LuaFunction::load(&mut lua, script.source())
+            .map_err(|err| lua_to_script_error("script parsing failed", Some(err)))?;
+        unsafe { ffi::lua_dump(state, write_chunk, &mut chunk as *mut Vec<u8> as *mut c_void) };
+    }
+    Ok(script.set_chunk(chunk))
+}
+
+/// Append the output of `lua_dump` to a buffer
+extern "C" fn write_chunk(_: *mut ffi::lua_State, data: *const c_void, size: usize, buffer: *mut c_void) -> c_int {
+    unsafe {
+        let buffer = &mut *(buffer as *mut Vec<u8>);
+        buffer.extend_from_slice(slice::from_raw_parts(data as *const u8, size));
+    }
+    0
+}
+
+/// Fail without evaluating a script if a previous evaluation found that the
+/// event trigger is not defined
+fn check_entry_point(script: &Script, entry_point: &str) -> ScriptResult<()> {
+    if script.defines(entry_point) == Some(false) {
+        Err(missing_entry_point(script, entry_point))
+    } else {
+        Ok(())
+    }
+}
+
+fn missing_entry_point(script: &Script, entry_point: &str) -> ScriptError {
+    ScriptError::new(ScriptErrorKind::MissingEntryPoint, format!("'{}' method missing", entry_point))
+        .in_script(script.path())
+}
+
 fn lua_to_script_error(description: &str, error: Option<LuaError>) -> ScriptError {
-    let mut full_description = String::from(description);
-    if let Some(error) = error {
-        full_description.push_str(": ");
-        match error {
-            LuaError::SyntaxError(err) => full_description.push_str(&err),
-            LuaError::ExecutionError(err) => full_description.push_str(&err),
-            LuaError::ReadError(err) => full_description.push_str(err.description()),
-            LuaError::WrongType => full_description.push_str("incorrect data type"),
+    let (kind, message) = match error {
+        Some(LuaError::SyntaxError(err)) => (ScriptErrorKind::Parse, err),
+        Some(LuaError::ExecutionError(err)) => (ScriptErrorKind::Runtime, err),
+        Some(LuaError::ReadError(err)) => (ScriptErrorKind::Parse, err.to_string()),
+        Some(LuaError::WrongType) => (ScriptErrorKind::WrongReturnType, String::from("incorrect data type")),
+        None => return ScriptError::new(ScriptErrorKind::Runtime, description),
+    };
+    match split_chunk_location(&message) {
+        Some((line, message)) => ScriptError::new(kind, format!("{}: {}", description, message)).at_line(line),
+        None => ScriptError::new(kind, format!("{}: {}", description, message)),
+    }
+}
+
+/// The line number and remaining text of a Lua error message beginning with
+/// the location in the evaluated chunk, such as `[string "chunk"]:3: message`
+fn split_chunk_location(message: &str) -> Option<(u32, &str)> {
+    let location = message.find(CHUNK_PREFIX)
+        .map(|index| &message[index + CHUNK_PREFIX.len()..])?;
+    let end = location.find(':')?;
+    let line = location[..end].parse().ok()?;
+    Some((line, location[end + 1..].trim_start()))
+}
+
+impl<L> LuaRead<L> for CompletionResult where L: AsMutLua {
+
+    fn lua_read_at_position(lua: L, index: i32) -> Result<CompletionResult, L> {
+        if let Ok(text) = String::lua_read_at_position(&lua, index) {
+            return Ok(CompletionResult(text.split(',')
+                                       .filter(|item| !item.is_empty())
+                                       .map(Completion::new)
+                                       .collect()));
+        }
+        let lua = match LuaTable::lua_read_at_position(lua, index) {
+            Ok(mut table) => return Ok(CompletionResult(read_completions(&mut table))),
+            Err(lua) => lua,
         };
+        // hlua represents a Lua context as the raw state pointer
+        let state: *mut ffi::lua_State = unsafe { mem::transmute(lua.as_lua()) };
+        if unsafe { ffi::lua_isnil(state, index) } {
+            Ok(CompletionResult(vec![]))
+        } else {
+            Err(lua)
+        }
     }
-    ScriptError { description: full_description }
 }
 
-fn coerce_lua_array(raw_value: AnyLuaValue) -> ScriptResult<Vec<String>> {
-    if let AnyLuaValue::LuaString(value) = raw_value {
-        if value.len() == 0 {
-            Ok(vec![])
+impl<L> LuaRead<L> for StringList where L: AsMutLua {
+
+    fn lua_read_at_position(mut lua: L, index: i32) -> Result<StringList, L> {
+        // hlua only reads tables from the top of the stack, so the table is
+        // read directly from the state
+        let state: *mut ffi::lua_State = unsafe { mem::transmute(lua.as_mut_lua()) };
+        unsafe {
+            if !ffi::lua_istable(state, index) {
+                return Err(lua);
+            }
+            let table = ffi::lua_absindex(state, index);
+            let count = ffi::lua_rawlen(state, table);
+            let mut values = Vec::with_capacity(count);
+            for item in 1..=count {
+                ffi::lua_rawgeti(state, table, item as c_int);
+                let mut length: usize = 0;
+                let value = ffi::lua_tolstring(state, -1, &mut length);
+                if !value.is_null() {
+                    let bytes = slice::from_raw_parts(value as *const u8, length);
+                    values.push(String::from_utf8_lossy(bytes).into_owned());
+                }
+                ffi::lua_pop(state, 1);
+            }
+            Ok(StringList(values))
+        }
+    }
+}
+
+/// Read a table of completions, stopping at the first index which is not a
+/// string or a record with a label or value
+fn read_completions<L: AsMutLua>(table: &mut LuaTable<L>) -> Vec<Completion> {
+    let mut completions: Vec<Completion> = vec![];
+    let mut index = 1;
+    loop {
+        if let Some(value) = table.get::<String, _>(index) {
+            completions.push(Completion::new(value));
+        } else if let Some(mut item) = table.get::<LuaTable<_>, _>(index) {
+            let label: Option<String> = item.get("label");
+            let value: Option<String> = item.get("value");
+            let description = item.get::<String, _>("description").filter(|text| !text.is_empty());
+            match (label.clone().or_else(|| value.clone()), value.or(label)) {
+                (Some(label), Some(value)) => completions.push(Completion { label, value, description }),
+                _ => break,
+            }
         } else {
-            Ok(value.split(",").map(|v| String::from(v)).collect())
+            break;
         }
-    } else {
-        Err(lua_to_script_error("Return type is not an string", None))
+        index += 1;
     }
+    completions
 }
 
 fn resolve_script_output<T>(output: Result<T, LuaError>) -> ScriptResult<T> {
     output.map_err(|err| lua_to_script_error("script failed to execute", Some(err)))
 }
 
-fn create_runtime<T, S>(ui: &T, config_path: String) -> Lua
+/// Change a configuration option on behalf of a script, returning whether the
+/// change succeeded and an error message. Only scripts with system access may
+/// save options or change the options controlling the access of commands.
+fn update_config<T, S, F>(ui: &T, profile: SandboxProfile, key: &str, persist: bool, change: F) -> (bool, String)
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine,
+              F: Fn(&Config) -> Result<(), ConfigError> {
+    if persist && !profile.allows_system_access() {
+        return (false, format!("Saving configuration options requires the full sandbox profile, not {}", profile));
+    }
+    if Config::controls_command_access(key) && !profile.allows_system_access() {
+        return (false, format!("Changing {} requires the full sandbox profile, not {}", key, profile));
+    }
+    match ui.update_config(persist, change) {
+        Ok(()) => (true, String::new()),
+        Err(err) => {
+            warn!("Unable to change configuration: {}", err);
+            (false, err.to_string())
+        },
+    }
+}
+
+/// Create a Lua runtime providing the standard libraries and browser functions
+/// allowed by the sandbox profile of a context.
+///
+/// The runtime outlives this function, so functions using `ui` or other
+/// locals must take them by value (`move`) rather than borrow them from this
+/// stack frame, which hlua does not check.
+/// A runtime for evaluating a script to read its declarations, such as its
+/// description, with the libraries and modules available to the `pure`
+/// sandbox profile but no browser functions
+fn create_declaration_runtime<'lua>(script: &Script) -> ScriptResult<Lua<'lua>> {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    restrict_libraries(&mut lua, script.library_paths().to_vec())?;
+    lua.set("NOT_FOUND", NOT_FOUND);
+    Ok(lua)
+}
+
+/// Evaluate a script in a declaration runtime. A failure is saved with the
+/// script, so it is not evaluated again until the file changes.
+fn evaluate_declarations(lua: &mut Lua, guard: &LimitGuard, script: &Script) -> ScriptResult<()> {
+    if let Some(err) = script.description_error() {
+        return Err(err);
+    }
+    let result = guard.resolve(evaluate(lua, script));
+    if let Err(ref err) = result {
+        script.set_description_error(err.clone());
+    }
+    result
+}
+
+/// Remove the standard library functions unavailable to sandboxed scripts,
+/// loading modules only from the library paths
+fn restrict_libraries(lua: &mut Lua, library_paths: Vec<String>) -> ScriptResult<()> {
+    if let Err(err) = lua.execute::<()>(RESTRICT_LIBRARIES) {
+        return Err(lua_to_script_error("failed to restrict standard libraries", Some(err)));
+    }
+    lua.set("__read_module", function1(move |name: String| {
+        info!("require: {}", name);
+        read_module(&library_paths, &name).unwrap_or((String::new(), String::new()))
+    }));
+    if let Err(err) = lua.execute::<()>(MODULE_LOADER) {
+        return Err(lua_to_script_error("failed to define module loader", Some(err)));
+    }
+    Ok(())
+}
+
+fn create_runtime<T, S>(ui: &T, context: RuntimeContext) -> ScriptResult<Lua<'_>>
         where T: ApplicationUI<S>,
               S: ScriptingEngine {
+    let RuntimeContext { config_path, profile, namespace, private, timer_owner } = context;
     let mut lua = Lua::new();
     lua.openlibs();
+    let library_paths = ui.config().command_library_paths();
+    if profile.allows_system_access() {
+        set_package_path(&mut lua, &library_paths);
+    } else {
+        restrict_libraries(&mut lua, library_paths)?;
+    }
     lua.set("NOT_FOUND", NOT_FOUND);
     lua.set("log_info", function1(|message: String| {
         info!("{}", message);
@@ -147,169 +820,314 @@ fn create_runtime<T, S>(ui: &T, config_path: String) -> Lua
     lua.set("log_debug", function1(|message: String| {
         debug!("{}", message);
     }));
-    lua.set("copy", function1(|message: String| {
-        info!("copy");
-        ui.copy(&message);
-    }));
-    lua.set("run_command", function2(|window_index: u32, command: String| {
-        info!("run_command");
-        ui.execute_command(coerce_optional_index(window_index), &command);
-    }));
     lua.set("config_file_path", config_path);
-    lua.set("lookup_bool", function2(|config_path: String, key: String| {
-        info!("lookup_bool ({}): {}", config_path, key);
-        if let Some(config) = Config::parse_file(&config_path) {
-            return config.lookup_bool(&key).unwrap_or(false)
-        }
-        false
+    lua.set("lookup_bool", function1(move |key: String| {
+        info!("lookup_bool: {}", key);
+        ui.config().lookup_bool(&key).unwrap_or(false)
     }));
-    lua.set("lookup_strings", function2(|config_path: String, key: String| {
-        info!("lookup_str ({}): {}", config_path, key);
-        if let Some(config) = Config::parse_file(&config_path) {
-            return config.lookup_str_vec(&key).unwrap_or(vec![])
+    lua.set("lookup_integer", function1(move |key: String| {
+        info!("lookup_integer: {}", key);
+        match ui.config().lookup_integer(&key) {
+            Some(value) => AnyLuaValue::LuaNumber(value as f64),
+            None => AnyLuaValue::LuaNil,
         }
-        vec![]
     }));
-    lua.set("lookup_string", function2(|config_path: String, key: String| {
-        info!("lookup_str ({}): {}", config_path, key);
-        if let Some(config) = Config::parse_file(&config_path) {
-            return config.lookup_str(&key).unwrap_or(String::new())
-        }
-        String::new()
+    lua.set("lookup_string", function1(move |key: String| {
+        info!("lookup_str: {}", key);
+        ui.config().lookup_str(&key).unwrap_or(String::new())
     }));
-    lua.set("focus_window", function1(|index: u32| {
-        info!("focus_window: {}", index);
-        ui.focus_window(index);
+    lua.set("lookup_strings", function1(move |key: String| {
+        info!("lookup_str_vec: {}", key);
+        ui.config().lookup_str_vec(&key).unwrap_or(vec![])
     }));
-    lua.set("focus_webview_in_window", function1(|index: u32| {
-        info!("focus_webview_in_window: {}", index);
-        ui.focus_window_area(index, WindowArea::WebView);
+    lua.set("lookup_table", function1(move |key: String| {
+        info!("lookup_str_table: {}", key);
+        ui.config().lookup_str_table(&key).unwrap_or(HashMap::new())
     }));
-    lua.set("focus_commandbar_in_window", function1(|index: u32| {
-        info!("focus_commandbar_in_window: {}", index);
-        ui.focus_window_area(index, WindowArea::CommandBar);
+    lua.set("lookup_site_bool", function2(move |uri: String, key: String| {
+        info!("lookup_site_bool: {}", key);
+        ui.config().lookup_site_bool(&uri, &key).unwrap_or(false)
     }));
-    lua.set("open_window", function1(|uri: String| {
-        info!("open_window");
-        ui.open_window::<_, Config>(coerce_optional_str(uri), None)
+    lua.set("lookup_site_string", function2(move |uri: String, key: String| {
+        info!("lookup_site_str: {}", key);
+        ui.config().lookup_site_str(&uri, &key).unwrap_or(String::new())
     }));
-    lua.set("open_custom_window", function2(|uri: String, config: String| {
-        info!("open_window");
-        ui.open_window(coerce_optional_str(uri), Config::parse(&config))
+    lua.set("lookup_site_strings", function2(move |uri: String, key: String| {
+        info!("lookup_site_str_vec: {}", key);
+        ui.config().lookup_site_str_vec(&uri, &key).unwrap_or(vec![])
     }));
-    lua.set("close_window", function1(|window_index: u32| {
-        info!("close_window: {}", window_index);
-        ui.close_window(window_index);
+    if let Err(err) = lua.execute::<()>(CONFIG_LOOKUPS) {
+        return Err(lua_to_script_error("failed to define configuration functions", Some(err)));
+    }
+    let store_namespace = namespace.clone();
+    lua.set("store_get", function1(move |key: String| {
+        info!("store_get ({}): {}", store_namespace, key);
+        match ui.command_store().get(&store_namespace, private, &key) {
+            Some(value) => AnyLuaValue::LuaString(value),
+            None => AnyLuaValue::LuaNil,
+        }
+    }));
+    let store_namespace = namespace.clone();
+    lua.set("store_keys", function0(move || {
+        info!("store_keys ({})", store_namespace);
+        ui.command_store().keys(&store_namespace, private)
     }));
-    lua.set("window_count", function0(|| {
+    lua.set("window_count", function0(move || {
         info!("get window_count");
         ui.window_count()
     }));
-    lua.set("focused_window_index", function0(|| {
+    lua.set("focused_window_index", function0(move || {
         info!("get focused_window_index");
         ui.focused_window_index().unwrap_or(NOT_FOUND)
     }));
-    lua.set("window_title", function1(|window_index: u32| {
+    lua.set("window_title", function1(move |window_index: u32| {
         info!("window_title: {}", window_index);
         ui.window_title(window_index)
     }));
-    lua.set("set_window_title", function2(|window_index: u32, title: String| {
-        info!("set_window_title: {}", window_index);
-        ui.set_window_title(window_index, &title);
-    }));
-    lua.set("hide_window", function1(|window_index: u32| {
-        info!("hide_window: {}", window_index);
-        ui.toggle_window(window_index, false);
-    }));
-    lua.set("show_window", function1(|window_index: u32| -> () {
-        info!("show_window: {}", window_index);
-        ui.toggle_window(window_index, true);
-    }));
-    lua.set("open_webview", function2(|window_index: u32, uri: String| {
-        info!("open_webview: {}", window_index);
-        ui.open_webview::<_, Config>(window_index, coerce_optional_str(uri), None);
-    }));
-    lua.set("open_custom_webview", function3(|window_index: u32, uri: String, config: String| {
-        info!("open_custom_webview: {} {}", window_index, config);
-        ui.open_webview::<_, Config>(window_index, coerce_optional_str(uri), Config::parse(&config));
-    }));
-    lua.set("webview_count", function1(|window_index: u32| {
+    lua.set("webview_count", function1(move |window_index: u32| {
         info!("get webview_count: {}", window_index);
         ui.webview_count(window_index)
     }));
-    lua.set("set_command_field_visible", function2(|window_index: u32, visible: bool| {
-        info!("set command_field_visible");
-        ui.set_command_field_visible(window_index, visible);
-    }));
-    lua.set("set_command_field_text", function2(|window_index: u32, text: String| {
-        info!("set command_field_text");
-        ui.set_command_field_text(window_index, &text);
-    }));
-    lua.set("command_field_visible", function1(|window_index: u32| {
+    lua.set("command_field_visible", function1(move |window_index: u32| {
         info!("get command_field_visible");
         ui.command_field_visible(window_index)
     }));
-    lua.set("command_field_text", function1(|window_index: u32| {
+    lua.set("command_field_text", function1(move |window_index: u32| {
         info!("get command_field_text");
         ui.command_field_text(window_index)
     }));
-    lua.set("focused_webview_index", function1(|window_index: u32| {
-        info!("get focused_webview_index");
-        ui.focused_webview_index(window_index).unwrap_or(NOT_FOUND)
-    }));
-    lua.set("resize_window", function3(|window_index: u32, width: u32, height: u32| {
-        info!("resize_window: {} => ({}, {})", window_index, width, height);
-        ui.resize_window(window_index, width, height);
-    }));
-    lua.set("close_webview", function2(|window_index: u32, webview_index: u32| {
-        info!("close_webview: ({}, {})", window_index, webview_index);
-        ui.close_webview(window_index, webview_index);
-    }));
-    lua.set("reload_webview", function3(|window_index: u32, webview_index: u32, disable_filters: bool| {
-        info!("reload_webview: ({}, {})", window_index, webview_index);
-        ui.reload_webview(window_index, webview_index, disable_filters);
-    }));
-    lua.set("focus_webview", function2(|window_index: u32, webview_index: u32| {
-        info!("focus_webview: ({}, {})", window_index, webview_index);
-        ui.focus_webview(window_index, webview_index);
-    }));
-    lua.set("load_uri", function3(|window_index: u32, webview_index: u32, uri: String| {
-        info!("load_uri: ({}, {})", window_index, webview_index);
-        ui.set_uri(window_index, webview_index, &uri);
+    lua.set("command_history", function1(move |count: u32| {
+        info!("get command_history: {}", count);
+        ui.command_history(count)
     }));
-    lua.set("go_back", function2(|window_index: u32, webview_index: u32| {
-        info!("go_back: ({}, {})", window_index, webview_index);
-        ui.go_back(window_index, webview_index);
+    lua.set("command_history_match", function2(move |prefix: String, index: u32| {
+        info!("get command_history_match: {}", index);
+        ui.command_history_match(&prefix, index).unwrap_or(String::new())
     }));
-    lua.set("go_forward", function2(|window_index: u32, webview_index: u32| {
-        info!("go_forward: ({}, {})", window_index, webview_index);
-        ui.go_forward(window_index, webview_index);
+    lua.set("focused_webview_index", function1(move |window_index: u32| {
+        info!("get focused_webview_index");
+        ui.focused_webview_index(window_index).unwrap_or(NOT_FOUND)
     }));
-    lua.set("webview_uri", function2(|window_index: u32, webview_index: u32| {
+    lua.set("webview_uri", function2(move |window_index: u32, webview_index: u32| {
         info!("get webview_uri: ({}, {})", window_index, webview_index);
         ui.uri(window_index, webview_index)
     }));
-    lua.set("webview_title", function2(|window_index: u32, webview_index: u32| {
+    lua.set("webview_title", function2(move |window_index: u32, webview_index: u32| {
         info!("get webview_title: ({}, {})", window_index, webview_index);
         ui.webview_title(window_index, webview_index)
     }));
-    lua.set("find", function3(|window_index: u32, webview_index: u32, query: String| {
-        info!("find: ({}, {})", window_index, webview_index);
-        ui.find_string(window_index, webview_index, &query);
-    }));
-    lua.set("hide_find", function2(|window_index: u32, webview_index: u32| {
-        info!("hide_find: ({}, {})", window_index, webview_index);
-        ui.hide_find_results(window_index, webview_index)
-    }));
-    lua.set("run_javascript", function3(|window_index: u32, webview_index: u32, script: String| {
-        info!("run_javascript: ({}, {})", window_index, webview_index);
-        ui.run_javascript(window_index, webview_index, &script);
-    }));
-    lua.set("add_styles", function3(|window_index: u32, webview_index: u32, styles: String| {
-        info!("add_styles: ({}, {})", window_index, webview_index);
-        ui.apply_styles(window_index, webview_index, &styles);
-    }));
-    lua
+    if profile.allows_commands() {
+        lua.set("run_command", function2(move |window_index: u32, command: String| {
+            info!("run_command");
+            let output = ui.execute_command(coerce_optional_index(window_index), &command);
+            (output.is_success(), output.message.unwrap_or(String::new()))
+        }));
+        if let Some(owner) = timer_owner {
+            lua.set("set_timeout", function2(move |milliseconds: u32, command: String| {
+                info!("set_timeout: {}", milliseconds);
+                ui.set_timer(&command, owner, Duration::from_millis(milliseconds as u64), false)
+            }));
+            lua.set("set_interval", function2(move |milliseconds: u32, command: String| {
+                info!("set_interval: {}", milliseconds);
+                ui.set_timer(&command, owner, Duration::from_millis(milliseconds as u64), true)
+            }));
+        }
+        lua.set("clear_timer", function1(move |timer_id: u32| {
+            info!("clear_timer: {}", timer_id);
+            ui.clear_timer(timer_id);
+        }));
+    }
+    if profile.allows_browser_changes() {
+        let store_namespace = namespace.clone();
+        lua.set("store_set", function2(move |key: String, value: String| {
+            info!("store_set ({}): {}", store_namespace, key);
+            ui.command_store().set(&store_namespace, private, &key, &value);
+        }));
+        let store_namespace = namespace.clone();
+        lua.set("store_delete", function1(move |key: String| {
+            info!("store_delete ({}): {}", store_namespace, key);
+            ui.command_store().delete(&store_namespace, private, &key);
+        }));
+        lua.set("__set_config_bool", function3(move |key: String, value: bool, persist: bool| {
+            info!("set_bool: {}", key);
+            update_config(ui, profile, &key, persist, |config| config.set_bool(&key, value))
+        }));
+        lua.set("__set_config_string", function3(move |key: String, value: String, persist: bool| {
+            info!("set_string: {}", key);
+            update_config(ui, profile, &key, persist, |config| config.set_str(&key, &value))
+        }));
+        lua.set("__set_config_strings", function3(move |key: String, values: StringList, persist: bool| {
+            info!("set_strings: {}", key);
+            update_config(ui, profile, &key, persist, |config| config.set_str_vec(&key, &values.0))
+        }));
+        if let Err(err) = lua.execute::<()>(CONFIG_SETTERS) {
+            return Err(lua_to_script_error("failed to define configuration functions", Some(err)));
+        }
+        lua.set("copy", function1(move |message: String| {
+            info!("copy");
+            ui.copy(&message);
+        }));
+        lua.set("focus_window", function1(move |index: u32| {
+            info!("focus_window: {}", index);
+            ui.focus_window(index);
+        }));
+        lua.set("focus_webview_in_window", function1(move |index: u32| {
+            info!("focus_webview_in_window: {}", index);
+            ui.focus_window_area(index, WindowArea::WebView);
+        }));
+        lua.set("focus_commandbar_in_window", function1(move |index: u32| {
+            info!("focus_commandbar_in_window: {}", index);
+            ui.focus_window_area(index, WindowArea::CommandBar);
+        }));
+        lua.set("open_window", function1(move |uri: String| {
+            info!("open_window");
+            ui.open_window::<_, Config>(coerce_optional_str(uri), None)
+        }));
+        lua.set("open_custom_window", function2(move |uri: String, config: String| {
+            info!("open_window");
+            ui.open_window(coerce_optional_str(uri), Config::parse(&config))
+        }));
+        lua.set("close_window", function1(move |window_index: u32| {
+            info!("close_window: {}", window_index);
+            ui.close_window(window_index);
+        }));
+        lua.set("set_window_title", function2(move |window_index: u32, title: String| {
+            info!("set_window_title: {}", window_index);
+            ui.set_window_title(window_index, &title);
+        }));
+        lua.set("hide_window", function1(move |window_index: u32| {
+            info!("hide_window: {}", window_index);
+            ui.toggle_window(window_index, false);
+        }));
+        lua.set("show_window", function1(move |window_index: u32| -> () {
+            info!("show_window: {}", window_index);
+            ui.toggle_window(window_index, true);
+        }));
+        lua.set("open_webview", function2(move |window_index: u32, uri: String| {
+            info!("open_webview: {}", window_index);
+            ui.open_webview::<_, Config>(window_index, coerce_optional_str(uri), None);
+        }));
+        lua.set("open_custom_webview", function3(move |window_index: u32, uri: String, config: String| {
+            info!("open_custom_webview: {} {}", window_index, config);
+            ui.open_webview::<_, Config>(window_index, coerce_optional_str(uri), Config::parse(&config));
+        }));
+        lua.set("set_command_field_visible", function2(move |window_index: u32, visible: bool| {
+            info!("set command_field_visible");
+            ui.set_command_field_visible(window_index, visible);
+        }));
+        lua.set("set_command_field_text", function2(move |window_index: u32, text: String| {
+            info!("set command_field_text");
+            ui.set_command_field_text(window_index, &text);
+        }));
+        lua.set("resize_window", function3(move |window_index: u32, width: u32, height: u32| {
+            info!("resize_window: {} => ({}, {})", window_index, width, height);
+            ui.resize_window(window_index, width, height);
+        }));
+        lua.set("close_webview", function2(move |window_index: u32, webview_index: u32| {
+            info!("close_webview: ({}, {})", window_index, webview_index);
+            ui.close_webview(window_index, webview_index);
+        }));
+        lua.set("reload_webview", function3(move |window_index: u32, webview_index: u32, disable_filters: bool| {
+            info!("reload_webview: ({}, {})", window_index, webview_index);
+            ui.reload_webview(window_index, webview_index, disable_filters);
+        }));
+        lua.set("focus_webview", function2(move |window_index: u32, webview_index: u32| {
+            info!("focus_webview: ({}, {})", window_index, webview_index);
+            ui.focus_webview(window_index, webview_index);
+        }));
+        lua.set("load_uri", function3(move |window_index: u32, webview_index: u32, uri: String| {
+            info!("load_uri: ({}, {})", window_index, webview_index);
+            ui.set_uri(window_index, webview_index, &uri);
+        }));
+        lua.set("go_back", function2(move |window_index: u32, webview_index: u32| {
+            info!("go_back: ({}, {})", window_index, webview_index);
+            ui.go_back(window_index, webview_index);
+        }));
+        lua.set("go_forward", function2(move |window_index: u32, webview_index: u32| {
+            info!("go_forward: ({}, {})", window_index, webview_index);
+            ui.go_forward(window_index, webview_index);
+        }));
+        lua.set("find", function3(move |window_index: u32, webview_index: u32, query: String| {
+            info!("find: ({}, {})", window_index, webview_index);
+            ui.find_string(window_index, webview_index, &query);
+        }));
+        lua.set("hide_find", function2(move |window_index: u32, webview_index: u32| {
+            info!("hide_find: ({}, {})", window_index, webview_index);
+            ui.hide_find_results(window_index, webview_index)
+        }));
+        lua.set("run_javascript", function3(move |window_index: u32, webview_index: u32, script: String| {
+            info!("run_javascript: ({}, {})", window_index, webview_index);
+            ui.run_javascript(window_index, webview_index, &script);
+        }));
+        let callback_command = namespace.clone();
+        lua.set("__evaluate_javascript", function4(move |window_index: u32, webview_index: u32, script: String, command: String| {
+            info!("evaluate_javascript: ({}, {})", window_index, webview_index);
+            let command = if command.is_empty() { callback_command.clone() } else { command };
+            let callback_name = command.split_whitespace().next().unwrap_or("");
+            if callback_name != callback_command && !profile.allows_system_access() {
+                warn!("Passing JavaScript results to '{}' requires the full sandbox profile, not {}",
+                      callback_name, profile);
+                return;
+            }
+            ui.evaluate_javascript(window_index, webview_index, &script, &command);
+        }));
+        if let Err(err) = lua.execute::<()>(JAVASCRIPT_EVALUATION) {
+            return Err(lua_to_script_error("failed to define JavaScript functions", Some(err)));
+        }
+        lua.set("add_styles", function3(move |window_index: u32, webview_index: u32, styles: String| {
+            info!("add_styles: ({}, {})", window_index, webview_index);
+            ui.apply_styles(window_index, webview_index, &styles);
+        }));
+    }
+    lua.set("__api_version", API_VERSION);
+    lua.set("__legacy_globals", ui.config().command_legacy_globals());
+    if let Err(err) = lua.execute::<()>(API_NAMESPACE) {
+        return Err(lua_to_script_error("failed to define the webkitten table", Some(err)));
+    }
+    Ok(lua)
+}
+
+/// Search the library paths before the default locations when loading
+/// modules using `require()`
+fn set_package_path(lua: &mut Lua, library_paths: &[String]) {
+    let mut package: LuaTable<_> = match lua.get("package") {
+        Some(package) => package,
+        None => return,
+    };
+    let mut templates: Vec<String> = library_paths.iter()
+        .flat_map(|path| vec![format!("{}/?.lua", path), format!("{}/?/init.lua", path)])
+        .collect();
+    if let Some(default_path) = package.get::<String, _>("path") {
+        templates.push(default_path);
+    }
+    package.set("path", templates.join(";"));
+}
+
+/// The path and contents of the first Lua file in the library paths matching
+/// a module name, where dots in the name separate directories, as in
+/// `webkitten.util` for `webkitten/util.lua` or `webkitten/util/init.lua`
+fn read_module(library_paths: &[String], name: &str) -> Option<(String, String)> {
+    let components: Vec<&str> = name.split('.').collect();
+    let valid = components.iter()
+        .all(|component| !component.is_empty() && !component.contains('/') && !component.contains('\\'));
+    if !valid {
+        return None;
+    }
+    let relative_path = components.join("/");
+    library_paths.iter()
+        .flat_map(|path| vec![Path::new(path).join(format!("{}.lua", relative_path)),
+                              Path::new(path).join(&relative_path).join("init.lua")])
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            let source = fs::read_to_string(&path)
+                .map_err(|err| warn!("Unable to read module {}: {}", path.display(), err))
+                .ok();
+            match (path.to_str(), source) {
+                (Some(path), Some(source)) => Some((String::from(path), source)),
+                _ => None,
+            }
+        })
+        .next()
 }
 
 fn coerce_optional_index(value: u32) -> Option<u32> {
@@ -335,7 +1153,16 @@ mod tests {
     use std::io::Write;
     use std::path::PathBuf;
     use std::slice;
-    use script::{ScriptingEngine,LuaEngine};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use command::Command;
+    use script::{Script,ScriptCache,ScriptingEngine,ScriptErrorKind,LuaEngine};
+    use super::hlua::Lua;
+    use super::hlua::functions_read::LuaFunction;
+    use super::{MAX_JSON_DEPTH,set_json_global};
+    use serde_json::Value;
+    use tests::{MockUI,TestDir,create_ui};
+    use ui::{ApplicationUI,BufferEvent,EventHandler};
 
     #[test]
     fn describe_missing_method() {
@@ -344,8 +1171,8 @@ mod tests {
                 return "Sends mail"
             end
         "#);
-        let file = File::open(path.clone()).ok().unwrap();
-        let result = LuaEngine::describe(file);
+        let script = Script::open(path.to_str().unwrap()).ok().unwrap();
+        let result = LuaEngine::describe(&script);
         assert!(result.is_err());
         cleanup_script(path);
     }
@@ -356,12 +1183,44 @@ mod tests {
             function description()
             end
         "#);
-        let file = File::open(path.clone()).ok().unwrap();
-        let result = LuaEngine::describe(file);
-        assert!(result.is_err());
+        let script = Script::open(path.to_str().unwrap()).ok().unwrap();
+        let err = LuaEngine::describe(&script).unwrap_err();
+        assert_eq!(ScriptErrorKind::WrongReturnType, err.kind());
+        assert_eq!(path.to_str(), err.path());
         cleanup_script(path);
     }
 
+    #[test]
+    fn describe_error_locations() {
+        let script = Script::new("/commands/syntax.lua", "function description()\n  return 'a' 'b\nend");
+        let err = LuaEngine::describe(&script).unwrap_err();
+        assert_eq!(ScriptErrorKind::Parse, err.kind());
+        assert_eq!(Some(2), err.line());
+        assert!(err.summary().starts_with("syntax.lua:2: script parsing failed: "), "{}", err);
+        let script = Script::new("/commands/runtime.lua", "function description()\n\n  return missing.field\nend");
+        let err = LuaEngine::describe(&script).unwrap_err();
+        assert_eq!(ScriptErrorKind::Runtime, err.kind());
+        assert_eq!(Some("/commands/runtime.lua"), err.path());
+        assert_eq!(Some(3), err.line());
+        let script = Script::new("/commands/empty.lua", "");
+        let err = LuaEngine::describe(&script).unwrap_err();
+        assert_eq!(ScriptErrorKind::MissingEntryPoint, err.kind());
+        assert_eq!("empty.lua: 'description' method missing", &err.summary());
+    }
+
+    #[test]
+    fn evaluate_compiled_chunk() {
+        let script = Script::new("/commands/compiled.lua", "function description() return 'Compiled' end");
+        assert!(script.chunk().is_none());
+        assert_eq!("Compiled", &LuaEngine::describe(&script).unwrap());
+        let chunk = script.chunk().unwrap();
+        assert!(Arc::ptr_eq(&chunk, &super::compile(&script).unwrap()));
+        let mut lua = Lua::new();
+        super::evaluate(&mut lua, &script).unwrap();
+        let description: Option<LuaFunction<_>> = lua.get("description");
+        assert!(description.is_some());
+    }
+
     #[test]
     fn describe_valid_command() {
         let path = create_script("mail_valid", r#"
@@ -369,12 +1228,128 @@ mod tests {
                 return "Sends mail"
             end
         "#);
-        let file = File::open(path.clone()).ok().unwrap();
-        let result = LuaEngine::describe(file).ok().unwrap();
+        let script = Script::open(path.to_str().unwrap()).ok().unwrap();
+        let result = LuaEngine::describe(&script).ok().unwrap();
         assert_eq!(String::from("Sends mail"), result);
         cleanup_script(path);
     }
 
+    #[test]
+    fn describe_with_sandboxed_libraries() {
+        let dir = TestDir::new("lua-describe-libraries");
+        dir.write("lib/labels.lua", "return {mail = 'Sends mail'}");
+        let path = dir.write("mail.lua", r#"
+            local labels = require("labels")
+            local name = string.upper(table.concat({"m", "a", "i", "l"}))
+            local sandboxed = io == nil and os.execute == nil
+            function description()
+                return labels.mail .. " as " .. name .. " " .. tostring(sandboxed)
+            end
+            function argument_spec()
+                return "to [subject]"
+            end
+        "#);
+        let cache = ScriptCache::new();
+        cache.set_library_paths(vec![dir.path().join("lib").to_string_lossy().into_owned()]);
+        let script = cache.load(path.to_str().unwrap()).unwrap();
+        assert_eq!("Sends mail as MAIL true", &LuaEngine::describe(&script).unwrap());
+        assert_eq!(Some(String::from("to [subject]")), LuaEngine::argument_spec(&script).unwrap());
+    }
+
+    #[test]
+    fn cache_failed_declarations() {
+        let script = Script::new("/commands/failing.lua", r#"
+            error("failed at load")
+            function argument_spec() return "name" end
+        "#);
+        let err = LuaEngine::describe(&script).unwrap_err();
+        assert_eq!(ScriptErrorKind::Runtime, err.kind());
+        assert_eq!(Some(err.to_string()), script.description_error().map(|err| err.to_string()));
+        assert_eq!(err.to_string(), LuaEngine::argument_spec(&script).unwrap_err().to_string());
+        assert!(script.argument_spec().is_none());
+    }
+
+    #[test]
+    fn describe_records_entry_points() {
+        let script = Script::new("entry-points.lua", r#"
+            function description()
+                return "Sends mail"
+            end
+        "#);
+        assert_eq!(None, script.defines("run"));
+        assert!(LuaEngine::describe(&script).is_ok());
+        assert_eq!(Some(String::from("Sends mail")), script.description());
+        assert_eq!(Some(true), script.defines("description"));
+        assert_eq!(Some(false), script.defines("run"));
+    }
+
+    #[test]
+    fn describe_exceeding_time_limit() {
+        let path = create_script("describe_time_limit", r#"
+            function description()
+                while true do end
+            end
+        "#);
+        let cache = ScriptCache::new();
+        cache.set_limits(Some(Duration::from_millis(50)), None);
+        let script = cache.load(path.to_str().unwrap()).ok().unwrap();
+        let err = LuaEngine::describe(&script).unwrap_err();
+        assert_eq!(ScriptErrorKind::Timeout, err.kind());
+        assert_eq!("describe_time_limit.lua: script exceeded the time limit", &err.summary());
+        cleanup_script(path);
+    }
+
+    #[test]
+    fn describe_exceeding_instruction_limit() {
+        let path = create_script("describe_instruction_limit", r#"
+            function description()
+                while true do end
+            end
+        "#);
+        let cache = ScriptCache::new();
+        cache.set_limits(None, Some(100000));
+        let script = cache.load(path.to_str().unwrap()).ok().unwrap();
+        let err = LuaEngine::describe(&script).unwrap_err();
+        assert_eq!(ScriptErrorKind::Timeout, err.kind());
+        assert_eq!("describe_instruction_limit.lua: script exceeded the instruction limit", &err.summary());
+        cleanup_script(path);
+    }
+
+    #[test]
+    fn describe_within_limits() {
+        let path = create_script("describe_within_limits", r#"
+            function description()
+                local total = 0
+                for i = 1, 1000 do total = total + i end
+                return "" .. total
+            end
+        "#);
+        let cache = ScriptCache::new();
+        cache.set_limits(Some(Duration::from_secs(5)), Some(100000));
+        let script = cache.load(path.to_str().unwrap()).ok().unwrap();
+        assert_eq!(String::from("500500"), LuaEngine::describe(&script).unwrap());
+        cleanup_script(path);
+    }
+
+    #[test]
+    fn limit_json_table_depth() {
+        let mut json = Value::Bool(true);
+        for _ in 0..200 {
+            json = Value::Array(vec![json]);
+        }
+        let mut lua = Lua::new();
+        lua.openlibs();
+        set_json_global(&mut lua, b"result\0", &json);
+        let depth: Option<u32> = lua.execute(r#"
+            local depth, value = 0, result
+            while type(value) == "table" do
+                depth, value = depth + 1, value[1]
+            end
+            return depth
+        "#).ok();
+        assert_eq!(Some(MAX_JSON_DEPTH as u32), depth);
+    }
+
     #[allow(unused_must_use)]
     fn cleanup_script(path: PathBuf) {
         remove_file(path);
@@ -393,4 +1368,194 @@ mod tests {
         }
         dir
     }
+
+    /// A script reporting which standard libraries and functions it can see
+    const SANDBOX_PROBE: &str = r#"
+        function report()
+            return table.concat({tostring(io ~= nil), tostring(os.execute ~= nil),
+                                 tostring(run_command ~= nil), tostring(load_uri ~= nil),
+                                 tostring(webview_uri ~= nil)}, " ")
+        end
+        function run()
+            error(report())
+        end
+        function on_request_uri()
+            load_uri(0, 0, report())
+        end
+    "#;
+
+    #[test]
+    fn execute_with_sandbox_profiles() {
+        let ui = create_ui("sandbox", r#"
+            [commands.sandbox]
+            pure = "pure"
+            isolated = "isolated"
+            unknown = "everything"
+        "#, &[("full.lua", SANDBOX_PROBE), ("pure.lua", SANDBOX_PROBE),
+              ("isolated.lua", SANDBOX_PROBE), ("unknown.lua", SANDBOX_PROBE)]);
+        for &(name, report) in &[("full", "true true true true true"),
+                                 ("pure", "false false false true true"),
+                                 ("isolated", "false false false false true"),
+                                 ("unknown", "false false false false true")] {
+            let output = ui.execute_command(Some(0), name);
+            assert!(output.message.unwrap().contains(report), "{}", name);
+        }
+    }
+
+    #[test]
+    fn buffer_event_with_sandbox_profiles() {
+        let ui = create_ui("sandbox-events", r#"
+            on-request-uri = ["hook", "trusted"]
+            [commands.sandbox]
+            trusted = "full"
+        "#, &[("hook.lua", SANDBOX_PROBE), ("trusted.lua", SANDBOX_PROBE)]);
+        ui.engine.on_buffer_event::<MockUI, LuaEngine>(&ui, 0, 0, Some("http://example.com"), BufferEvent::Request);
+        assert_eq!(vec![String::from("false false false true true"),
+                        String::from("true true true true true")],
+                   *ui.loaded_uris.borrow());
+    }
+
+    const LIBRARY_MODULE: &str = r#"
+        local util = {}
+        function util.ends_with(text, suffix)
+            return suffix == "" or string.sub(text, -#suffix) == suffix
+        end
+        return util
+    "#;
+
+    #[test]
+    fn execute_with_library_modules() {
+        let ui = create_ui("libraries", r#"
+            [commands.sandbox]
+            isolated = "isolated"
+        "#, &[("lib/webkitten/util.lua", LIBRARY_MODULE),
+              ("lib/greeting/init.lua", "return {text = 'hello'}"),
+              ("full.lua", r#"
+                function run()
+                    local util = require("webkitten.util")
+                    set_command_field_text(1, tostring(util.ends_with("init.lua", ".lua")))
+                    return util == require("webkitten.util")
+                end
+              "#),
+              ("isolated.lua", r#"
+                function run()
+                    local util = require("webkitten.util")
+                    local missing = pcall(require, "webkitten.missing")
+                    local escaped = pcall(require, "..config")
+                    log_info(require("greeting").text)
+                    return util.ends_with("init.lua", ".lua") and not missing and not escaped
+                end
+              "#)]);
+        assert!(ui.execute_command(Some(0), "full").is_success());
+        assert_eq!(String::from("true"), ui.command_field_text(1));
+        assert!(ui.execute_command(Some(0), "isolated").is_success());
+        assert!(!ui.execute_command(Some(0), "lib webkitten util").is_success());
+        let names: Vec<String> = Command::list_available(&ui.engine.config, ui.engine.builtins(), &["lua"]).iter()
+            .filter(|command| command.builtin.is_none())
+            .map(|command| command.name())
+            .collect();
+        assert_eq!(vec![String::from("full"), String::from("isolated")], names);
+    }
+
+    const API_PROBE: &str = r#"
+        function run()
+            local window, buffer = webkitten.window, webkitten.buffer
+            window.set_command_text(1, table.concat({
+                webkitten.api_version,
+                tostring(webkitten.NOT_FOUND ~= nil),
+                tostring(buffer.uri ~= nil),
+                tostring(window.open ~= nil),
+                tostring(webkitten.config.lookup_string("commands.label")),
+                tostring(webview_uri ~= nil),
+                tostring(NOT_FOUND ~= nil),
+            }, " "))
+            return true
+        end
+    "#;
+
+    #[test]
+    fn execute_with_api_namespace() {
+        let ui = create_ui("api-namespace", r#"
+            label = "hi"
+            [commands.sandbox]
+            isolated = "isolated"
+        "#, &[("probe.lua", API_PROBE), ("isolated.lua", r#"
+            function run()
+                return webkitten.window.open == nil and webkitten.buffer.uri ~= nil
+                    and open_window == nil and webview_uri ~= nil
+            end
+        "#)]);
+        assert!(ui.execute_command(Some(0), "probe").is_success());
+        assert_eq!(String::from("1 true true true hi true true"), ui.command_field_text(1));
+        assert!(ui.execute_command(Some(0), "isolated").is_success());
+        let ui = create_ui("api-namespace-only", r#"
+            label = "hi"
+            legacy-globals = false
+        "#, &[("probe.lua", API_PROBE)]);
+        assert!(ui.execute_command(Some(0), "probe").is_success());
+        assert_eq!(String::from("1 true true true hi false false"), ui.command_field_text(1));
+    }
+
+    #[test]
+    fn execute_with_javascript_results() {
+        let ui = create_ui("javascript", "", &[("links.lua", r#"
+            function run()
+                evaluate_javascript(0, 0, arguments[1])
+                return true
+            end
+
+            function on_javascript_result()
+                if error_message then
+                    set_command_field_text(1, "failed: " .. error_message)
+                else
+                    local links = {}
+                    for index, link in ipairs(javascript_result.links) do
+                        links[index] = link.href
+                    end
+                    set_command_field_text(1, table.concat(links, ",") .. " " ..
+                        tostring(javascript_result.count) .. " " .. tostring(javascript_result.missing))
+                end
+            end
+        "#), ("report.lua", r#"
+            function run()
+                evaluate_javascript(0, 0, "[1, 2]", "collect sum")
+                return true
+            end
+        "#), ("collect.lua", r#"
+            function on_javascript_result()
+                set_command_field_text(1, arguments[1] .. " " .. tostring(javascript_result[1] + javascript_result[2]))
+            end
+        "#)]);
+        assert!(ui.execute_command(Some(0), r#"links '{"links":[{"href":"a"},{"href":"b"}],"count":2,"missing":null}'"#).is_success());
+        assert_eq!(String::from("a,b 2 nil"), ui.command_field_text(1));
+        assert!(ui.execute_command(Some(0), "links throw").is_success());
+        assert_eq!(String::from("failed: Error: thrown"), ui.command_field_text(1));
+        assert!(ui.execute_command(Some(0), "report").is_success());
+        assert_eq!(String::from("sum 3"), ui.command_field_text(1));
+    }
+
+    #[test]
+    fn javascript_results_use_command_profile() {
+        const PROBE: &str = r#"
+            function run()
+                evaluate_javascript(0, 0, "1", arguments[1])
+                return true
+            end
+
+            function on_javascript_result()
+                set_command_field_text(1, tostring(io ~= nil))
+            end
+        "#;
+        let ui = create_ui("javascript-profile", r#"
+            [commands.sandbox]
+            pure-probe = "pure"
+        "#, &[("probe.lua", PROBE), ("pure-probe.lua", PROBE)]);
+        assert!(ui.execute_command(Some(0), "probe").is_success());
+        assert_eq!(String::from("true"), ui.command_field_text(1));
+        assert!(ui.execute_command(Some(0), "pure-probe").is_success());
+        assert_eq!(String::from("false"), ui.command_field_text(1));
+        ui.set_command_field_text(1, "");
+        assert!(ui.execute_command(Some(0), "pure-probe probe").is_success());
+        assert_eq!(String::from(""), ui.command_field_text(1));
+    }
 }
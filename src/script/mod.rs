@@ -1,12 +1,22 @@
+mod cache;
+mod limits;
 mod lua;
+mod process;
+mod registry;
+mod sandbox;
 
+pub use self::cache::{Script,ScriptCache};
+pub use self::limits::ExecutionLimits;
 pub use self::lua::LuaEngine;
+pub use self::process::ProcessEngine;
+pub use self::registry::{EngineRegistry,Engines,Registrar,ScriptHandler,ScriptRunner};
+pub use self::sandbox::SandboxProfile;
 
 use std::error::Error;
-use std::fs::File;
 use std::fmt;
+use std::path::Path;
 
-use super::ui::{ApplicationUI,BufferEvent};
+use super::ui::{ApplicationUI,BufferEvent,Completion,JavaScriptResult};
 
 /// A sentinel value for representing empty optional numbers to scripting
 /// languages without optionals
@@ -14,9 +24,98 @@ pub const NOT_FOUND: u32 = 483600;
 
 pub type ScriptResult<T> = Result<T, ScriptError>;
 
-#[derive(Debug)]
+/// The cause of a script failing
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ScriptErrorKind {
+    /// The script could not be parsed
+    Parse,
+    /// The script raised an error while running, or could not be run
+    Runtime,
+    /// The script does not define the event trigger being invoked
+    MissingEntryPoint,
+    /// An event trigger returned a value of the wrong type
+    WrongReturnType,
+    /// The script exceeded the time or instruction limit
+    Timeout,
+    /// The script was stopped by a request to cancel running commands
+    Cancelled,
+}
+
+/// A failure to evaluate or run a script, with the location of the failure
+/// when known
+///
+/// ## Examples
+///
+/// ```
+/// use webkitten::script::{ScriptError,ScriptErrorKind};
+///
+/// let err = ScriptError::new(ScriptErrorKind::Runtime, "attempt to call a nil value")
+///     .in_script("/commands/go.lua")
+///     .at_line(3);
+/// assert_eq!("/commands/go.lua:3: attempt to call a nil value", &err.to_string());
+/// assert_eq!("go.lua:3: attempt to call a nil value", &err.summary());
+/// ```
+#[derive(Debug,Clone)]
 pub struct ScriptError {
-    description: String
+    description: String,
+    kind: ScriptErrorKind,
+    path: Option<String>,
+    line: Option<u32>,
+}
+
+impl ScriptError {
+
+    pub fn new<D: Into<String>>(kind: ScriptErrorKind, description: D) -> ScriptError {
+        ScriptError { description: description.into(), kind, path: None, line: None }
+    }
+
+    /// Record the path of the script which failed, unless already known
+    pub fn in_script(mut self, path: &str) -> ScriptError {
+        if self.path.is_none() {
+            self.path = Some(String::from(path));
+        }
+        self
+    }
+
+    /// Record the line of the script where the failure occurred
+    pub fn at_line(mut self, line: u32) -> ScriptError {
+        self.line = Some(line);
+        self
+    }
+
+    /// The cause of the failure
+    pub fn kind(&self) -> ScriptErrorKind {
+        self.kind
+    }
+
+    /// The path of the script which failed, if known
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// The line of the script where the failure occurred, if known
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// A short description of the failure suitable for the command bar,
+    /// identifying the script by file name rather than by path
+    pub fn summary(&self) -> String {
+        let name = self.path.as_ref().map(|path| {
+            Path::new(path).file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or(path.clone())
+        });
+        self.format_location(name.as_deref())
+    }
+
+    fn format_location(&self, name: Option<&str>) -> String {
+        match (name, self.line) {
+            (Some(name), Some(line)) => format!("{}:{}: {}", name, line, self.description),
+            (Some(name), None) => format!("{}: {}", name, self.description),
+            _ => self.description.clone(),
+        }
+    }
 }
 
 impl Error for ScriptError {
@@ -32,43 +131,132 @@ impl Error for ScriptError {
 
 impl fmt::Display for ScriptError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.description)
+        write!(f, "{}", self.format_location(self.path()))
+    }
+}
+
+/// Details of a single use of a script which may differ between commands
+/// resolving to the same command file, so are not cached with the script
+///
+/// ## Examples
+///
+/// ```
+/// use webkitten::script::{Invocation,SandboxProfile};
+///
+/// let invocation = Invocation::new("/config.toml", "bookmark save")
+///     .with_sandbox(Some(SandboxProfile::Pure))
+///     .in_window(Some(1));
+/// assert_eq!("bookmark save", &invocation.command_name);
+/// assert_eq!(Some(1), invocation.window_index);
+/// ```
+#[derive(Debug,Clone,PartialEq)]
+pub struct Invocation {
+    /// The path of the configuration file
+    pub config_path: String,
+    /// The name of the command run using the script
+    pub command_name: String,
+    /// The sandbox profile configured for the command, if any
+    pub sandbox: Option<SandboxProfile>,
+    /// The window the command was run for, if any. Commands without a window
+    /// act on the focused window.
+    pub window_index: Option<u32>,
+}
+
+impl Invocation {
+
+    pub fn new<P: Into<String>, N: Into<String>>(config_path: P, command_name: N) -> Self {
+        Invocation {
+            config_path: config_path.into(),
+            command_name: command_name.into(),
+            sandbox: None,
+            window_index: None,
+        }
+    }
+
+    /// Run the script using a sandbox profile
+    pub fn with_sandbox(mut self, sandbox: Option<SandboxProfile>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Run the script for a window rather than the focused window
+    pub fn in_window(mut self, window_index: Option<u32>) -> Self {
+        self.window_index = window_index;
+        self
     }
 }
 
 /// A scripting runtime and event handler capable of evaluating file contents
 /// within the runtime, converting between internal runtime types and Rust
 /// types, and providing an interface to interaction with the UI.
+///
+/// Scripts are cached between invocations, so an engine may record details
+/// learned from evaluating a script, such as its description and entry
+/// points, in order to skip evaluating it again until the file is modified.
 pub trait ScriptingEngine {
 
     /// The file extension to use when searching for command matches for this
-    /// engine
+    /// engine, or an empty string to match executable files without an
+    /// extension
     fn file_extension() -> &'static str;
 
-    /// Evaluate the contents of a file withn the scripting runtime and execute
-    /// the description event trigger
-    fn describe(file: File) -> ScriptResult<String>;
+    /// All file extensions of command files evaluated by this engine, in
+    /// order of preference
+    fn file_extensions() -> Vec<&'static str> {
+        vec![Self::file_extension()]
+    }
+
+    /// Whether this engine evaluates the command file at a path, based on its
+    /// file extension. An empty file extension matches paths without one.
+    fn handles(path: &str) -> bool {
+        let extension = match Path::new(path).extension() {
+            Some(extension) => extension.to_str(),
+            None => Some(""),
+        };
+        extension.map(|extension| Self::file_extensions().contains(&extension))
+            .unwrap_or(false)
+    }
 
-    /// Evaluate the contents of a file within the scripting runtime and execute
-    /// the event trigger for running a command directly, providing the
+    /// Evaluate the contents of a script withn the scripting runtime and
+    /// execute the description event trigger
+    fn describe(script: &Script) -> ScriptResult<String>;
+
+    /// The argument spec declared by a script, if any, which is validated
+    /// before running the command. See `argspec::ArgumentSpec` for the format.
+    fn argument_spec(_script: &Script) -> ScriptResult<Option<String>> {
+        Ok(None)
+    }
+
+    /// Evaluate the contents of a script within the scripting runtime and
+    /// execute the event trigger for running a command directly, providing the
     /// arguments to the scope
-    fn execute<T, S>(file: File, arguments: Vec<String>, ui: &T, config_path: &str) -> ScriptResult<bool>
+    fn execute<T, S>(script: &Script, arguments: Vec<String>, ui: &T, invocation: &Invocation) -> ScriptResult<bool>
         where T: ApplicationUI<S>,
               S: ScriptingEngine;
 
-    /// Evaluate the contents of a file within the scripting runtime and execute
-    /// the event trigger for getting autocompletion results, providing the
-    /// arguments and prefix to the scope
-    fn autocomplete<T, S>(file: File, arguments: Vec<String>, prefix: &str, ui: &T, config_path: &str) -> ScriptResult<Vec<String>>
+    /// Evaluate the contents of a script within the scripting runtime and
+    /// execute the event trigger for getting autocompletion results, providing
+    /// the arguments and prefix to the scope
+    fn autocomplete<T, S>(script: &Script, arguments: Vec<String>, prefix: &str, ui: &T, invocation: &Invocation) -> ScriptResult<Vec<Completion>>
         where T: ApplicationUI<S>,
               S: ScriptingEngine;
 
-    /// Evaluate the contents of a file within the scripting runtime and execute
-    /// the event trigger matching the BufferEvent, provided the window index,
-    /// webview index, and requested URI to the scope.
-    fn on_buffer_event<T, S>(file: File, ui: &T, config_path: &str, window_index: u32,
+    /// Evaluate the contents of a script within the scripting runtime and
+    /// execute the event trigger matching the BufferEvent, provided the window
+    /// index, webview index, and requested URI to the scope.
+    fn on_buffer_event<T, S>(script: &Script, ui: &T, invocation: &Invocation, window_index: u32,
                              webview_index: u32, requested_uri: Option<&str>,
                              event: &BufferEvent) -> ScriptResult<()>
         where T: ApplicationUI<S>,
               S: ScriptingEngine;
+
+    /// Evaluate the contents of a script within the scripting runtime and
+    /// execute the event trigger for the outcome of evaluating JavaScript,
+    /// providing the arguments, window index, webview index, and result or
+    /// error message to the scope
+    fn on_javascript_result<T, S>(script: &Script, arguments: Vec<String>, ui: &T, invocation: &Invocation,
+                                  window_index: u32, webview_index: u32,
+                                  result: &JavaScriptResult) -> ScriptResult<()>
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine;
 }
@@ -0,0 +1,765 @@
+//! A scripting engine running executable command files as separate processes
+//! which communicate using JSON messages, one per line, over standard input
+//! and output.
+//!
+//! When a command is invoked, the engine writes an invocation message to the
+//! process:
+//!
+//! ```json
+//! {"event": "run", "arguments": ["example.com"], "prefix": null,
+//!  "window_index": 0, "webview_index": 1, "uri": null, "error_message": null,
+//!  "javascript_result": null,
+//!  "config_path": "/home/user/.config/webkitten/config.toml"}
+//! ```
+//!
+//! The event is one of `describe`, `run`, `complete`, `load_uri`, `focus`,
+//! `request_uri`, `fail_uri`, or `javascript_result`. The process may then write any number of UI
+//! requests, such as `{"request": "load_uri", "window_index": 0,
+//! "webview_index": 1, "uri": "https://example.com"}`, each of which is
+//! answered with a response message, such as `{"response": null}`, followed
+//! by a final result, such as `{"result": true}`, or an error, such as
+//! `{"error": "No bookmarks found"}`.
+//!
+//! A process is stopped if it runs longer than the time limit of the script,
+//! and anything it writes to standard error is logged.
+use std::io::{self,BufRead,BufReader,Read,Write};
+use std::process::{Child,ChildStdin,Command,ExitStatus,Stdio};
+use std::sync::mpsc::{Receiver,RecvTimeoutError,channel};
+use std::thread;
+use std::time::{Duration,Instant};
+
+use serde_json::{self,Map,Value};
+use wait_timeout::ChildExt;
+
+use config::Config;
+use super::limits::INTERRUPT_POLL_INTERVAL;
+use super::{Invocation,Script,ScriptError,ScriptErrorKind,ScriptingEngine,ScriptResult};
+use ui::{ApplicationUI,BufferEvent,Completion,JavaScriptResult,WindowArea};
+
+/// A scripting engine which runs executable files without a file extension
+pub struct ProcessEngine;
+
+/// Answers a request from a process, given the request name and its fields
+type RequestHandler<'a> = dyn FnMut(&str, &Map<String, Value>) -> ScriptResult<Value> + 'a;
+
+/// Details of an event sent to a process
+#[derive(Default)]
+struct Message<'a> {
+    event: &'a str,
+    arguments: Vec<String>,
+    prefix: Option<&'a str>,
+    window_index: Option<u32>,
+    webview_index: Option<u32>,
+    uri: Option<&'a str>,
+    error_message: Option<&'a str>,
+    /// The result of evaluating JavaScript for the `javascript_result` event
+    javascript_result: Option<Value>,
+    config_path: Option<&'a str>,
+}
+
+/// The messages received from a process before it exited
+struct Outcome {
+    /// The final result, if any
+    result: Option<Value>,
+    status: ExitStatus,
+}
+
+impl ScriptingEngine for ProcessEngine {
+
+    fn file_extension() -> &'static str {
+        ""
+    }
+
+    fn describe(script: &Script) -> ScriptResult<String> {
+        declare(script)?;
+        script.description().ok_or_else(|| process_error(script, "no description provided"))
+    }
+
+    fn argument_spec(script: &Script) -> ScriptResult<Option<String>> {
+        declare(script)?;
+        Ok(script.argument_spec().and_then(|spec| spec))
+    }
+
+    fn execute<T, S>(script: &Script, arguments: Vec<String>, ui: &T, invocation: &Invocation) -> ScriptResult<bool>
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        let window_index = invocation.window_index.or_else(|| ui.focused_window_index());
+        let message = Message {
+            event: "run",
+            arguments,
+            window_index,
+            webview_index: window_index.and_then(|index| ui.focused_webview_index(index)),
+            config_path: Some(&invocation.config_path),
+            ..Message::default()
+        };
+        let outcome = communicate(script, &message, &mut |name, request| {
+            handle_request::<T, S>(ui, name, request)
+        })?;
+        match outcome.result {
+            Some(Value::Bool(success)) => Ok(success),
+            Some(_) => Err(result_type_error(script, "result must be a boolean")),
+            None => Ok(outcome.status.success()),
+        }
+    }
+
+    fn autocomplete<T, S>(script: &Script, arguments: Vec<String>, prefix: &str, ui: &T, invocation: &Invocation) -> ScriptResult<Vec<Completion>>
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        let window_index = invocation.window_index.or_else(|| ui.focused_window_index());
+        let message = Message {
+            event: "complete",
+            arguments,
+            prefix: Some(prefix),
+            window_index,
+            webview_index: window_index.and_then(|index| ui.focused_webview_index(index)),
+            config_path: Some(&invocation.config_path),
+            ..Message::default()
+        };
+        let outcome = communicate(script, &message, &mut |name, request| {
+            handle_request::<T, S>(ui, name, request)
+        })?;
+        match outcome.result {
+            Some(Value::Array(items)) => items.iter()
+                .map(|item| read_completion(item)
+                     .ok_or_else(|| result_type_error(script, "completions must be strings or objects with a value")))
+                .collect(),
+            Some(_) => Err(result_type_error(script, "result must be an array of completions")),
+            None if outcome.status.success() => Ok(vec![]),
+            None => Err(process_error(script, &format!("exited with {}", outcome.status))),
+        }
+    }
+
+    fn on_buffer_event<T, S>(script: &Script, ui: &T, invocation: &Invocation, window_index: u32,
+                             webview_index: u32, requested_uri: Option<&str>,
+                             event: &BufferEvent) -> ScriptResult<()>
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        let (name, error_message) = match event {
+            BufferEvent::Load => ("load_uri", None),
+            BufferEvent::Focus => ("focus", None),
+            BufferEvent::Request => ("request_uri", None),
+            BufferEvent::Fail(message) => ("fail_uri", Some(message.as_str())),
+        };
+        let message = Message {
+            event: name,
+            window_index: Some(window_index),
+            webview_index: Some(webview_index),
+            uri: requested_uri,
+            error_message,
+            config_path: Some(&invocation.config_path),
+            ..Message::default()
+        };
+        let outcome = communicate(script, &message, &mut |name, request| {
+            handle_request::<T, S>(ui, name, request)
+        })?;
+        if outcome.result.is_none() && !outcome.status.success() {
+            return Err(process_error(script, &format!("exited with {}", outcome.status)));
+        }
+        Ok(())
+    }
+
+    fn on_javascript_result<T, S>(script: &Script, arguments: Vec<String>, ui: &T, invocation: &Invocation,
+                                  window_index: u32, webview_index: u32,
+                                  result: &JavaScriptResult) -> ScriptResult<()>
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        let (javascript_result, error_message) = match *result {
+            Ok(ref json) => (Some(serde_json::from_str(json).unwrap_or(Value::Null)), None),
+            Err(ref message) => (None, Some(message.as_str())),
+        };
+        let message = Message {
+            event: "javascript_result",
+            arguments,
+            window_index: Some(window_index),
+            webview_index: Some(webview_index),
+            error_message,
+            javascript_result,
+            config_path: Some(&invocation.config_path),
+            ..Message::default()
+        };
+        let outcome = communicate(script, &message, &mut |name, request| {
+            handle_request::<T, S>(ui, name, request)
+        })?;
+        if outcome.result.is_none() && !outcome.status.success() {
+            return Err(process_error(script, &format!("exited with {}", outcome.status)));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Message<'a> {
+
+    fn to_json(&self) -> Value {
+        let mut message = Map::new();
+        message.insert(String::from("event"), Value::from(self.event));
+        message.insert(String::from("arguments"), Value::from(self.arguments.clone()));
+        message.insert(String::from("prefix"), Value::from(self.prefix));
+        message.insert(String::from("window_index"), Value::from(self.window_index));
+        message.insert(String::from("webview_index"), Value::from(self.webview_index));
+        message.insert(String::from("uri"), Value::from(self.uri));
+        message.insert(String::from("error_message"), Value::from(self.error_message));
+        message.insert(String::from("javascript_result"), self.javascript_result.clone().unwrap_or(Value::Null));
+        message.insert(String::from("config_path"), Value::from(self.config_path));
+        Value::Object(message)
+    }
+}
+
+/// Send the `describe` event to a command file unless previously sent,
+/// saving the description and argument spec from the result, which is either
+/// a description or an object with `description` and `arguments` keys. A
+/// failure is also saved, so the process is not run again until the file
+/// changes.
+fn declare(script: &Script) -> ScriptResult<()> {
+    if let Some(err) = script.description_error() {
+        return Err(err);
+    }
+    if script.argument_spec().is_some() {
+        return Ok(());
+    }
+    match request_declaration(script) {
+        Ok(spec) => {
+            script.set_argument_spec(spec.as_deref());
+            Ok(())
+        },
+        Err(err) => {
+            script.set_description_error(err.clone());
+            Err(err)
+        },
+    }
+}
+
+/// Run the `describe` event, saving the description and returning the
+/// argument spec, if any
+fn request_declaration(script: &Script) -> ScriptResult<Option<String>> {
+    let message = Message { event: "describe", ..Message::default() };
+    let outcome = communicate(script, &message, &mut |name, _| {
+        Err(process_error(script, &format!("'{}' is unavailable while describing a command", name)))
+    })?;
+    match outcome.result {
+        Some(Value::String(description)) => script.set_description(&description),
+        Some(Value::Object(declaration)) => {
+            if let Some(description) = declaration.get("description").and_then(|value| value.as_str()) {
+                script.set_description(description);
+            }
+            return Ok(declaration.get("arguments").and_then(|value| value.as_str()).map(String::from));
+        },
+        Some(_) => return Err(result_type_error(script, "description must be a string or object")),
+        None if !outcome.status.success() => {
+            return Err(process_error(script, &format!("exited with {}", outcome.status)));
+        },
+        None => (),
+    }
+    Ok(None)
+}
+
+/// Run a command file, sending an invocation and answering UI requests until
+/// the process sends a result or exits. The process is stopped once it runs
+/// longer than the time limit of the script, or if running scripts are
+/// cancelled by a request.
+fn communicate(script: &Script, message: &Message,
+               handler: &mut RequestHandler) -> ScriptResult<Outcome> {
+    let deadline = script.limits().time_limit.map(|limit| Instant::now() + limit);
+    let marker = script.limits().cancellation_marker();
+    let mut child = Command::new(script.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| process_error(script, &format!("failed to start: {}", err)))?;
+    if let Some(stderr) = child.stderr.take() {
+        log_errors(script.path(), stderr);
+    }
+    let mut stdin = child.stdin.take();
+    let lines = child.stdout.take().map(read_lines);
+    if let Err(err) = send(&mut stdin, &message.to_json()) {
+        info!("{} closed input before invocation: {}", script.path(), err);
+    }
+    let result = match lines {
+        Some(lines) => receive(script, &lines, deadline, marker, &mut stdin, handler)
+            .map_err(|err| err.in_script(script.path())),
+        None => Ok(None),
+    };
+    drop(stdin);
+    if result.is_err() {
+        let _ = child.kill();
+    }
+    let status = match wait(script, &mut child, deadline)? {
+        Some(status) => status,
+        None => {
+            let _ = child.kill();
+            let status = child.wait()
+                .map_err(|err| process_error(script, &format!("failed to exit: {}", err)))?;
+            if let Ok(None) = result {
+                return Err(timeout_error(script));
+            }
+            status
+        },
+    };
+    result.map(|result| Outcome { result, status })
+}
+
+/// Wait for a process to exit, or `None` if it is still running once the
+/// deadline passes
+fn wait(script: &Script, child: &mut Child, deadline: Option<Instant>) -> ScriptResult<Option<ExitStatus>> {
+    match deadline {
+        Some(deadline) => child.wait_timeout(remaining(deadline)),
+        None => child.wait().map(Some),
+    }.map_err(|err| process_error(script, &format!("failed to exit: {}", err)))
+}
+
+/// Read messages from a process until it sends a result or closes its output
+fn receive(script: &Script, lines: &Receiver<io::Result<String>>, deadline: Option<Instant>,
+           cancellation_marker: usize, stdin: &mut Option<ChildStdin>,
+           handler: &mut RequestHandler) -> ScriptResult<Option<Value>> {
+    while let Some(line) = next_line(script, lines, deadline, cancellation_marker)? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = match serde_json::from_str(&line) {
+            Ok(Value::Object(message)) => message,
+            _ => return Err(process_error(script, &format!("invalid message: {}", line))),
+        };
+        if let Some(error) = message.get("error") {
+            let description = error.as_str().map(String::from).unwrap_or(error.to_string());
+            return Err(process_error(script, &description));
+        }
+        if let Some(result) = message.get("result") {
+            return Ok(Some(result.clone()));
+        }
+        match message.get("request").and_then(|name| name.as_str()) {
+            Some(name) => {
+                let response = handler(name, &message)?;
+                if script.limits().is_cancelled_since(cancellation_marker) {
+                    return Err(ScriptError::new(ScriptErrorKind::Cancelled, "process was cancelled"));
+                }
+                let mut reply = Map::new();
+                reply.insert(String::from("response"), response);
+                if send(stdin, &Value::Object(reply)).is_err() {
+                    info!("{} closed input before response to '{}'", script.path(), name);
+                }
+            },
+            None => return Err(process_error(script, &format!("invalid message: {}", line))),
+        }
+    }
+    Ok(None)
+}
+
+/// The next line written by a process, or `None` once its output is closed.
+/// While waiting, the interrupt check of the script is polled so that input
+/// requesting cancellation stops the process.
+fn next_line(script: &Script, lines: &Receiver<io::Result<String>>,
+             deadline: Option<Instant>, cancellation_marker: usize) -> ScriptResult<Option<String>> {
+    let polling = script.limits().interrupt_check.is_some();
+    let line = loop {
+        let wait = match deadline {
+            Some(deadline) if polling => Some(remaining(deadline).min(INTERRUPT_POLL_INTERVAL)),
+            Some(deadline) => Some(remaining(deadline)),
+            None if polling => Some(INTERRUPT_POLL_INTERVAL),
+            None => None,
+        };
+        let received = match wait {
+            Some(wait) => lines.recv_timeout(wait),
+            None => lines.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(line) => break line,
+            Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            Err(RecvTimeoutError::Timeout) => {
+                if deadline.map(|deadline| Instant::now() >= deadline).unwrap_or(false) {
+                    return Err(timeout_error(script));
+                }
+                script.limits().poll_interrupt();
+                if script.limits().is_cancelled_since(cancellation_marker) {
+                    return Err(ScriptError::new(ScriptErrorKind::Cancelled, "process was cancelled"));
+                }
+            },
+        }
+    };
+    line.map(Some)
+        .map_err(|err| process_error(script, &format!("failed to read output: {}", err)))
+}
+
+/// Read lines from process output on another thread, so that reading can
+/// stop once the time limit passes
+fn read_lines<R: Read + Send + 'static>(output: R) -> Receiver<io::Result<String>> {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            let failed = line.is_err();
+            if sender.send(line).is_err() || failed {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Log each line a process writes to standard error
+fn log_errors<R: Read + Send + 'static>(path: &str, output: R) {
+    let path = String::from(path);
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            match line {
+                Ok(line) => warn!("{}: {}", path, line),
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+fn remaining(deadline: Instant) -> Duration {
+    deadline.saturating_duration_since(Instant::now())
+}
+
+fn send(stdin: &mut Option<ChildStdin>, message: &Value) -> ScriptResult<()> {
+    match *stdin {
+        Some(ref mut stdin) => writeln!(stdin, "{}", message)
+            .and_then(|_| stdin.flush())
+            .map_err(|err| ScriptError::new(ScriptErrorKind::Runtime, format!("failed to write message: {}", err))),
+        None => Ok(()),
+    }
+}
+
+/// Perform a UI request sent by a process, returning the response
+fn handle_request<T, S>(ui: &T, name: &str, request: &Map<String, Value>) -> ScriptResult<Value>
+    where T: ApplicationUI<S>,
+          S: ScriptingEngine {
+    info!("process request: {}", name);
+    let response = match name {
+        "log_info" => { info!("{}", string(request, name, "message")?); Value::Null },
+        "log_debug" => { debug!("{}", string(request, name, "message")?); Value::Null },
+        "copy" => { ui.copy(&string(request, name, "text")?); Value::Null },
+        "run_command" => {
+            let output = ui.execute_command(optional_index(request, "window_index"),
+                                            &string(request, name, "command")?);
+            let mut response = Map::new();
+            response.insert(String::from("success"), Value::from(output.is_success()));
+            response.insert(String::from("message"), Value::from(output.message));
+            Value::Object(response)
+        },
+        "window_count" => Value::from(ui.window_count()),
+        "focused_window_index" => Value::from(ui.focused_window_index()),
+        "open_window" => Value::from(ui.open_window::<_, Config>(optional_string(request, "uri"), None)),
+        "close_window" => { ui.close_window(index(request, name, "window_index")?); Value::Null },
+        "focus_window" => { ui.focus_window(index(request, name, "window_index")?); Value::Null },
+        "focus_commandbar_in_window" => {
+            ui.focus_window_area(index(request, name, "window_index")?, WindowArea::CommandBar);
+            Value::Null
+        },
+        "focus_webview_in_window" => {
+            ui.focus_window_area(index(request, name, "window_index")?, WindowArea::WebView);
+            Value::Null
+        },
+        "window_title" => Value::from(ui.window_title(index(request, name, "window_index")?)),
+        "set_window_title" => {
+            ui.set_window_title(index(request, name, "window_index")?, &string(request, name, "title")?);
+            Value::Null
+        },
+        "command_field_text" => Value::from(ui.command_field_text(index(request, name, "window_index")?)),
+        "set_command_field_text" => {
+            ui.set_command_field_text(index(request, name, "window_index")?, &string(request, name, "text")?);
+            Value::Null
+        },
+        "command_field_visible" => Value::from(ui.command_field_visible(index(request, name, "window_index")?)),
+        "set_command_field_visible" => {
+            ui.set_command_field_visible(index(request, name, "window_index")?, boolean(request, name, "visible")?);
+            Value::Null
+        },
+        "command_history" => Value::from(ui.command_history(index(request, name, "count")?)),
+        "webview_count" => Value::from(ui.webview_count(index(request, name, "window_index")?)),
+        "focused_webview_index" => Value::from(ui.focused_webview_index(index(request, name, "window_index")?)),
+        "open_webview" => {
+            ui.open_webview::<_, Config>(index(request, name, "window_index")?,
+                                         optional_string(request, "uri"), None);
+            Value::Null
+        },
+        _ => return handle_webview_request::<T, S>(ui, name, request),
+    };
+    Ok(response)
+}
+
+/// Perform a UI request which applies to a single webview
+fn handle_webview_request<T, S>(ui: &T, name: &str, request: &Map<String, Value>) -> ScriptResult<Value>
+    where T: ApplicationUI<S>,
+          S: ScriptingEngine {
+    let window_index = index(request, name, "window_index")?;
+    let webview_index = index(request, name, "webview_index")?;
+    let response = match name {
+        "close_webview" => { ui.close_webview(window_index, webview_index); Value::Null },
+        "focus_webview" => { ui.focus_webview(window_index, webview_index); Value::Null },
+        "reload_webview" => {
+            let disable_filters = request.get("disable_filters").and_then(|value| value.as_bool());
+            ui.reload_webview(window_index, webview_index, disable_filters.unwrap_or(false));
+            Value::Null
+        },
+        "load_uri" => { ui.set_uri(window_index, webview_index, &string(request, name, "uri")?); Value::Null },
+        "go_back" => Value::from(ui.go_back(window_index, webview_index)),
+        "go_forward" => Value::from(ui.go_forward(window_index, webview_index)),
+        "webview_uri" => Value::from(ui.uri(window_index, webview_index)),
+        "webview_title" => Value::from(ui.webview_title(window_index, webview_index)),
+        "find" => {
+            ui.find_string(window_index, webview_index, &string(request, name, "query")?);
+            Value::Null
+        },
+        "hide_find" => { ui.hide_find_results(window_index, webview_index); Value::Null },
+        "run_javascript" => {
+            ui.run_javascript(window_index, webview_index, &string(request, name, "script")?);
+            Value::Null
+        },
+        "evaluate_javascript" => {
+            ui.evaluate_javascript(window_index, webview_index, &string(request, name, "script")?,
+                                   &string(request, name, "command")?);
+            Value::Null
+        },
+        "add_styles" => {
+            ui.apply_styles(window_index, webview_index, &string(request, name, "styles")?);
+            Value::Null
+        },
+        _ => return Err(ScriptError::new(ScriptErrorKind::Runtime, format!("unknown request '{}'", name))),
+    };
+    Ok(response)
+}
+
+/// A completion from a string value or an object with a value and optional
+/// label and description
+fn read_completion(item: &Value) -> Option<Completion> {
+    if let Some(value) = item.as_str() {
+        return Some(Completion::new(value));
+    }
+    let item = item.as_object()?;
+    let value = item.get("value").and_then(|value| value.as_str())?;
+    let mut completion = match item.get("description").and_then(|value| value.as_str()) {
+        Some(description) => Completion::described(value, description),
+        None => Completion::new(value),
+    };
+    if let Some(label) = item.get("label").and_then(|value| value.as_str()) {
+        completion.label = String::from(label);
+    }
+    Some(completion)
+}
+
+fn index(request: &Map<String, Value>, name: &str, key: &str) -> ScriptResult<u32> {
+    request.get(key).and_then(|value| value.as_u64())
+        .map(|value| value as u32)
+        .ok_or_else(|| missing_parameter(name, key))
+}
+
+fn optional_index(request: &Map<String, Value>, key: &str) -> Option<u32> {
+    request.get(key).and_then(|value| value.as_u64()).map(|value| value as u32)
+}
+
+fn string(request: &Map<String, Value>, name: &str, key: &str) -> ScriptResult<String> {
+    optional_string(request, key).ok_or_else(|| missing_parameter(name, key))
+}
+
+fn optional_string(request: &Map<String, Value>, key: &str) -> Option<String> {
+    request.get(key).and_then(|value| value.as_str()).map(String::from)
+}
+
+fn boolean(request: &Map<String, Value>, name: &str, key: &str) -> ScriptResult<bool> {
+    request.get(key).and_then(|value| value.as_bool())
+        .ok_or_else(|| missing_parameter(name, key))
+}
+
+fn missing_parameter(name: &str, key: &str) -> ScriptError {
+    ScriptError::new(ScriptErrorKind::Runtime, format!("'{}' request requires '{}'", name, key))
+}
+
+fn process_error(script: &Script, description: &str) -> ScriptError {
+    ScriptError::new(ScriptErrorKind::Runtime, description).in_script(script.path())
+}
+
+fn timeout_error(script: &Script) -> ScriptError {
+    ScriptError::new(ScriptErrorKind::Timeout, "process exceeded the time limit").in_script(script.path())
+}
+
+/// An error for a result of the wrong type for the event
+fn result_type_error(script: &Script, description: &str) -> ScriptError {
+    ScriptError::new(ScriptErrorKind::WrongReturnType, description).in_script(script.path())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs::{Permissions,read_to_string,set_permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use super::*;
+    use script::{LuaEngine,Script,ScriptCache};
+    use tests::{MockUI,TestDir,create_ui};
+
+    #[allow(unused_must_use)]
+    fn create_script(dir: &TestDir, name: &str, contents: &str) -> Script {
+        let path = dir.write(name, &format!("#!/bin/sh\n{}", contents));
+        set_permissions(&path, Permissions::from_mode(0o755));
+        Script::open(path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn describe_command() {
+        let dir = TestDir::new("process-describe");
+        let script = create_script(&dir, "describe", r#"
+            read invocation
+            echo '{"result": "Says hello"}'
+        "#);
+        assert_eq!("Says hello", &ProcessEngine::describe(&script).unwrap());
+        assert_eq!(Some(String::from("Says hello")), script.description());
+        assert_eq!(None, ProcessEngine::argument_spec(&script).unwrap());
+    }
+
+    #[test]
+    fn declare_argument_spec() {
+        let dir = TestDir::new("process-declare");
+        let script = create_script(&dir, "declare", r#"
+            read invocation
+            echo '{"result": {"description": "Opens a bookmark", "arguments": "name [mode:tab|window]"}}'
+        "#);
+        assert_eq!(Some(String::from("name [mode:tab|window]")),
+                   ProcessEngine::argument_spec(&script).unwrap());
+        assert_eq!("Opens a bookmark", &ProcessEngine::describe(&script).unwrap());
+    }
+
+    #[test]
+    fn cache_failed_description() {
+        let dir = TestDir::new("process-describe-failure");
+        let count = dir.path().join("count");
+        let script = create_script(&dir, "failure", &format!(r#"
+            read invocation
+            echo run >> "{}"
+            echo 'warning' >&2
+            exit 1
+        "#, count.display()));
+        assert!(ProcessEngine::describe(&script).is_err());
+        assert!(ProcessEngine::argument_spec(&script).is_err());
+        assert!(ProcessEngine::describe(&script).is_err());
+        assert_eq!("run\n", &read_to_string(&count).unwrap());
+    }
+
+    #[test]
+    fn stop_after_time_limit() {
+        let ui = create_ui("process-timeout", "", &[]);
+        let path = ui.dir.as_ref().unwrap().write("slow", "#!/bin/sh\nread invocation\nsleep 5\n");
+        set_permissions(&path, Permissions::from_mode(0o755)).unwrap();
+        let cache = ScriptCache::new();
+        cache.set_limits(Some(Duration::from_millis(200)), None);
+        let script = cache.load(path.to_str().unwrap()).unwrap();
+        let started = Instant::now();
+        let result = ProcessEngine::execute::<MockUI, LuaEngine>(&script, vec![], &ui, &Invocation::new("config.toml", "test"));
+        assert_eq!(ScriptErrorKind::Timeout, result.unwrap_err().kind());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn stop_after_cancel_request() {
+        let dir = TestDir::new("process-cancel");
+        let path = dir.write("cancel", r#"#!/bin/sh
+            read invocation
+            echo '{"request": "run_command", "command": "cancel"}'
+            read response
+            echo '{"result": true}'
+        "#);
+        set_permissions(&path, Permissions::from_mode(0o755)).unwrap();
+        let cache = ScriptCache::new();
+        let script = cache.load(path.to_str().unwrap()).unwrap();
+        let message = Message { event: "run", ..Message::default() };
+        let result = communicate(&script, &message, &mut |_, _| {
+            cache.cancel();
+            Ok(Value::Null)
+        });
+        assert_eq!(ScriptErrorKind::Cancelled, result.err().unwrap().kind());
+    }
+
+    #[test]
+    fn stop_on_interrupt() {
+        fn interrupt() -> bool { true }
+        let dir = TestDir::new("process-interrupt");
+        let path = dir.write("wait", r#"#!/bin/sh
+            read invocation
+            sleep 10
+            echo '{"result": true}'
+        "#);
+        set_permissions(&path, Permissions::from_mode(0o755)).unwrap();
+        let cache = ScriptCache::new();
+        cache.set_interrupt_check(interrupt);
+        let script = cache.load(path.to_str().unwrap()).unwrap();
+        let message = Message { event: "run", ..Message::default() };
+        let started = Instant::now();
+        let result = communicate(&script, &message, &mut |_, _| Ok(Value::Null));
+        assert_eq!(ScriptErrorKind::Cancelled, result.err().unwrap().kind());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn execute_with_requests() {
+        let ui = create_ui("process-requests", "", &[]);
+        let script = create_script(ui.dir.as_ref().unwrap(), "requests", r#"
+            read invocation
+            echo "$invocation" | grep -q '"arguments":\["example.com"\]' || exit 1
+            echo '{"request": "load_uri", "window_index": 0, "webview_index": 0, "uri": "https://example.com"}'
+            read response
+            echo '{"request": "set_command_field_text", "window_index": 0, "text": "loaded"}'
+            read response
+            echo '{"request": "command_field_text", "window_index": 0}'
+            read response
+            [ "$response" = '{"response":"loaded"}' ] && echo '{"result": true}' || echo '{"result": false}'
+        "#);
+        let result = ProcessEngine::execute::<MockUI, LuaEngine>(&script, vec![String::from("example.com")],
+                                                                 &ui, &Invocation::new("config.toml", "test"));
+        assert!(result.unwrap());
+        assert_eq!(vec![String::from("https://example.com")], *ui.loaded_uris.borrow());
+    }
+
+    #[test]
+    fn execute_without_result() {
+        let ui = create_ui("process-status", "", &[]);
+        let script = create_script(ui.dir.as_ref().unwrap(), "status", "exit 3");
+        let result = ProcessEngine::execute::<MockUI, LuaEngine>(&script, vec![], &ui, &Invocation::new("config.toml", "test"));
+        assert!(!result.unwrap());
+    }
+
+    #[test]
+    fn execute_with_error() {
+        let ui = create_ui("process-error", "", &[]);
+        let script = create_script(ui.dir.as_ref().unwrap(), "error", r#"
+            read invocation
+            echo '{"error": "No bookmarks found"}'
+        "#);
+        let result = ProcessEngine::execute::<MockUI, LuaEngine>(&script, vec![], &ui, &Invocation::new("config.toml", "test"));
+        assert!(format!("{}", result.unwrap_err()).ends_with("No bookmarks found"));
+        let script = create_script(ui.dir.as_ref().unwrap(), "invalid", "echo 'not json'");
+        assert!(ProcessEngine::execute::<MockUI, LuaEngine>(&script, vec![], &ui, &Invocation::new("config.toml", "test")).is_err());
+    }
+
+    #[test]
+    fn complete_command() {
+        let ui = create_ui("process-complete", "", &[]);
+        let script = create_script(ui.dir.as_ref().unwrap(), "complete", r#"
+            read invocation
+            echo '{"result": ["one", {"label": "Two", "value": "2", "description": "Second"}]}'
+        "#);
+        let completions = ProcessEngine::autocomplete::<MockUI, LuaEngine>(&script, vec![], "bookmark ",
+                                                                           &ui, &Invocation::new("config.toml", "test")).unwrap();
+        assert_eq!(vec![Completion::new("one"), Completion {
+            label: String::from("Two"),
+            value: String::from("2"),
+            description: Some(String::from("Second")),
+        }], completions);
+    }
+
+    #[test]
+    fn javascript_result() {
+        let ui = create_ui("process-javascript", "", &[]);
+        let script = create_script(ui.dir.as_ref().unwrap(), "javascript", r#"
+            read invocation
+            echo "$invocation" | grep -q '"javascript_result":{"title":"Example"}' || exit 1
+            echo '{"request": "load_uri", "window_index": 0, "webview_index": 0, "uri": "https://example.com"}'
+            read response
+            echo '{"result": null}'
+        "#);
+        let result = Ok(String::from(r#"{"title": "Example"}"#));
+        ProcessEngine::on_javascript_result::<MockUI, LuaEngine>(&script, vec![], &ui, &Invocation::new("config.toml", "test"),
+                                                                 0, 0, &result).unwrap();
+        assert_eq!(vec![String::from("https://example.com")], *ui.loaded_uris.borrow());
+        let result = Err(String::from("ReferenceError"));
+        assert!(ProcessEngine::on_javascript_result::<MockUI, LuaEngine>(&script, vec![], &ui, &Invocation::new("config.toml", "test"),
+                                                                         0, 0, &result).is_err());
+    }
+}
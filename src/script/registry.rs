@@ -0,0 +1,362 @@
+//! Registry of scripting engines keyed by file extension, allowing command
+//! files for several engines to coexist in the same search paths
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use super::{Invocation,LuaEngine,ProcessEngine,Script,ScriptError,ScriptErrorKind,
+            ScriptingEngine,ScriptResult};
+use ui::{ApplicationUI,BufferEvent,Completion,JavaScriptResult};
+
+/// The operations of a scripting engine which do not need the UI
+pub trait ScriptHandler {
+
+    /// All file extensions of command files evaluated by the engine, in
+    /// order of preference
+    fn file_extensions(&self) -> Vec<&'static str>;
+
+    /// Evaluate a script and return its description
+    fn describe(&self, script: &Script) -> ScriptResult<String>;
+
+    /// The argument spec declared by a script, if any
+    fn argument_spec(&self, script: &Script) -> ScriptResult<Option<String>>;
+}
+
+/// The operations of a scripting engine run against a UI of type `T`
+pub trait ScriptRunner<T, S>: ScriptHandler {
+
+    /// Run a script as a command
+    fn execute(&self, script: &Script, arguments: Vec<String>, ui: &T,
+               invocation: &Invocation) -> ScriptResult<bool>;
+
+    /// Run the autocompletion event trigger of a script
+    fn autocomplete(&self, script: &Script, arguments: Vec<String>, prefix: &str, ui: &T,
+                    invocation: &Invocation) -> ScriptResult<Vec<Completion>>;
+
+    /// Run the event trigger of a script matching a buffer event in the
+    /// buffer at a window index and webview index
+    fn on_buffer_event(&self, script: &Script, ui: &T, invocation: &Invocation, buffer: (u32, u32),
+                       requested_uri: Option<&str>, event: &BufferEvent) -> ScriptResult<()>;
+
+    /// Run the event trigger of a script for the outcome of evaluating
+    /// JavaScript in the buffer at a window index and webview index
+    fn on_javascript_result(&self, script: &Script, arguments: Vec<String>, ui: &T,
+                            invocation: &Invocation, buffer: (u32, u32),
+                            result: &JavaScriptResult) -> ScriptResult<()>;
+}
+
+/// A collection which scripting engines can be added to
+pub trait Registrar {
+
+    /// Add an engine for each of its file extensions not already claimed by
+    /// an earlier engine
+    fn register<E>(&mut self) where E: ScriptingEngine + 'static;
+}
+
+/// Scripting engines keyed by the file extensions they evaluate, resolved
+/// when a script is run rather than when the application is compiled
+///
+/// ## Examples
+///
+/// ```
+/// use webkitten::script::{EngineRegistry,LuaEngine,Registrar,Script,ScriptHandler};
+///
+/// let mut registry: EngineRegistry<dyn ScriptHandler> = EngineRegistry::new();
+/// registry.register::<LuaEngine>();
+/// assert_eq!(vec!["lua"], registry.file_extensions());
+/// assert!(registry.get("/commands/go.lua").is_ok());
+/// assert!(registry.get("/commands/go.js").is_err());
+/// ```
+pub struct EngineRegistry<H: ?Sized> {
+    engines: Vec<Box<H>>,
+    extensions: Vec<&'static str>,
+    index: HashMap<&'static str, usize>,
+}
+
+impl<H: ?Sized> EngineRegistry<H> {
+
+    /// A registry without any engines
+    pub fn new() -> Self {
+        EngineRegistry { engines: vec![], extensions: vec![], index: HashMap::new() }
+    }
+
+    /// All file extensions of registered engines, in order of preference
+    pub fn file_extensions(&self) -> Vec<&'static str> {
+        self.extensions.clone()
+    }
+
+    /// The engine evaluating the command file at a path, based on its file
+    /// extension. An empty file extension matches paths without one.
+    pub fn get(&self, path: &str) -> ScriptResult<&H> {
+        let extension = match Path::new(path).extension() {
+            Some(extension) => extension.to_str(),
+            None => Some(""),
+        };
+        extension.and_then(|extension| self.index.get(extension))
+            .map(|position| &*self.engines[*position])
+            .ok_or_else(|| ScriptError::new(ScriptErrorKind::Runtime, "no scripting engine found")
+                .in_script(path))
+    }
+
+    fn insert(&mut self, extensions: Vec<&'static str>, engine: Box<H>) {
+        let position = self.engines.len();
+        let mut claimed = false;
+        for extension in extensions {
+            if !self.index.contains_key(extension) {
+                self.index.insert(extension, position);
+                self.extensions.push(extension);
+                claimed = true;
+            }
+        }
+        if claimed {
+            self.engines.push(engine);
+        }
+    }
+}
+
+impl<H: ?Sized> Default for EngineRegistry<H> {
+    fn default() -> Self {
+        EngineRegistry::new()
+    }
+}
+
+impl Registrar for EngineRegistry<dyn ScriptHandler> {
+    fn register<E>(&mut self) where E: ScriptingEngine + 'static {
+        self.insert(E::file_extensions(), Box::new(Handler::<E>::new()));
+    }
+}
+
+impl<T, S> Registrar for EngineRegistry<dyn ScriptRunner<T, S>>
+    where T: ApplicationUI<S>,
+          S: ScriptingEngine {
+    fn register<E>(&mut self) where E: ScriptingEngine + 'static {
+        self.insert(E::file_extensions(), Box::new(Handler::<E>::new()));
+    }
+}
+
+/// The scripting engines provided with webkitten, dispatching each command
+/// file to the engine registered for its file extension
+///
+/// ## Examples
+///
+/// ```
+/// use webkitten::script::{Engines,ScriptingEngine};
+///
+/// assert_eq!(vec!["lua", ""], Engines::file_extensions());
+/// assert!(Engines::handles("/commands/go.lua"));
+/// assert!(!Engines::handles("/commands/go.js"));
+/// ```
+pub struct Engines;
+
+impl Engines {
+
+    /// Add the provided engines to a registry, in order of preference
+    pub fn register<R: Registrar>(registry: &mut R) {
+        registry.register::<LuaEngine>();
+        registry.register::<ProcessEngine>();
+    }
+
+    fn handlers() -> EngineRegistry<dyn ScriptHandler> {
+        let mut registry = EngineRegistry::new();
+        Engines::register(&mut registry);
+        registry
+    }
+
+    fn runners<T, S>() -> EngineRegistry<dyn ScriptRunner<T, S>>
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        let mut registry = EngineRegistry::new();
+        Engines::register(&mut registry);
+        registry
+    }
+}
+
+impl ScriptingEngine for Engines {
+
+    fn file_extension() -> &'static str {
+        Engines::handlers().file_extensions()[0]
+    }
+
+    fn file_extensions() -> Vec<&'static str> {
+        Engines::handlers().file_extensions()
+    }
+
+    fn describe(script: &Script) -> ScriptResult<String> {
+        Engines::handlers().get(script.path())?.describe(script)
+    }
+
+    fn argument_spec(script: &Script) -> ScriptResult<Option<String>> {
+        Engines::handlers().get(script.path())?.argument_spec(script)
+    }
+
+    fn execute<T, S>(script: &Script, arguments: Vec<String>, ui: &T, invocation: &Invocation) -> ScriptResult<bool>
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        Engines::runners::<T, S>().get(script.path())?
+            .execute(script, arguments, ui, invocation)
+    }
+
+    fn autocomplete<T, S>(script: &Script, arguments: Vec<String>, prefix: &str, ui: &T, invocation: &Invocation) -> ScriptResult<Vec<Completion>>
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        Engines::runners::<T, S>().get(script.path())?
+            .autocomplete(script, arguments, prefix, ui, invocation)
+    }
+
+    fn on_buffer_event<T, S>(script: &Script, ui: &T, invocation: &Invocation, window_index: u32,
+                             webview_index: u32, requested_uri: Option<&str>,
+                             event: &BufferEvent) -> ScriptResult<()>
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        Engines::runners::<T, S>().get(script.path())?
+            .on_buffer_event(script, ui, invocation, (window_index, webview_index), requested_uri, event)
+    }
+
+    fn on_javascript_result<T, S>(script: &Script, arguments: Vec<String>, ui: &T, invocation: &Invocation,
+                                  window_index: u32, webview_index: u32,
+                                  result: &JavaScriptResult) -> ScriptResult<()>
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine {
+        Engines::runners::<T, S>().get(script.path())?
+            .on_javascript_result(script, arguments, ui, invocation, (window_index, webview_index), result)
+    }
+}
+
+/// Adapts the associated functions of a `ScriptingEngine` to a registry
+/// entry
+struct Handler<E> {
+    engine: PhantomData<E>,
+}
+
+impl<E> Handler<E> {
+    fn new() -> Self {
+        Handler { engine: PhantomData }
+    }
+}
+
+impl<E: ScriptingEngine> ScriptHandler for Handler<E> {
+
+    fn file_extensions(&self) -> Vec<&'static str> {
+        E::file_extensions()
+    }
+
+    fn describe(&self, script: &Script) -> ScriptResult<String> {
+        E::describe(script)
+    }
+
+    fn argument_spec(&self, script: &Script) -> ScriptResult<Option<String>> {
+        E::argument_spec(script)
+    }
+}
+
+impl<E, T, S> ScriptRunner<T, S> for Handler<E>
+    where E: ScriptingEngine,
+          T: ApplicationUI<S>,
+          S: ScriptingEngine {
+
+    fn execute(&self, script: &Script, arguments: Vec<String>, ui: &T,
+               invocation: &Invocation) -> ScriptResult<bool> {
+        E::execute::<T, S>(script, arguments, ui, invocation)
+    }
+
+    fn autocomplete(&self, script: &Script, arguments: Vec<String>, prefix: &str, ui: &T,
+                    invocation: &Invocation) -> ScriptResult<Vec<Completion>> {
+        E::autocomplete::<T, S>(script, arguments, prefix, ui, invocation)
+    }
+
+    fn on_buffer_event(&self, script: &Script, ui: &T, invocation: &Invocation, buffer: (u32, u32),
+                       requested_uri: Option<&str>, event: &BufferEvent) -> ScriptResult<()> {
+        let (window_index, webview_index) = buffer;
+        E::on_buffer_event::<T, S>(script, ui, invocation, window_index, webview_index,
+                                   requested_uri, event)
+    }
+
+    fn on_javascript_result(&self, script: &Script, arguments: Vec<String>, ui: &T,
+                            invocation: &Invocation, buffer: (u32, u32),
+                            result: &JavaScriptResult) -> ScriptResult<()> {
+        let (window_index, webview_index) = buffer;
+        E::on_javascript_result::<T, S>(script, arguments, ui, invocation, window_index,
+                                        webview_index, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EngineRegistry,Registrar,ScriptHandler};
+    use super::super::{Invocation,LuaEngine,Script,ScriptErrorKind,ScriptingEngine,ScriptResult};
+    use ui::{ApplicationUI,BufferEvent,Completion,JavaScriptResult};
+
+    /// An engine describing a command by its source text
+    struct TextEngine;
+
+    impl ScriptingEngine for TextEngine {
+
+        fn file_extension() -> &'static str {
+            "txt"
+        }
+
+        fn describe(script: &Script) -> ScriptResult<String> {
+            Ok(String::from(script.source().trim()))
+        }
+
+        fn execute<T, S>(_: &Script, _: Vec<String>, _: &T, _: &Invocation) -> ScriptResult<bool>
+            where T: ApplicationUI<S>,
+                  S: ScriptingEngine {
+            Ok(true)
+        }
+
+        fn autocomplete<T, S>(_: &Script, _: Vec<String>, _: &str, _: &T, _: &Invocation) -> ScriptResult<Vec<Completion>>
+            where T: ApplicationUI<S>,
+                  S: ScriptingEngine {
+            Ok(vec![])
+        }
+
+        fn on_buffer_event<T, S>(_: &Script, _: &T, _: &Invocation, _: u32, _: u32, _: Option<&str>,
+                                 _: &BufferEvent) -> ScriptResult<()>
+            where T: ApplicationUI<S>,
+                  S: ScriptingEngine {
+            Ok(())
+        }
+
+        fn on_javascript_result<T, S>(_: &Script, _: Vec<String>, _: &T, _: &Invocation, _: u32, _: u32,
+                                      _: &JavaScriptResult) -> ScriptResult<()>
+            where T: ApplicationUI<S>,
+                  S: ScriptingEngine {
+            Ok(())
+        }
+    }
+
+    fn registry() -> EngineRegistry<dyn ScriptHandler> {
+        let mut registry = EngineRegistry::new();
+        registry.register::<LuaEngine>();
+        registry.register::<TextEngine>();
+        registry
+    }
+
+    #[test]
+    fn combine_file_extensions() {
+        assert_eq!(vec!["lua", "txt"], registry().file_extensions());
+        assert!(registry().get("/commands/go.txt").is_ok());
+    }
+
+    #[test]
+    fn prefer_first_registered_engine() {
+        let mut registry = registry();
+        registry.register::<LuaEngine>();
+        assert_eq!(vec!["lua", "txt"], registry.file_extensions());
+        let script = Script::new("go.lua", r#"function description() return "Lua" end"#);
+        assert_eq!("Lua", &registry.get(script.path()).unwrap().describe(&script).unwrap());
+    }
+
+    #[test]
+    fn dispatch_by_file_extension() {
+        let registry = registry();
+        let script = Script::new("go.lua", r#"function description() return "Lua" end"#);
+        assert_eq!("Lua", &registry.get(script.path()).unwrap().describe(&script).unwrap());
+        let script = Script::new("go.txt", "Text");
+        assert_eq!("Text", &registry.get(script.path()).unwrap().describe(&script).unwrap());
+        let err = registry.get("go.js").err().unwrap();
+        assert_eq!(ScriptErrorKind::Runtime, err.kind());
+        assert_eq!(Some("go.js"), err.path());
+    }
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+
+/// The standard libraries and browser functions available to a script,
+/// configured per command using `commands.sandbox."[COMMAND]"`
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum SandboxProfile {
+    /// Every standard library and browser function. The default for commands
+    /// run from the command bar or a key chord.
+    Full,
+    /// Standard libraries without access to files, processes, or the
+    /// environment, and every browser function except running other
+    /// commands and timers. The default for event triggers.
+    Pure,
+    /// The standard libraries of `Pure`, and only the browser functions which
+    /// read configuration options, stored values, or the state of windows and
+    /// buffers
+    Isolated,
+}
+
+impl SandboxProfile {
+
+    /// The profile with a configured name, if any
+    pub fn parse(name: &str) -> Option<SandboxProfile> {
+        match name {
+            "full" => Some(SandboxProfile::Full),
+            "pure" => Some(SandboxProfile::Pure),
+            "isolated" => Some(SandboxProfile::Isolated),
+            _ => None,
+        }
+    }
+
+    /// The name used to configure the profile
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SandboxProfile::Full => "full",
+            SandboxProfile::Pure => "pure",
+            SandboxProfile::Isolated => "isolated",
+        }
+    }
+
+    /// Whether the profile includes every standard library
+    pub fn allows_system_access(&self) -> bool {
+        *self == SandboxProfile::Full
+    }
+
+    /// Whether the profile includes functions which change windows, buffers,
+    /// the command bar, or stored values
+    pub fn allows_browser_changes(&self) -> bool {
+        *self != SandboxProfile::Isolated
+    }
+
+    /// Whether the profile includes running other commands, immediately or
+    /// using timers, which are run with their own profiles
+    pub fn allows_commands(&self) -> bool {
+        *self == SandboxProfile::Full
+    }
+}
+
+impl fmt::Display for SandboxProfile {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_names() {
+        for profile in &[SandboxProfile::Full, SandboxProfile::Pure, SandboxProfile::Isolated] {
+            assert_eq!(Some(*profile), SandboxProfile::parse(profile.name()));
+        }
+        assert_eq!(None, SandboxProfile::parse("Pure"));
+        assert_eq!(None, SandboxProfile::parse(""));
+    }
+}
@@ -0,0 +1,271 @@
+//! Values saved by commands between invocations
+use std::collections::{BTreeMap,HashMap,HashSet};
+use std::fs::{File,create_dir_all,rename};
+use std::io::{self,Read};
+use std::path::PathBuf;
+use std::sync::{Mutex,MutexGuard};
+
+use serde_json::{self,Value};
+
+/// Values saved by commands, grouped into a namespace for each command. Each
+/// namespace is saved as a JSON file in a directory, while values saved from
+/// private webviews are kept in separate namespaces in memory only. Changes
+/// are written when the store is flushed or dropped, so a command setting
+/// several values writes each file once.
+///
+/// ## Examples
+///
+/// ```
+/// use webkitten::store::Store;
+///
+/// let store = Store::new(None);
+/// store.set("bookmark", false, "home", "example.com");
+/// assert_eq!(Some(String::from("example.com")), store.get("bookmark", false, "home"));
+/// assert_eq!(None, store.get("bookmark", true, "home"));
+/// assert_eq!(vec![String::from("home")], store.keys("bookmark", false));
+/// store.flush().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Store {
+    directory: Option<PathBuf>,
+    namespaces: Mutex<HashMap<String, BTreeMap<String, String>>>,
+    private_namespaces: Mutex<HashMap<String, BTreeMap<String, String>>>,
+    changed: Mutex<HashSet<String>>,
+}
+
+impl Store {
+
+    /// Create a store saving namespaces to files in a directory. Without a
+    /// directory, values are kept in memory only.
+    pub fn new(directory: Option<PathBuf>) -> Self {
+        Store {
+            directory,
+            namespaces: Mutex::new(HashMap::new()),
+            private_namespaces: Mutex::new(HashMap::new()),
+            changed: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// The value of a key in a namespace, if any
+    pub fn get(&self, namespace: &str, private: bool, key: &str) -> Option<String> {
+        self.with_namespace(namespace, private, |values| values.get(key).cloned())
+    }
+
+    /// The keys in a namespace, in sorted order
+    pub fn keys(&self, namespace: &str, private: bool) -> Vec<String> {
+        self.with_namespace(namespace, private, |values| values.keys().cloned().collect())
+    }
+
+    /// Set the value of a key in a namespace, which is saved on the next
+    /// flush unless it is private
+    pub fn set(&self, namespace: &str, private: bool, key: &str, value: &str) {
+        self.update(namespace, private, |values| {
+            values.insert(String::from(key), String::from(value)) != Some(String::from(value))
+        })
+    }
+
+    /// Remove a key from a namespace, which is saved on the next flush unless
+    /// it is private
+    pub fn delete(&self, namespace: &str, private: bool, key: &str) {
+        self.update(namespace, private, |values| values.remove(key).is_some())
+    }
+
+    /// Write the namespaces changed since the last flush to their files.
+    /// Namespaces which fail to save are written again on the next flush.
+    pub fn flush(&self) -> io::Result<()> {
+        let changed: Vec<String> = lock(&self.changed).drain().collect();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        let namespaces = lock(&self.namespaces);
+        let mut result = Ok(());
+        for namespace in changed {
+            if let Some(values) = namespaces.get(&namespace) {
+                if let Err(err) = self.save(&namespace, values) {
+                    lock(&self.changed).insert(namespace);
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+
+    fn with_namespace<F, T>(&self, namespace: &str, private: bool, f: F) -> T
+            where F: FnOnce(&mut BTreeMap<String, String>) -> T {
+        let mut namespaces = lock(if private { &self.private_namespaces } else { &self.namespaces });
+        if !namespaces.contains_key(namespace) {
+            let values = if private { BTreeMap::new() } else { self.load(namespace) };
+            namespaces.insert(String::from(namespace), values);
+        }
+        f(namespaces.get_mut(namespace).unwrap())
+    }
+
+    /// Change the values in a namespace, marking it to be saved if `f`
+    /// returns `true`
+    fn update<F>(&self, namespace: &str, private: bool, f: F)
+            where F: FnOnce(&mut BTreeMap<String, String>) -> bool {
+        self.with_namespace(namespace, private, |values| {
+            if f(values) && !private {
+                lock(&self.changed).insert(String::from(namespace));
+            }
+        })
+    }
+
+    fn path(&self, namespace: &str) -> Option<PathBuf> {
+        self.directory.as_ref().map(|dir| dir.join(format!("{}.json", file_stem(namespace))))
+    }
+
+    fn load(&self, namespace: &str) -> BTreeMap<String, String> {
+        let mut values = BTreeMap::new();
+        if let Some(path) = self.path(namespace) {
+            let mut contents = String::new();
+            if File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)).is_err() {
+                return values;
+            }
+            match serde_json::from_str(&contents) {
+                Ok(Value::Object(object)) => for (key, value) in object {
+                    if let Value::String(value) = value {
+                        values.insert(key, value);
+                    }
+                },
+                _ => warn!("Ignoring invalid store file: {}", path.display()),
+            }
+        }
+        values
+    }
+
+    /// Write values to a temporary file which then replaces the namespace
+    /// file, so an interrupted write never leaves a partial file
+    fn save(&self, namespace: &str, values: &BTreeMap<String, String>) -> io::Result<()> {
+        if let (Some(dir), Some(path)) = (self.directory.as_ref(), self.path(namespace)) {
+            create_dir_all(dir)?;
+            let temp_path = path.with_extension("json.tmp");
+            {
+                let mut file = File::create(&temp_path)?;
+                serde_json::to_writer(&mut file, values)?;
+                file.sync_all()?;
+            }
+            rename(&temp_path, &path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Store {
+
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("Unable to save store: {}", err);
+        }
+    }
+}
+
+/// Lock a mutex, recovering the values if a thread panicked while holding it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// A file name for a namespace, escaping characters other than letters,
+/// digits, `-`, and `_` so that names cannot refer to other directories
+fn file_stem(namespace: &str) -> String {
+    let mut stem = String::new();
+    for byte in namespace.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            stem.push(byte as char);
+        } else {
+            stem.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    stem
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_dir;
+    use super::*;
+    use tests::{TestDir,create_ui};
+    use ui::ApplicationUI;
+
+    #[test]
+    fn persist_values() {
+        let test_dir = TestDir::new("store-persist");
+        let dir = test_dir.path().join("store");
+        {
+            let store = Store::new(Some(dir.clone()));
+            store.set("bookmark save", false, "home", "example.com");
+            store.set("bookmark save", false, "work", "example.org");
+            store.delete("bookmark save", false, "work");
+            store.set("bookmark save", true, "secret", "example.net");
+        }
+        let store = Store::new(Some(dir.clone()));
+        assert_eq!(vec![String::from("home")], store.keys("bookmark save", false));
+        assert_eq!(Some(String::from("example.com")), store.get("bookmark save", false, "home"));
+        assert!(store.keys("bookmark save", true).is_empty());
+        let files: Vec<String> = read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(vec![String::from("bookmark%20save.json")], files);
+    }
+
+    #[test]
+    fn separate_namespaces() {
+        let store = Store::new(None);
+        store.set("a", false, "key", "1");
+        store.set("b", false, "key", "2");
+        store.set("a", true, "key", "3");
+        assert_eq!(Some(String::from("1")), store.get("a", false, "key"));
+        assert_eq!(Some(String::from("2")), store.get("b", false, "key"));
+        assert_eq!(Some(String::from("3")), store.get("a", true, "key"));
+        store.delete("a", true, "key");
+        assert_eq!(None, store.get("a", true, "key"));
+        assert_eq!(Some(String::from("1")), store.get("a", false, "key"));
+    }
+
+    #[test]
+    fn write_changes_on_flush() {
+        let test_dir = TestDir::new("store-flush");
+        let dir = test_dir.path().join("store");
+        let store = Store::new(Some(dir.clone()));
+        store.set("count", false, "total", "1");
+        store.set("count", false, "total", "2");
+        assert!(!dir.join("count.json").exists());
+        store.flush().unwrap();
+        assert_eq!(Some(String::from("2")), Store::new(Some(dir.clone())).get("count", false, "total"));
+        store.flush().unwrap();
+    }
+
+    #[test]
+    fn escape_file_names() {
+        assert_eq!("go", &file_stem("go"));
+        assert_eq!("%2E%2E%2F%2E%2E%2Fetc", &file_stem("../../etc"));
+        assert_eq!("bookmark%20save", &file_stem("bookmark save"));
+    }
+
+    #[test]
+    #[allow(unused_must_use)]
+    fn execute_with_store() {
+        let ui = create_ui("store", "", &[("counter.lua", r#"
+            function run()
+                local count = tonumber(store_get("count") or "0") + 1
+                store_set("count", tostring(count))
+                store_set("removed", "")
+                store_delete("removed")
+                set_command_field_text(1, count .. ":" .. table.concat(store_keys(), ","))
+                return true
+            end
+        "#)]);
+        let store_dir = ui.file_path("store");
+        assert!(ui.execute_command(Some(0), "counter").is_success());
+        assert!(ui.execute_command(Some(0), "counter").is_success());
+        assert_eq!(String::from("2:count"), ui.command_field_text(1));
+        *ui.private.borrow_mut() = true;
+        assert!(ui.execute_command(Some(0), "counter").is_success());
+        assert_eq!(String::from("1:count"), ui.command_field_text(1));
+        let mut contents = String::new();
+        File::open(store_dir.join("counter.json")).unwrap().read_to_string(&mut contents);
+        assert_eq!(r#"{"count":"2"}"#, &contents);
+    }
+}
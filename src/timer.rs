@@ -0,0 +1,262 @@
+//! Commands scheduled by scripts to run later
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The window or buffer which owns a timer. Timers are cancelled when their
+/// owner closes.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum TimerOwner {
+    /// A window by index
+    Window(u32),
+    /// A buffer by window index and webview index
+    Webview(u32, u32),
+}
+
+impl TimerOwner {
+
+    /// The index of the window containing the owner
+    pub fn window_index(&self) -> u32 {
+        match *self {
+            TimerOwner::Window(index) => index,
+            TimerOwner::Webview(index, _) => index,
+        }
+    }
+}
+
+/// Command text to run after a delay
+#[derive(Debug,Clone,PartialEq)]
+pub struct Timer {
+    pub command: String,
+    pub owner: TimerOwner,
+    /// The delay between runs of a repeating timer
+    pub interval: Option<Duration>,
+}
+
+/// Pending timers by identifier
+///
+/// ## Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use webkitten::timer::{Timers,TimerOwner};
+///
+/// let mut timers = Timers::new();
+/// let id = timers.add("reload", TimerOwner::Webview(0, 1), Some(Duration::from_secs(60)));
+/// assert_eq!(Some("reload"), timers.get(id).map(|timer| timer.command.as_str()));
+/// timers.close_webview(0, 0);
+/// assert_eq!(Some(TimerOwner::Webview(0, 0)), timers.get(id).map(|timer| timer.owner));
+/// assert_eq!(vec![id], timers.close_window(0));
+/// assert!(timers.get(id).is_none());
+/// ```
+#[derive(Debug)]
+pub struct Timers {
+    next_id: u32,
+    timers: HashMap<u32, Timer>,
+}
+
+impl Default for Timers {
+
+    fn default() -> Self {
+        Timers::new()
+    }
+}
+
+impl Timers {
+
+    pub fn new() -> Self {
+        Timers { next_id: 1, timers: HashMap::new() }
+    }
+
+    /// Add a timer, returning its identifier
+    pub fn add(&mut self, command: &str, owner: TimerOwner, interval: Option<Duration>) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.timers.insert(id, Timer {
+            command: String::from(command),
+            owner,
+            interval,
+        });
+        id
+    }
+
+    /// The timer with an identifier, if it has not been removed
+    pub fn get(&self, id: u32) -> Option<&Timer> {
+        self.timers.get(&id)
+    }
+
+    /// Remove a timer, returning it if it was pending
+    pub fn remove(&mut self, id: u32) -> Option<Timer> {
+        self.timers.remove(&id)
+    }
+
+    /// The number of pending timers
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Whether no timers are pending
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Remove the timers owned by a window or its buffers, returning their
+    /// identifiers
+    pub fn close_window(&mut self, window_index: u32) -> Vec<u32> {
+        self.remove_matching(|owner| owner.window_index() == window_index)
+    }
+
+    /// Remove the timers owned by a buffer, returning their identifiers.
+    /// Buffers after it in the window move to a lower index, so the timers
+    /// they own do too.
+    pub fn close_webview(&mut self, window_index: u32, webview_index: u32) -> Vec<u32> {
+        let removed = self.remove_matching(|owner| *owner == TimerOwner::Webview(window_index, webview_index));
+        for timer in self.timers.values_mut() {
+            if let TimerOwner::Webview(window, ref mut webview) = timer.owner {
+                if window == window_index && *webview > webview_index {
+                    *webview -= 1;
+                }
+            }
+        }
+        removed
+    }
+
+    fn remove_matching<F>(&mut self, matches: F) -> Vec<u32>
+            where F: Fn(&TimerOwner) -> bool {
+        let mut ids: Vec<u32> = self.timers.iter()
+            .filter(|&(_, timer)| matches(&timer.owner))
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort();
+        for id in &ids {
+            self.timers.remove(id);
+        }
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use script::LuaEngine;
+    use tests::{MockUI,create_ui};
+    use ui::{ApplicationUI,EventHandler};
+
+    #[test]
+    fn close_webview_shifts_later_owners() {
+        let mut timers = Timers::new();
+        let first = timers.add("a", TimerOwner::Webview(0, 0), None);
+        let second = timers.add("b", TimerOwner::Webview(0, 1), None);
+        let third = timers.add("c", TimerOwner::Webview(0, 2), None);
+        let other = timers.add("d", TimerOwner::Webview(1, 2), None);
+        let window = timers.add("e", TimerOwner::Window(0), None);
+        assert_eq!(vec![second], timers.close_webview(0, 1));
+        assert_eq!(Some(TimerOwner::Webview(0, 0)), timers.get(first).map(|t| t.owner));
+        assert_eq!(Some(TimerOwner::Webview(0, 1)), timers.get(third).map(|t| t.owner));
+        assert_eq!(Some(TimerOwner::Webview(1, 2)), timers.get(other).map(|t| t.owner));
+        assert_eq!(Some(TimerOwner::Window(0)), timers.get(window).map(|t| t.owner));
+    }
+
+    #[test]
+    fn close_window_removes_all_owners() {
+        let mut timers = Timers::new();
+        let first = timers.add("a", TimerOwner::Webview(0, 0), None);
+        let second = timers.add("b", TimerOwner::Window(0), None);
+        timers.add("c", TimerOwner::Window(1), None);
+        assert_eq!(vec![first, second], timers.close_window(0));
+        assert_eq!(1, timers.len());
+    }
+
+    /// Scripts which schedule commands and record when they run
+    const TIMER_SCRIPTS: &[(&str, &str)] = &[("later.lua", r#"
+        function run()
+            local id
+            if arguments[1] == "repeat" then
+                id = set_interval(0, "mark " .. arguments[2])
+            else
+                id = set_timeout(500, "mark " .. arguments[2])
+            end
+            set_command_field_text(1, tostring(id))
+            return true
+        end
+    "#), ("mark.lua", r#"
+        function run()
+            load_uri(0, 0, arguments[1])
+            return true
+        end
+    "#), ("nest.lua", r#"
+        function run()
+            local command = arguments[1] or "mark nested"
+            set_command_field_text(1, tostring(set_timeout(500, command)))
+            return true
+        end
+    "#), ("stop.lua", r#"
+        function run()
+            clear_timer(tonumber(arguments[1]))
+            return true
+        end
+    "#)];
+
+    #[test]
+    fn execute_timeout() {
+        let ui = create_ui("timeout", "", TIMER_SCRIPTS);
+        assert!(ui.execute_command(Some(0), "later after once").is_success());
+        let id: u32 = ui.command_field_text(1).parse().unwrap();
+        assert_eq!(vec![(id, Duration::from_millis(500))], *ui.scheduled_timers.borrow());
+        assert!(ui.loaded_uris.borrow().is_empty());
+        ui.engine.on_timer::<MockUI, LuaEngine>(&ui, id);
+        ui.engine.on_timer::<MockUI, LuaEngine>(&ui, id);
+        assert_eq!(vec![String::from("once")], *ui.loaded_uris.borrow());
+        assert_eq!(1, ui.scheduled_timers.borrow().len());
+    }
+
+    #[test]
+    fn execute_interval() {
+        let ui = create_ui("interval", "", TIMER_SCRIPTS);
+        assert!(ui.execute_command(Some(0), "later repeat tick").is_success());
+        let id: u32 = ui.command_field_text(1).parse().unwrap();
+        ui.engine.on_timer::<MockUI, LuaEngine>(&ui, id);
+        ui.engine.on_timer::<MockUI, LuaEngine>(&ui, id);
+        assert_eq!(vec![(id, Duration::from_millis(10)); 3], *ui.scheduled_timers.borrow());
+        assert!(ui.execute_command(Some(0), &format!("stop {}", id)).is_success());
+        ui.engine.on_timer::<MockUI, LuaEngine>(&ui, id);
+        assert_eq!(vec![String::from("tick"), String::from("tick")], *ui.loaded_uris.borrow());
+        assert_eq!(3, ui.scheduled_timers.borrow().len());
+    }
+
+    #[test]
+    fn close_webview_cancels_timers() {
+        let ui = create_ui("timer-owner", "", TIMER_SCRIPTS);
+        assert!(ui.execute_command(Some(0), "later repeat tick").is_success());
+        let id: u32 = ui.command_field_text(1).parse().unwrap();
+        ui.engine.on_webview_close(0, 0);
+        ui.engine.on_timer::<MockUI, LuaEngine>(&ui, id);
+        assert!(ui.loaded_uris.borrow().is_empty());
+    }
+
+    #[test]
+    fn timers_belong_to_invoking_window() {
+        let ui = create_ui("timer-window", "", TIMER_SCRIPTS);
+        assert!(ui.execute_command(Some(2), "later repeat tick").is_success());
+        let id: u32 = ui.command_field_text(1).parse().unwrap();
+        ui.engine.on_webview_close(0, 0);
+        ui.engine.on_timer::<MockUI, LuaEngine>(&ui, id);
+        assert_eq!(1, ui.loaded_uris.borrow().len());
+        ui.engine.on_window_close(2);
+        ui.engine.on_timer::<MockUI, LuaEngine>(&ui, id);
+        assert_eq!(1, ui.loaded_uris.borrow().len());
+    }
+
+    #[test]
+    fn run_timers_for_owning_window() {
+        let ui = create_ui("timer-owner", "", TIMER_SCRIPTS);
+        assert!(ui.execute_command(Some(2), "nest nest").is_success());
+        let id: u32 = ui.command_field_text(1).parse().unwrap();
+        ui.set_command_field_text(2, "typing");
+        ui.engine.on_timer::<MockUI, LuaEngine>(&ui, id);
+        assert_eq!("typing", ui.command_field_text(2));
+        let nested: u32 = ui.command_field_text(1).parse().unwrap();
+        ui.engine.on_window_close(2);
+        ui.engine.on_timer::<MockUI, LuaEngine>(&ui, nested);
+        assert!(ui.loaded_uris.borrow().is_empty());
+    }
+}
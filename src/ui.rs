@@ -1,9 +1,16 @@
 use std::collections::HashMap;
+use std::time::Duration;
+use std::path::Path;
 use url::Url;
-use script::ScriptingEngine;
+use serde_json::Value;
+use config::{Config,ConfigError};
+use script::{SandboxProfile,ScriptingEngine};
+use history::HistoryStrategy;
+use store::Store;
+use timer::TimerOwner;
 
 use keybinding;
-
+use command;
 
 pub trait ApplicationUI<S>: Sized where S: ScriptingEngine {
 
@@ -17,7 +24,7 @@ pub trait ApplicationUI<S>: Sized where S: ScriptingEngine {
     fn copy(&self, text: &str);
 
     /// Send a command to the event handler to be run in a particular window
-    fn execute_command(&self, window_index: Option<u32>, text: &str);
+    fn execute_command(&self, window_index: Option<u32>, text: &str) -> CommandOutput;
 
     /// The index of the focused window
     fn focused_window_index(&self) -> Option<u32>;
@@ -57,6 +64,41 @@ pub trait ApplicationUI<S>: Sized where S: ScriptingEngine {
     /// Set the visibility in the command bar of a specified window
     fn set_command_field_visible(&self, window_index: u32, visible: bool);
 
+    /// Show a message describing a failed command in a specified window,
+    /// apart from the command bar text so that it is not run as a command
+    fn show_command_error(&self, window_index: u32, message: &str);
+
+    /// Up to `count` previously run commands, starting with the most recent
+    fn command_history(&self, count: u32) -> Vec<String>;
+
+    /// A previously run command beginning with `prefix` at an index, where `0`
+    /// is the most recent match
+    fn command_history_match(&self, prefix: &str, index: u32) -> Option<String>;
+
+    /// Values saved by commands between invocations
+    fn command_store(&self) -> &Store;
+
+    /// The configuration options of the running browser
+    fn config(&self) -> &Config;
+
+    /// Change configuration options while running. The change is also
+    /// saved to the configuration overrides file if `persist` is set and the
+    /// change succeeds.
+    fn update_config<F>(&self, persist: bool, change: F) -> Result<(), ConfigError>
+        where F: Fn(&Config) -> Result<(), ConfigError>;
+
+    /// Run command text after a delay, repeating at that interval if `repeat`
+    /// is set, until the timer is cleared or its owner closes. Returns an
+    /// identifier for the timer.
+    fn set_timer(&self, command: &str, owner: TimerOwner, delay: Duration, repeat: bool) -> u32;
+
+    /// Cancel a timer created using `set_timer`
+    fn clear_timer(&self, timer_id: u32);
+
+    /// Call `EventHandler::on_timer` with a timer identifier from the event
+    /// loop once a delay has passed
+    fn schedule_timer(&self, timer_id: u32, delay: Duration);
+
     /// Title of a specified window
     fn window_title(&self, window_index: u32) -> String;
 
@@ -96,6 +138,9 @@ pub trait ApplicationUI<S>: Sized where S: ScriptingEngine {
     /// Get the currently loaded URI or empty string
     fn uri(&self, window_index: u32, webview_index: u32) -> String;
 
+    /// `true` if a webview does not persist any browsing data
+    fn is_private_webview(&self, window_index: u32, webview_index: u32) -> bool;
+
     /// Find a string within the selected web view
     fn find_string(&self, window_index: u32, webview_index: u32, query: &str);
 
@@ -108,29 +153,116 @@ pub trait ApplicationUI<S>: Sized where S: ScriptingEngine {
     /// Run a JavaScript snippet in a webview
     fn run_javascript(&self, window_index: u32, webview_index: u32, script: &str);
 
+    /// Evaluate a JavaScript snippet in a webview, passing the outcome to
+    /// `EventHandler::on_javascript_result` with the command text once
+    /// evaluation finishes. Implementations evaluate the snippet wrapped using
+    /// `json_result_script` so that the result is serialized as JSON, in a
+    /// content world isolated from scripts in the page where available so
+    /// the page cannot replace `JSON.stringify`.
+    fn evaluate_javascript(&self, window_index: u32, webview_index: u32, script: &str, command: &str);
+
     /// Apply a stylesheet to a webview
     fn apply_styles(&self, window_index: u32, webview_index: u32, styles: &str);
 }
 
+/// The outcome of evaluating JavaScript in a webview: the result serialized
+/// as JSON, or the message of an exception thrown while evaluating
+pub type JavaScriptResult = Result<String, String>;
+
+/// Wrap a JavaScript snippet so that evaluating it returns the JSON
+/// serialization of its result. The snippet is evaluated in the global scope.
+///
+/// ## Examples
+///
+/// ```
+/// use webkitten::ui::json_result_script;
+///
+/// assert_eq!(r#"JSON.stringify((0, eval)("document.title"))"#,
+///            &json_result_script("document.title"));
+/// ```
+pub fn json_result_script(script: &str) -> String {
+    format!("JSON.stringify((0, eval)({}))", Value::String(String::from(script)))
+}
+
 pub enum WindowArea {
     CommandBar,
     WebView,
 }
 
+#[derive(Debug,Clone,Copy,PartialEq)]
 pub enum CommandError {
     /// No command matches the given text
     CommandNotFound,
     /// Command execution halted with an error
     ErrorDuringExecution,
+    /// Command text expanded into itself, or expanded into other commands
+    /// too many times
+    ExpansionLoop,
     /// The provided arguments were invalid in the context of the given command
     InvalidArguments,
     /// There was no command text specified
     NoCommandSpecified,
 }
 
+/// The result of running a command, indicating whether it was successful and
+/// any message to show to the user
+#[derive(Debug,Clone,PartialEq)]
 pub struct CommandOutput {
     pub error: Option<CommandError>,
     pub message: Option<String>,
+    /// Whether the command reported success upon completion
+    pub completed: bool,
+}
+
+impl CommandOutput {
+
+    /// Output of a command which completed without error
+    pub fn success() -> Self {
+        CommandOutput::completion(true)
+    }
+
+    /// Output of a command which completed without error, reporting whether
+    /// it was successful
+    pub fn completion(completed: bool) -> Self {
+        CommandOutput { error: None, message: None, completed }
+    }
+
+    /// Output of a command which failed, with a message describing the failure
+    pub fn error<M: Into<String>>(error: CommandError, message: M) -> Self {
+        CommandOutput { error: Some(error), message: Some(message.into()), completed: false }
+    }
+
+    /// `true` if the command completed without error and reported success
+    pub fn is_success(&self) -> bool {
+        self.error.is_none() && self.completed
+    }
+}
+
+/// A suggestion for completing command text
+#[derive(Debug,Clone,PartialEq)]
+pub struct Completion {
+    /// The text shown when presenting the completion
+    pub label: String,
+    /// The text inserted when the completion is chosen
+    pub value: String,
+    /// A summary of the completion
+    pub description: Option<String>,
+}
+
+impl Completion {
+
+    /// A completion inserting and showing the same text
+    pub fn new<V: Into<String>>(value: V) -> Self {
+        let value = value.into();
+        Completion { label: value.clone(), value, description: None }
+    }
+
+    /// A completion with a summary
+    pub fn described<V: Into<String>, D: Into<String>>(value: V, description: D) -> Self {
+        let mut completion = Completion::new(value);
+        completion.description = Some(description.into());
+        completion
+    }
 }
 
 #[derive(Debug,Clone)]
@@ -143,8 +275,22 @@ pub enum BufferEvent {
 
 pub trait EventHandler {
 
-    /// Handle a Return key press within the command bar
-    fn execute_command<T, S>(&self, ui: &T, window_index: Option<u32>, text: &str)
+    /// Run command text in a window, returning the outcome of running the
+    /// command
+    fn execute_command<T, S>(&self, ui: &T, window_index: Option<u32>, text: &str) -> CommandOutput
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine;
+
+    /// Handle the text in the command bar of a window changing, running the
+    /// command expanded from any matching prefix in `commands.on-text-change`
+    /// without showing its outcome in the window
+    fn on_command_text_change<T, S>(&self, ui: &T, window_index: u32, text: &str)
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine;
+
+    /// Handle a Return key press within the command bar, adding the text to
+    /// command history before running it
+    fn submit_command<T, S>(&self, ui: &T, window_index: Option<u32>, text: &str) -> CommandOutput
         where T: ApplicationUI<S>,
               S: ScriptingEngine;
 
@@ -154,10 +300,17 @@ pub trait EventHandler {
               S: ScriptingEngine;
 
     /// Get available commands and/or arguments given a prefix
-    fn command_completions<T, S>(&self, ui: &T, prefix: &str) -> Vec<String>
+    fn command_completions<T, S>(&self, ui: &T, prefix: &str) -> Vec<Completion>
         where T: ApplicationUI<S>,
               S: ScriptingEngine;
 
+    /// Up to `count` previously run commands, starting with the most recent
+    fn command_history(&self, count: usize) -> Vec<String>;
+
+    /// A previously run command beginning with `prefix` at an index, where `0`
+    /// is the most recent match
+    fn command_history_match(&self, prefix: &str, index: usize) -> Option<String>;
+
     /// Handle a buffer event
     ///
     /// ## Events
@@ -175,6 +328,36 @@ pub trait EventHandler {
     fn on_new_frame_request<T, S>(&self, ui: &T, window_index: u32, uri: &str)
         where T: ApplicationUI<S>,
               S: ScriptingEngine;
+
+    /// Run command text after a delay, repeating at that interval if `repeat`
+    /// is set, until the timer is cleared or its owner closes. Returns an
+    /// identifier for the timer.
+    fn set_timer<T, S>(&self, ui: &T, command: &str, owner: TimerOwner, delay: Duration, repeat: bool) -> u32
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine;
+
+    /// Cancel a timer
+    fn clear_timer(&self, timer_id: u32);
+
+    /// Handle a timer scheduled using `ApplicationUI::schedule_timer`
+    /// becoming due, running its command unless it has been cancelled
+    fn on_timer<T, S>(&self, ui: &T, timer_id: u32)
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine;
+
+    /// Handle JavaScript evaluated using `ApplicationUI::evaluate_javascript`
+    /// finishing, running the `javascript_result` event trigger of the
+    /// command with the outcome
+    fn on_javascript_result<T, S>(&self, ui: &T, window_index: u32, webview_index: u32,
+                                  command: &str, result: JavaScriptResult)
+        where T: ApplicationUI<S>,
+              S: ScriptingEngine;
+
+    /// Handle a window closing, cancelling the timers it owns
+    fn on_window_close(&self, window_index: u32);
+
+    /// Handle a webview closing, cancelling the timers it owns
+    fn on_webview_close(&self, window_index: u32, webview_index: u32);
 }
 
 pub trait BrowserConfiguration: Sized {
@@ -202,19 +385,80 @@ pub trait BrowserConfiguration: Sized {
         self.lookup_raw_str("general.config-dir")
     }
 
-    /// The name of the scripting engine to use for evaluating command files.
-    /// Defaults to "lua".
+    /// The file extension of the scripting engine preferred when command files
+    /// with the same name exist for several engines. Defaults to "lua".
     fn command_interpreter(&self) -> Option<String> {
         self.lookup_str("commands.interpreter").or(Some(String::from("lua")))
     }
 
-    /// The name of a command resolving any matching alias in `commands.aliases`
+    /// The name of a command resolving any matching alias in `commands.aliases`.
+    /// Aliases with arguments or placeholders resolve to the first command
+    /// they invoke.
     fn resolved_command_name(&self, name: &str) -> Option<String> {
-        let command = self.lookup_str(&format!("commands.aliases.{}", name))
-            .unwrap_or(String::from(name));
+        let command = match self.lookup_str(&format!("commands.aliases.{}", name)) {
+            Some(alias) => match alias.split(|c: char| c.is_whitespace() || command::is_separator(c))
+                    .find(|word| !word.is_empty()) {
+                Some(word) => String::from(word),
+                None => return None,
+            },
+            None => String::from(name),
+        };
         if self.command_disabled(&command) { None } else { Some(command) }
     }
 
+    /// Which previously run commands to retain based on
+    /// `commands.history.strategy` (`all`, `none`, or `last`) and
+    /// `commands.history.size`. Defaults to the last 500 commands, which is
+    /// also used if the size is negative.
+    fn command_history_strategy(&self) -> HistoryStrategy {
+        let size = self.lookup_integer("commands.history.size")
+            .and_then(|size| if size < 0 {
+                warn!("Ignoring negative commands.history.size: {}", size);
+                None
+            } else {
+                Some(size as usize)
+            });
+        self.lookup_str("commands.history.strategy")
+            .and_then(|name| HistoryStrategy::parse(&name, size))
+            .unwrap_or(HistoryStrategy::parse("last", size).unwrap())
+    }
+
+    /// The file used to save previously run commands based on
+    /// `commands.history.path`
+    fn command_history_path(&self) -> Option<String> {
+        self.lookup_str("commands.history.path")
+    }
+
+    /// The longest time a command script may run before it is stopped, set in
+    /// milliseconds using `commands.time-limit`. Unlimited by default or if
+    /// the limit is 0 or less.
+    fn command_time_limit(&self) -> Option<Duration> {
+        self.lookup_integer("commands.time-limit")
+            .filter(|limit| *limit > 0)
+            .map(|limit| Duration::from_millis(limit as u64))
+    }
+
+    /// The largest number of instructions a command script may run before it
+    /// is stopped, set using `commands.instruction-limit`. Unlimited by
+    /// default.
+    fn command_instruction_limit(&self) -> Option<u64> {
+        self.lookup_integer("commands.instruction-limit")
+            .filter(|limit| *limit > 0)
+            .map(|limit| limit as u64)
+    }
+
+    /// The sandbox profile of a command set using
+    /// `commands.sandbox."[COMMAND]"`. Unknown profiles are treated as the
+    /// most restrictive profile.
+    fn command_sandbox(&self, name: &str) -> Option<SandboxProfile> {
+        self.lookup_str_table("commands.sandbox")
+            .and_then(|profiles| profiles.get(name).cloned())
+            .map(|profile| SandboxProfile::parse(&profile).unwrap_or_else(|| {
+                warn!("Unknown sandbox profile '{}' for '{}'", profile, name);
+                SandboxProfile::Isolated
+            }))
+    }
+
     /// Font to use in the command bar
     fn bar_font(&self) -> Option<(String, i64)> {
         if let Some(family) = self.lookup_str("general.bar-font.family") {
@@ -255,6 +499,20 @@ pub trait BrowserConfiguration: Sized {
         table
     }
 
+    /// The key and modifier mask of the keybinding which runs the `cancel`
+    /// command, if any
+    fn cancel_keybinding(&self) -> Option<(char, usize)> {
+        self.command_keybindings().into_iter()
+            .filter(|(text, _)| {
+                text.split_whitespace().next()
+                    .and_then(|name| self.resolved_command_name(name))
+                    .map(|name| name == "cancel")
+                    .unwrap_or(false)
+            })
+            .map(|(_, binding)| binding)
+            .next()
+    }
+
     /// Whether a command is disabled based on `commands.disabled`
     fn command_disabled(&self, name: &str) -> bool {
         if let Some(disabled) = self.lookup_str_vec("commands.disabled") {
@@ -306,6 +564,41 @@ pub trait BrowserConfiguration: Sized {
         self.lookup_str_vec("commands.search-paths").unwrap_or(vec![])
     }
 
+    /// Paths to search for modules loaded by command scripts using
+    /// configuration option `commands.library-paths`. Defaults to the `lib`
+    /// directory within each command search path.
+    fn command_library_paths(&self) -> Vec<String> {
+        self.lookup_str_vec("commands.library-paths").unwrap_or_else(|| {
+            self.command_search_paths().iter()
+                .filter_map(|path| Path::new(path).join("lib").to_str().map(String::from))
+                .collect()
+        })
+    }
+
+    /// Whether Lua command scripts can call browser functions as globals, as
+    /// well as within the `webkitten` table, using configuration option
+    /// `commands.legacy-globals`. Defaults to `true`.
+    fn command_legacy_globals(&self) -> bool {
+        self.lookup_bool("commands.legacy-globals").unwrap_or(true)
+    }
+
+    /// Whether executable files without a file extension in the command
+    /// search paths are run as commands, using configuration option
+    /// `commands.executables`. Defaults to `false`.
+    fn command_executables(&self) -> bool {
+        self.lookup_bool("commands.executables").unwrap_or(false)
+    }
+
+    /// The file extensions of command files to resolve out of those handled
+    /// by the scripting engines, leaving out executables without an extension
+    /// unless enabled by `commands.executables`
+    fn command_file_extensions(&self, extensions: Vec<&'static str>) -> Vec<&'static str> {
+        let executables = self.command_executables();
+        extensions.into_iter()
+            .filter(|extension| executables || !extension.is_empty())
+            .collect()
+    }
+
     /// Command to run when no other commands are matched using configuration
     /// option `commands.default`
     fn default_command(&self) -> Option<String> {
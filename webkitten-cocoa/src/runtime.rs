@@ -1,27 +1,37 @@
 use objc::declare::ClassDecl;
 use objc::runtime::{Object,Sel,BOOL,YES,NO};
-use macos::{Id,ObjCClass};
+use macos::{Id,ObjCClass,nil};
 use macos::foundation::*;
 use macos::appkit::{NSControl,NSEvent,NSView,NSEventModifierFlags,
-                    NSLayoutConstraint,NSWorkspace};
+                    NSLayoutConstraint,NSTextField,NSWindow,NSWorkspace};
 use macos::core_services::register_default_scheme_handler;
 use macos::core_graphics::CGFloat;
 use macos::webkit::*;
-use webkitten::ui::{ApplicationUI,EventHandler,BrowserConfiguration,BufferEvent};
+use webkitten::ui::{ApplicationUI,EventHandler,BrowserConfiguration,BufferEvent,Completion};
 use webkitten::{WEBKITTEN_APP_ID,WEBKITTEN_TITLE};
 use webkitten::config::Config;
 use block::Block;
+use std::cell::RefCell;
+use std::time::Duration;
 
 use ui::{CocoaUI,UI};
 
 const APP_VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+thread_local! {
+    /// Completions last offered in the command bar, used to swap a chosen
+    /// label for its value when the command is submitted
+    static COMPLETIONS: RefCell<Vec<Completion>> = RefCell::new(vec![]);
+}
+
 impl_objc_class!(CommandBarDelegate);
 impl_objc_class!(WebViewHistoryDelegate);
 impl_objc_class!(WebViewContainerView);
 impl_objc_class!(KeyInputDelegate);
 impl_objc_class!(AppDelegate);
 impl_objc_class!(CommandBarView);
+impl_objc_class!(TimerDelegate);
+impl_objc_class!(WindowDelegate);
 
 impl CommandBarDelegate {
     pub fn new() -> Self {
@@ -39,6 +49,14 @@ impl WebViewHistoryDelegate {
     }
 }
 
+impl WindowDelegate {
+    pub fn new() -> Self {
+        WindowDelegate {
+            ptr: unsafe { msg_send![class!(WindowDelegate), new] }
+        }
+    }
+}
+
 impl AppDelegate {
     pub fn new() -> Self {
         AppDelegate {
@@ -72,6 +90,37 @@ impl KeyInputDelegate {
     }
 }
 
+impl TimerDelegate {
+    pub fn new(timer_id: u32) -> Self {
+        let ptr = unsafe {
+            let delegate: *mut Object = msg_send![class!(TimerDelegate), new];
+            let obj = &mut *(delegate as *mut _ as *mut Object);
+            obj.set_ivar("_timerID", timer_id);
+            delegate
+        };
+        TimerDelegate { ptr: ptr }
+    }
+
+    pub fn timer_id(&self) -> u32 {
+        let obj = unsafe { &mut *(self.ptr as *mut _ as *mut Object) };
+        unsafe { *obj.get_ivar("_timerID") }
+    }
+
+    /// Send `fireTimer:` from the main run loop after a delay. The timer
+    /// retains the delegate until then.
+    pub fn schedule(self, delay: Duration) {
+        let interval = delay.as_secs() as f64 + delay.subsec_nanos() as f64 / 1_000_000_000.0;
+        unsafe {
+            let _: Id = msg_send![class!(NSTimer), scheduledTimerWithTimeInterval:interval
+                                                   target:self.ptr
+                                                   selector:sel!(fireTimer:)
+                                                   userInfo:nil
+                                                   repeats:NO];
+            let () = msg_send![self.ptr, release];
+        }
+    }
+}
+
 impl CommandBarView {
 
     pub fn new() -> Self {
@@ -159,12 +208,27 @@ fn declare_app_delegates() {
             run_keybinding_command as extern fn(&mut Object, Sel));
     }
     key_input.register();
+    let mut timer = ClassDecl::new(TimerDelegate::class_name(), class!(NSObject)).unwrap();
+    timer.add_ivar::<u32>("_timerID");
+    unsafe {
+        timer.add_method(sel!(fireTimer:),
+            fire_timer as extern fn(&mut Object, Sel, Id));
+    }
+    timer.register();
+    let mut window = ClassDecl::new(WindowDelegate::class_name(), class!(NSObject)).unwrap();
+    unsafe {
+        window.add_method(sel!(windowWillClose:),
+            window_will_close as extern fn(&Object, Sel, Id));
+    }
+    window.register();
     let mut app_delegate = ClassDecl::new(AppDelegate::class_name(), class!(NSObject)).unwrap();
     unsafe {
         app_delegate.add_method(sel!(applicationWillFinishLaunching:),
             app_will_finish_launching as extern fn (&mut Object, Sel, Id));
         app_delegate.add_method(sel!(applicationDidFinishLaunching:),
             app_finished_launching as extern fn (&Object, Sel, Id));
+        app_delegate.add_method(sel!(applicationWillTerminate:),
+            app_will_terminate as extern fn (&Object, Sel, Id));
         app_delegate.add_method(sel!(application:openFile:),
             open_file as extern fn (&Object, Sel, Id, Id) -> BOOL);
         app_delegate.add_method(sel!(setAsDefaultBrowser),
@@ -177,9 +241,13 @@ fn declare_app_delegates() {
 
 fn declare_bar_delegate() {
     let mut decl = ClassDecl::new(CommandBarDelegate::class_name(), class!(NSObject)).unwrap();
+    decl.add_ivar::<NSInteger>("_historyPosition");
+    decl.add_ivar::<Id>("_historyPrefix");
     unsafe {
         decl.add_method(sel!(controlTextDidChange:),
-            command_bar_text_changed as extern fn(&Object, Sel, Id));
+            command_bar_text_changed as extern fn(&mut Object, Sel, Id));
+        decl.add_method(sel!(control:textView:doCommandBySelector:),
+            command_bar_do_command as extern fn(&mut Object, Sel, Id, Id, Sel) -> BOOL);
         decl.add_method(sel!(controlTextDidEndEditing:),
             command_bar_did_end_editing as extern fn(&Object, Sel, Id));
         decl.add_method(sel!(control:textView:completions:forPartialWordRange:indexOfSelectedItem:),
@@ -258,6 +326,10 @@ extern fn app_will_finish_launching(this: &mut Object, _cmd: Sel, _note: Id) {
 extern fn app_finished_launching(_: &Object, _cmd: Sel, _note: Id) {
 }
 
+extern fn app_will_terminate(_: &Object, _cmd: Sel, _note: Id) {
+    UI.engine.close::<CocoaUI<_>, _>(&UI);
+}
+
 extern fn handle_get_url(_: &Object, _cmd: Sel, event: Id, _reply_event: Id) {
     let url = NSAppleEventDescriptor::from_ptr(event)
         .and_then(|event| event.url_param_value())
@@ -293,6 +365,22 @@ extern fn run_keybinding_command(this: &mut Object, _cmd: Sel) {
     }
 }
 
+extern fn fire_timer(this: &mut Object, _cmd: Sel, _timer: Id) {
+    if let Some(delegate) = TimerDelegate::from_ptr(this) {
+        UI.engine.on_timer::<CocoaUI<_>, _>(&UI, delegate.timer_id());
+    }
+}
+
+/// Cancel the timers of a window closed from its title bar or menu. Windows
+/// closed by `close_window()` release this delegate before closing.
+extern fn window_will_close(_: &Object, _cmd: Sel, notification: Id) {
+    let window = NSNotification::from_ptr(notification)
+        .and_then(|notification| notification.object::<NSWindow>());
+    if let Some(window) = window {
+        UI.engine.on_window_close(window.number());
+    }
+}
+
 extern fn webview_will_navigate(_: &Object, _cmd: Sel, webview_ptr: Id, action:Id,
                                 handler: Id) {
     const PERMITTED_SCHEMES: [&'static str; 5] = ["file","http","https","ftp","about"];
@@ -363,17 +451,62 @@ extern fn webview_did_load(_: &Object, _cmd: Sel, webview_ptr: Id, nav_ptr: Id)
 extern fn command_bar_did_end_editing(_: &Object, _cmd: Sel, notification: Id) {
     if is_return_key_event(notification) {
         if let Some(text) = notification_object_text(notification) {
-            UI.engine.execute_command::<CocoaUI<_>, _>(&UI, UI.focused_window_index(), text);
+            let text = completed_text(text);
+            let output = UI.engine.submit_command::<CocoaUI<_>, _>(&UI, UI.focused_window_index(), &text);
+            if let Some(message) = output.message {
+                info!("{}", message);
+            }
         }
     }
 }
 
-extern fn command_bar_text_changed(_: &Object, _cmd: Sel, notification: Id) {
-    if let Some(text) = notification_object_text(notification) {
-        if let Some(command) = UI.engine.config.command_matching_prefix(text) {
-            UI.engine.execute_command::<CocoaUI<_>, _>(&UI, UI.focused_window_index(), &command);
+extern fn command_bar_text_changed(this: &mut Object, _cmd: Sel, notification: Id) {
+    unsafe { this.set_ivar::<NSInteger>("_historyPosition", 0) };
+    let field = NSNotification::from_ptr(notification)
+        .and_then(|note| note.object::<NSTextField>());
+    if let Some(field) = field {
+        field.set_placeholder("");
+    }
+    if let (Some(text), Some(window_index)) = (notification_object_text(notification), UI.focused_window_index()) {
+        UI.engine.on_command_text_change::<CocoaUI<_>, _>(&UI, window_index, text);
+    }
+}
+
+/// Replace the command bar text with a previously run command matching the
+/// text typed before navigating when the up or down arrow key is pressed
+extern fn command_bar_do_command(this: &mut Object, _cmd: Sel, _control: Id, _text_view: Id, command: Sel) -> BOOL {
+    let offset: NSInteger = if command == sel!(moveUp:) {
+        1
+    } else if command == sel!(moveDown:) {
+        -1
+    } else {
+        return NO;
+    };
+    if let Some(window_index) = UI.focused_window_index() {
+        let mut position: NSInteger = unsafe { *this.get_ivar("_historyPosition") };
+        if position == 0 {
+            let text = UI.command_field_text(window_index);
+            let previous: Id = unsafe { *this.get_ivar("_historyPrefix") };
+            if let Some(mut previous) = NSString::from_ptr(previous) {
+                previous.release();
+            }
+            unsafe { this.set_ivar("_historyPrefix", NSString::from(&text).ptr()) };
         }
+        let prefix = NSString::from_ptr(unsafe { *this.get_ivar("_historyPrefix") })
+            .and_then(|prefix| prefix.as_str())
+            .map(|prefix| String::from(prefix))
+            .unwrap_or(String::new());
+        position += offset;
+        if position <= 0 {
+            UI.set_command_field_text(window_index, &prefix);
+            unsafe { this.set_ivar::<NSInteger>("_historyPosition", 0) };
+        } else if let Some(item) = UI.command_history_match(&prefix, (position - 1) as u32) {
+            UI.set_command_field_text(window_index, &item);
+            unsafe { this.set_ivar("_historyPosition", position) };
+        }
+        return YES;
     }
+    NO
 }
 
 extern fn command_bar_get_completion(_: &Object, _cmd: Sel, control: Id, _: Id, words: Id, _: NSRange, _: Id) -> Id {
@@ -383,12 +516,27 @@ extern fn command_bar_get_completion(_: &Object, _cmd: Sel, control: Id, _: Id,
         .and_then(|string| string.as_str());
     if let Some(prefix) = prefix {
         let completions = UI.engine.command_completions::<CocoaUI<_>, _>(&UI, prefix);
-        NSArray::from_vec(completions, |item| NSString::from(&item)).ptr()
+        let labels = NSArray::from_vec(completions.clone(), |item| NSString::from(&item.label)).ptr();
+        COMPLETIONS.with(|offered| *offered.borrow_mut() = completions);
+        labels
     } else {
         words
     }
 }
 
+/// Replace a completion label at the end of the command bar text with the
+/// value it stands for
+fn completed_text(text: &str) -> String {
+    COMPLETIONS.with(|offered| {
+        let completions = offered.replace(vec![]);
+        completions.iter()
+            .filter(|item| item.label != item.value)
+            .find(|item| text.ends_with(&item.label))
+            .map(|item| format!("{}{}", &text[..text.len() - item.label.len()], item.value))
+            .unwrap_or_else(|| String::from(text))
+    })
+}
+
 fn register_uri_event(webview_ptr: Id, nav_ptr: Id, event: BufferEvent) {
     let uri = WKNavigation::from_ptr(nav_ptr)
         .and_then(|u| u.url_string())
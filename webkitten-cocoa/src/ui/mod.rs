@@ -5,25 +5,28 @@ use std::fs::File;
 use std::io::Read;
 use std::marker::PhantomData;
 use std::process;
+use std::time::Duration;
 
 use webkitten::ui::*;
-use webkitten::config::Config;
+use webkitten::config::{Config,ConfigError};
 use webkitten::Engine;
-use webkitten::script::{ScriptingEngine,LuaEngine};
+use webkitten::script::{ScriptingEngine,Engines};
 use webkitten::optparse::parse_opts;
-use macos::foundation::{NSURLRequest,NSURL,NSString,NSAutoreleasePool};
+use webkitten::store::Store;
+use webkitten::timer::TimerOwner;
+use macos::foundation::{NSURLRequest,NSURL,NSString,NSError,NSAutoreleasePool,NSUInteger};
 use macos::appkit::{NSPasteboard,nsapp};
 use macos::webkit::*;
 use macos::{Id,nil};
 use block::ConcreteBlock;
 
-use runtime::log_error_description;
+use runtime::{TimerDelegate,log_error_description};
 
 
 const DEFAULT_CONFIG_PATH: &'static str = ".config/webkitten/config.toml";
 
 lazy_static! {
-    pub static ref UI: CocoaUI<LuaEngine> = {
+    pub static ref UI: CocoaUI<Engines> = {
         if let Some(home_dir) = dirs::home_dir() {
             let default_config_path = &format!("{}/{}", home_dir.display(), DEFAULT_CONFIG_PATH);
             let run_config = parse_opts(default_config_path);
@@ -75,6 +78,30 @@ impl<S: ScriptingEngine> CocoaUI<S> {
     }
 }
 
+/// Whether the keybinding of the `cancel` command was pressed while command
+/// scripts block the run loop. Other key presses are returned to the event
+/// queue in order.
+fn cancel_key_pressed() -> bool {
+    let (key, modifier) = match UI.engine.config.cancel_keybinding() {
+        Some(binding) => binding,
+        None => return false,
+    };
+    let app = nsapp();
+    let mut skipped = vec![];
+    let mut pressed = false;
+    while let Some(event) = app.next_key_down_event() {
+        if event.matches_key(key, modifier as NSUInteger) {
+            pressed = true;
+            break;
+        }
+        skipped.push(event);
+    }
+    for event in skipped.iter().rev() {
+        app.post_event(event, true);
+    }
+    pressed
+}
+
 impl<S: ScriptingEngine> ApplicationUI<S> for CocoaUI<S> {
 
     fn new(engine: Engine) -> Option<Self> {
@@ -85,6 +112,7 @@ impl<S: ScriptingEngine> ApplicationUI<S> for CocoaUI<S> {
         let pool = NSAutoreleasePool::new();
         self.compile_content_extensions(|_| {});
         let delegate = application::initialize_app_env();
+        self.engine.set_interrupt_check(cancel_key_pressed);
         self.open_first_window();
         application::start_run_loop(&delegate);
         pool.drain();
@@ -94,8 +122,8 @@ impl<S: ScriptingEngine> ApplicationUI<S> for CocoaUI<S> {
         NSPasteboard::general().copy(text);
     }
 
-    fn execute_command(&self, window_index: Option<u32>, text: &str) {
-        UI.engine.execute_command::<CocoaUI<_>, _>(&UI, window_index, text);
+    fn execute_command(&self, window_index: Option<u32>, text: &str) -> CommandOutput {
+        UI.engine.execute_command::<CocoaUI<_>, _>(&UI, window_index, text)
     }
 
     fn open_window<U, B>(&self, uri: Option<U>, config: Option<B>) -> u32
@@ -109,6 +137,7 @@ impl<S: ScriptingEngine> ApplicationUI<S> for CocoaUI<S> {
     }
 
     fn close_window(&self, index: u32) {
+        self.engine.on_window_close(index);
         window::close(index);
     }
 
@@ -152,6 +181,43 @@ impl<S: ScriptingEngine> ApplicationUI<S> for CocoaUI<S> {
         window::set_command_field_visible(window_index, visible);
     }
 
+    fn show_command_error(&self, window_index: u32, message: &str) {
+        window::show_command_error(window_index, message);
+    }
+
+    fn command_history(&self, count: u32) -> Vec<String> {
+        self.engine.command_history(count as usize)
+    }
+
+    fn command_history_match(&self, prefix: &str, index: u32) -> Option<String> {
+        self.engine.command_history_match(prefix, index as usize)
+    }
+
+    fn command_store(&self) -> &Store {
+        self.engine.store()
+    }
+
+    fn config(&self) -> &Config {
+        &self.engine.config
+    }
+
+    fn update_config<F>(&self, persist: bool, change: F) -> Result<(), ConfigError>
+            where F: Fn(&Config) -> Result<(), ConfigError> {
+        self.engine.update_config(persist, change)
+    }
+
+    fn set_timer(&self, command: &str, owner: TimerOwner, delay: Duration, repeat: bool) -> u32 {
+        self.engine.set_timer::<Self, S>(self, command, owner, delay, repeat)
+    }
+
+    fn clear_timer(&self, timer_id: u32) {
+        self.engine.clear_timer(timer_id);
+    }
+
+    fn schedule_timer(&self, timer_id: u32, delay: Duration) {
+        TimerDelegate::new(timer_id).schedule(delay);
+    }
+
     fn window_title(&self, window_index: u32) -> String {
         window::title(window_index)
     }
@@ -179,6 +245,7 @@ impl<S: ScriptingEngine> ApplicationUI<S> for CocoaUI<S> {
     }
 
     fn close_webview(&self, window_index: u32, webview_index: u32) {
+        self.engine.on_webview_close(window_index, webview_index);
         window::close_webview(window_index, webview_index);
     }
 
@@ -228,6 +295,12 @@ impl<S: ScriptingEngine> ApplicationUI<S> for CocoaUI<S> {
             .unwrap_or(""))
     }
 
+    fn is_private_webview(&self, window_index: u32, webview_index: u32) -> bool {
+        window::webview(window_index, webview_index)
+            .map(|webview| !webview.configuration().website_data_store().is_persistent())
+            .unwrap_or(false)
+    }
+
     fn webview_title(&self, window_index: u32, webview_index: u32) -> String {
         String::from(window::webview(window_index, webview_index)
             .and_then(|webview| webview.title())
@@ -253,6 +326,29 @@ impl<S: ScriptingEngine> ApplicationUI<S> for CocoaUI<S> {
         }
     }
 
+    fn evaluate_javascript(&self, window_index: u32, webview_index: u32, script: &str, command: &str) {
+        if let Some(webview) = window::webview(window_index, webview_index) {
+            let command = String::from(command);
+            let block = ConcreteBlock::new(move |result: Id, err: Id| {
+                let outcome = match NSError::from_ptr(err) {
+                    Some(err) => Err(javascript_error_message(&err)),
+                    None => Ok(NSString::from_ptr(result)
+                        .and_then(|json| json.as_str().map(String::from))
+                        .unwrap_or(String::from("null"))),
+                };
+                UI.engine.on_javascript_result::<CocoaUI<_>, _>(&UI, window_index, webview_index,
+                                                                &command, outcome);
+            });
+            let script = json_result_script(script);
+            if webview.can_evaluate_javascript_in_client_world() {
+                webview.evaluate_javascript_in_client_world(&script, &block.copy());
+            } else {
+                info!("Using fallback JavaScript evaluation in the page world");
+                webview.evaluate_javascript_with_completion(&script, &block.copy());
+            }
+        }
+    }
+
     fn apply_styles(&self, window_index: u32, webview_index: u32, styles: &str) {
         if let Some(webview) = window::webview(window_index, webview_index) {
             let controller = webview.configuration().user_content_controller();
@@ -283,3 +379,13 @@ pub fn create_request(uri: &str) -> NSURLRequest {
     }
     NSURLRequest::from(NSURL::from(NSString::from(&target)))
 }
+
+/// The message of an exception thrown while evaluating JavaScript, falling
+/// back to the description of the error
+fn javascript_error_message(err: &NSError) -> String {
+    err.user_info()
+        .and_then(|info| info.get::<NSString>("WKJavaScriptExceptionMessage"))
+        .or_else(|| err.localized_description())
+        .and_then(|message| message.as_str().map(String::from))
+        .unwrap_or(String::from("JavaScript evaluation failed"))
+}
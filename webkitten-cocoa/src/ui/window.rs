@@ -9,7 +9,7 @@ use webkitten::ui::{BrowserConfiguration,WindowArea,BufferEvent,EventHandler};
 
 use ui::{CocoaUI,UI};
 use runtime::{CommandBarDelegate,WebViewHistoryDelegate,WebViewContainerView,
-              log_error_description,default_user_agent,CommandBarView};
+              WindowDelegate,log_error_description,default_user_agent,CommandBarView};
 
 
 const BAR_HEIGHT: usize = 24;
@@ -199,6 +199,19 @@ pub fn set_command_field_text(window_index: u32, text: &str) {
     }
 }
 
+/// Show an error message as the placeholder of an emptied command bar, so
+/// that the message is hidden once text is entered and is not run as a
+/// command
+pub fn show_command_error(window_index: u32, message: &str) {
+    if let Some(window) = window_for_index(window_index) {
+        if let Some(bar) = subview(&window, WindowArea::CommandBar).coerce::<NSTextField>() {
+            set_command_field_text(window_index, "");
+            bar.set_placeholder(message);
+            set_command_field_visible(window_index, true);
+        }
+    }
+}
+
 pub fn command_field_visible(window_index: u32) -> bool {
     let bar = window_for_index(window_index)
         .and_then(|window| subview(&window, WindowArea::CommandBar).coerce::<CommandBarView>());
@@ -335,6 +348,7 @@ fn create_nswindow() -> NSWindow {
     window.cascade_top_left_from_point(NSPoint { x: 20., y: 20. });
     window.center();
     window.set_title(WEBKITTEN_TITLE);
+    window.set_delegate(&WindowDelegate::new());
     layout_window_subviews(&window);
     window
 }